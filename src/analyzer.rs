@@ -1,18 +1,29 @@
 use crate::blockchain::{BlockchainService, BytecodeCache};
+use crate::code_cache::{CodeVersion, DiskCodeCache};
+use crate::config::NetworkConfig;
+use crate::signatures::{self, SignatureDb};
+use crate::storage_layout::StorageLayout;
 use crate::cfg_gen::{
-    cfg_graph::CFGRunner,
+    call_tracer::{self, CallTrace},
+    vm_trace::{self, TraceReplay},
+    local_exec::{self, ExecutionOutcome, ExecutionSnapshot},
+    cfg_graph::{edge_traversals, heat_color, CFGRunner, EdgeTraversal, Edges},
+    dispatcher::{Dispatcher, FunctionId},
     dasm::{self, InstructionBlock},
-    trace::{self, CallEdge, TraceStep},
+    eof,
+    layout::BytecodeLayout,
+    storage::{self, StorageEffect},
+    trace::{self, CallEdge, CallFrame, CallFrameBuilder, CodeId, CodeKind, EmittedLog, TraceStep},
 };
 use eyre::{eyre, Result};
-use ethers::types::{H160, Bytes};
+use ethers::types::{BlockId, BlockNumber, Bytes, H160, H256, U256};
 use fnv::FnvBuildHasher;
 use petgraph::{
     graph::DiGraph,
     visit::{EdgeRef}
 };
 use revm::{
-    primitives::{Bytecode as RevmBytecode},
+    primitives::{Bytecode as RevmBytecode, SpecId},
     interpreter::analysis::to_analysed,
 };
 use std::collections::{BTreeMap, HashMap, HashSet};
@@ -21,85 +32,354 @@ use std::path::Path;
 
 /// Represents a contract's control flow graph and execution information
 pub struct ContractCFG {
-    pub address: H160,
+    pub code_id: CodeId,
     pub cfg_runner: CFGRunner<'static>,
     pub executed_pcs: HashSet<u16>,
+    pub layout: BytecodeLayout, // where the code ends and data or compiler metadata begins
 }
 
 /// Node in the global transaction graph
 #[derive(Clone, Debug)]
 pub struct TransactionNode {
-    pub contract_address: H160,
+    pub contract_address: H160, // code address, the bytecode this block belongs to
+    pub context_address: H160,  // storage context the block executed against
+    pub frame_id: usize,
     pub pc: u16,
     pub instruction: String,
     pub contains_sstore: bool,  // Marks whether it contains SSTORE opcode
     pub contains_add_or_sub: bool, // Marks whether it contains ADD or SUB opcodes
+    pub gas: u64,               // gas the block spent in this frame, forwarded gas excluded
 }
 
 impl Default for TransactionNode {
     fn default() -> Self {
         Self {
             contract_address: H160::zero(),
+            context_address: H160::zero(),
+            frame_id: 0,
             pc: 0,
             instruction: String::new(),
             contains_sstore: false,
             contains_add_or_sub: false,
+            gas: 0,
         }
     }
 }
 
+/// Block of a frame and the gas it spent there, see [`TransactionAnalyzer::gas_hotspots`]
+#[derive(Clone, Debug)]
+pub struct GasHotspot {
+    pub frame_id: usize,
+    pub code_id: CodeId,
+    pub block: (u16, u16),
+    pub gas: u64,
+}
+
 /// Edge in the global transaction graph
 #[derive(Clone, Debug)]
 pub enum TransactionEdge {
-    Internal(String),    // Internal contract flow, string represents edge type
-    External(String),    // Cross-contract call, string represents call type (CALL, DELEGATECALL, etc.)
+    Internal { edge_type: Option<Edges>, traversal: Option<EdgeTraversal> }, // Internal contract flow taken by the frame, no type when the CFG missed it
+    External(String),    // Cross-contract call, string is the call type (CALL, DELEGATECALL, etc.) and the decoded call
+    Return { success: bool, label: String }, // Callee's last block back to where the caller continued
 }
 
 pub struct TransactionAnalyzer {
-    pub trace_steps: Vec<TraceStep>,
-    pub contract_addresses: HashSet<H160>,
+    pub contract_addresses: HashSet<H160>, // code addresses of every executed frame
+    pub tx_hash: Option<H256>,             // transaction being analyzed, used to fetch its pre-state
+    pub chain_id: Option<u64>,             // chain the transaction ran on
+    pub block: Option<BlockId>,            // block to fetch code at, latest if unset
+    pub spec_id: SpecId,                   // hardfork the transaction ran under, decides which opcodes are valid
     pub bytecode_cache: BytecodeCache,
-    pub contract_cfgs: HashMap<H160, ContractCFG>,
+    pub code_cache: Option<DiskCodeCache>,  // persistent cache consulted before fetching code
+    pub gas_heatmap: bool,                  // fill executed blocks by gas spent instead of by operations
+    pub signatures: SignatureDb,            // names external calls and emitted events
+    pub trace_code: HashMap<CodeId, Bytes>, // code recovered from the trace itself, preferred over fetched code
+    pub prestate_storage: HashMap<H160, HashMap<H256, H256>>, // storage before the transaction, known for snapshots
+    pub storage_layouts: HashMap<H160, StorageLayout>, // names the slots of accounts or of the code they run
+    pub contract_cfgs: HashMap<CodeId, ContractCFG>,
+    pub call_frames: Vec<CallFrame>,
     pub call_edges: Vec<CallEdge>,
     pub global_graph: DiGraph<TransactionNode, TransactionEdge>,
-    pub node_mapping: HashMap<(H160, u16), petgraph::graph::NodeIndex>,
+    pub node_mapping: HashMap<(usize, u16), petgraph::graph::NodeIndex>, // (frame_id, pc) -> node
 }
 
 impl TransactionAnalyzer {
-    pub fn new(trace_steps: Vec<TraceStep>) -> Self {
-        let contract_addresses = trace::extract_contract_addresses(&trace_steps);
-        let call_edges = trace::extract_call_edges(&trace_steps);
+    pub fn new(trace_steps: &[TraceStep]) -> Self {
+        Self::from_call_frames(trace::extract_call_frames(trace_steps))
+    }
+    
+    /// Create an analyzer from call frames alone, without opcode-level trace steps
+    pub fn from_call_frames(call_frames: Vec<CallFrame>) -> Self {
+        let contract_addresses = trace::extract_code_addresses(&call_frames);
+        let call_edges = trace::extract_call_edges(&call_frames);
+        let trace_code = trace::extract_created_code(&call_frames);
         
         Self {
-            trace_steps,
             contract_addresses,
+            tx_hash: None,
+            chain_id: None,
+            block: None,
+            spec_id: SpecId::LATEST,
             bytecode_cache: BytecodeCache::new(),
+            code_cache: None,
+            gas_heatmap: false,
+            signatures: SignatureDb::bundled(),
+            trace_code,
+            prestate_storage: HashMap::new(),
+            storage_layouts: HashMap::new(),
             contract_cfgs: HashMap::new(),
+            call_frames,
             call_edges,
             global_graph: DiGraph::new(),
             node_mapping: HashMap::new(),
         }
     }
     
+    /// Create an analyzer from a geth `callTracer` call tree
+    ///
+    /// Only the cross-contract layer of the global graph can be built from it.
+    pub fn from_call_trace(call_trace: &CallTrace) -> Self {
+        Self::from_call_frames(call_tracer::extract_call_frames(call_trace))
+    }
+    
+    /// Create an analyzer from a `trace_replayTransaction` result with `vmTrace`
+    ///
+    /// The code of every frame is embedded in the trace, so no bytecode needs to be fetched.
+    pub fn from_trace_replay(trace_replay: &TraceReplay) -> Self {
+        let vm_trace_frames = vm_trace::extract_call_frames(trace_replay);
+        let mut analyzer = Self::from_call_frames(vm_trace_frames.frames);
+        analyzer.trace_code.extend(vm_trace_frames.code);
+        analyzer
+    }
+    
+    /// Create an analyzer by re-executing a transaction snapshot locally in revm
+    ///
+    /// The pre-state holds the code of every account the transaction touched, so no
+    /// bytecode needs to be fetched either. Memory is needed to recover created code.
+    pub fn from_snapshot(snapshot: &ExecutionSnapshot, network: &NetworkConfig, with_memory: bool) -> Result<(Self, ExecutionOutcome)> {
+        if let Some(chain_id) = snapshot.transaction.chain_id {
+            if chain_id.as_u64() != network.chain_id {
+                return Err(eyre!("Snapshot is from chain {} but the selected network is chain {}", chain_id, network.chain_id));
+            }
+        }
+        let block = &snapshot.block;
+        let spec_id = network.spec_at(block.number.low_u64(), block.timestamp.low_u64());
+        
+        let mut frame_builder = CallFrameBuilder::new();
+        let outcome = local_exec::execute(snapshot, spec_id, with_memory, |step| frame_builder.push(step))?;
+        
+        let mut analyzer = Self::from_call_frames(frame_builder.finish());
+        for (address, account) in &snapshot.prestate {
+            if let Some(code) = account.code.as_ref().filter(|code| !code.0.is_empty()) {
+                analyzer.trace_code.entry(CodeId::runtime(*address)).or_insert_with(|| code.clone());
+            }
+            if let Some(storage) = &account.storage {
+                analyzer.prestate_storage.insert(*address, storage.clone());
+            }
+        }
+        analyzer.set_block(snapshot.block.number.low_u64());
+        analyzer.spec_id = spec_id;
+        analyzer.chain_id = Some(network.chain_id);
+        Ok((analyzer, outcome))
+    }
+    
+    /// Create an analyzer from a trace file: struct logs, `callTracer` output or a `vmTrace` replay
     pub fn from_trace_file(trace_path: &str) -> Result<Self> {
-        let trace_steps = trace::parse_trace_file(trace_path)?;
-        Ok(Self::new(trace_steps))
+        // Struct logs can be several GB, fold them into frames while parsing
+        let mut frame_builder = CallFrameBuilder::new();
+        let struct_log_err = match trace::stream_trace_file(trace_path, |step| frame_builder.push(step)) {
+            Ok(_) => return Ok(Self::from_call_frames(frame_builder.finish())),
+            Err(e) => e,
+        };
+        if let Ok(call_trace) = call_tracer::parse_call_trace_file(trace_path) {
+            return Ok(Self::from_call_trace(&call_trace));
+        }
+        if let Ok(trace_replay) = vm_trace::parse_trace_replay_file(trace_path) {
+            return Ok(Self::from_trace_replay(&trace_replay));
+        }
+        Err(eyre!("Unrecognized trace format in {}: {}", trace_path, struct_log_err))
+    }
+    
+    /// Pin code fetching to the state the given transaction executed against
+    pub async fn set_transaction(&mut self, tx_hash: H256, blockchain_service: &dyn BlockchainService) -> Result<u64> {
+        let block_number = blockchain_service.get_transaction_block(tx_hash).await?;
+        self.tx_hash = Some(tx_hash);
+        self.block = Some(BlockId::Number(BlockNumber::Number(block_number.into())));
+        Ok(block_number)
     }
     
-    pub async fn fetch_bytecodes(&mut self, blockchain_service: &impl BlockchainService) -> Result<()> {
-        let addresses: Vec<H160> = self.contract_addresses.iter().cloned().collect();
-        self.bytecode_cache = crate::blockchain::fetch_all_bytecodes(&addresses, blockchain_service).await?;
+    /// Pin code fetching to a block, for traces that come without a transaction hash
+    pub fn set_block(&mut self, block_number: u64) {
+        self.block = Some(BlockId::Number(BlockNumber::Number(block_number.into())));
+    }
+    
+    /// Number of the block code is fetched at, if pinned
+    pub fn block_number(&self) -> Option<u64> {
+        match self.block {
+            Some(BlockId::Number(BlockNumber::Number(number))) => Some(number.as_u64()),
+            _ => None,
+        }
+    }
+    
+    /// Decode code as the given hardfork does, `SpecId::LATEST` unless set
+    pub fn set_spec_id(&mut self, spec_id: SpecId) {
+        self.spec_id = spec_id;
+    }
+    
+    /// Record the chain the transaction ran on
+    pub fn set_chain_id(&mut self, chain_id: u64) {
+        self.chain_id = Some(chain_id);
+    }
+    
+    /// Consult a persistent code cache before fetching code, and fill it with what gets fetched
+    pub fn set_code_cache(&mut self, code_cache: DiskCodeCache) {
+        self.code_cache = Some(code_cache);
+    }
+    
+    /// Name the storage slots of an account with a solc storage layout
+    ///
+    /// The address is the account whose storage is accessed or, for proxies, the
+    /// implementation whose code accesses it.
+    pub fn set_storage_layout(&mut self, address: H160, layout: StorageLayout) {
+        self.storage_layouts.insert(address, layout);
+    }
+    
+    /// Fill executed blocks of the exported graphs by the gas they spent, set before generating CFGs
+    pub fn set_gas_heatmap(&mut self, gas_heatmap: bool) {
+        self.gas_heatmap = gas_heatmap;
+    }
+    
+    /// Fetch the bytecode of every executed contract
+    ///
+    /// Code is looked up in the persistent cache first, at the state the transaction's
+    /// block started from (or the pinned block, or latest). Addresses the cache knows
+    /// from other blocks only have their code hash checked. When the transaction is
+    /// known its pre-state gives the rest, which is exactly the code that ran, anything
+    /// still missing is fetched at that same state. Contracts whose code was recovered
+    /// from the trace are skipped: the chain may hold different code for contracts
+    /// deployed by this transaction, and vmTrace input already embeds the code of every frame.
+    pub async fn fetch_bytecodes(&mut self, blockchain_service: &dyn BlockchainService) -> Result<()> {
+        let mut cache = BytecodeCache::new();
+        let wanted: Vec<H160> = self
+            .contract_addresses
+            .iter()
+            .filter(|address| !self.trace_code.contains_key(&CodeId::runtime(**address)))
+            .cloned()
+            .collect();
+        
+        // The transaction's own block holds the state after it ran, code the transaction (or one
+        // before it in the block) destroyed or replaced is only found in the previous block
+        let state_block = match (self.tx_hash, self.block_number()) {
+            (Some(_), Some(number)) if number > 0 => Some(number - 1),
+            (_, number) => number,
+        };
+        let (block, version) = match state_block {
+            Some(number) => (BlockId::Number(BlockNumber::Number(number.into())), CodeVersion::Block(number)),
+            None => (BlockId::Number(BlockNumber::Latest), CodeVersion::Latest),
+        };
+        self.load_cached_code(&wanted, version, &mut cache);
+        if let Some(number) = state_block {
+            self.recheck_cached_code(&wanted, number, blockchain_service, &mut cache).await;
+        }
+        
+        if let Some(tx_hash) = self.tx_hash {
+            if wanted.iter().any(|address| cache.get(address).is_none()) {
+                match blockchain_service.get_prestate_code(tx_hash).await {
+                    Ok(codes) => {
+                        for (address, code) in codes {
+                            if wanted.contains(&address) && cache.get(&address).is_none() && !code.0.is_empty() {
+                                self.store_cached_code(address, version, &code);
+                                cache.insert(address, code);
+                            }
+                        }
+                    }
+                    Err(e) => println!("⚠️ Could not fetch transaction pre-state ({}), falling back to the previous block's state", e),
+                }
+            }
+        }
+        
+        let remaining: Vec<H160> = wanted.into_iter().filter(|address| cache.get(address).is_none()).collect();
+        let fetched = crate::blockchain::fetch_all_bytecodes(&remaining, block, blockchain_service).await?;
+        for (address, code) in &fetched.cache {
+            self.store_cached_code(*address, version, code);
+        }
+        cache.cache.extend(fetched.cache);
+        
+        if let Some(code_cache) = self.code_cache.as_mut() {
+            if let Err(e) = code_cache.enforce_size_limit() {
+                println!("⚠️ Could not trim the bytecode cache: {}", e);
+            }
+        }
+        
+        self.bytecode_cache = cache;
         Ok(())
     }
     
+    /// Copy the persistently cached code of `addresses` at `version` into `cache`
+    fn load_cached_code(&mut self, addresses: &[H160], version: CodeVersion, cache: &mut BytecodeCache) {
+        let Some(code_cache) = self.code_cache.as_mut() else {
+            return;
+        };
+        for address in addresses {
+            if let Some(code) = code_cache.get(*address, version) {
+                cache.insert(*address, code);
+            }
+        }
+    }
+    
+    /// For addresses cached at other blocks only, compare the code hash at `block` with the cached code
+    ///
+    /// One `eth_getProof` per address instead of its code, unchanged code is then taken
+    /// from the cache. Failed checks leave the address to be fetched.
+    async fn recheck_cached_code(&mut self, addresses: &[H160], block: u64, blockchain_service: &dyn BlockchainService, cache: &mut BytecodeCache) {
+        let Some(code_cache) = self.code_cache.as_mut() else {
+            return;
+        };
+        let known: Vec<H160> = addresses
+            .iter()
+            .filter(|address| cache.get(address).is_none() && code_cache.is_known(**address))
+            .cloned()
+            .collect();
+        for address in known {
+            let block_id = BlockId::Number(BlockNumber::Number(block.into()));
+            let Ok(code_hash) = blockchain_service.get_code_hash_at(address, block_id).await else {
+                continue;
+            };
+            if let Some(code) = code_cache.get_by_hash(address, block, code_hash) {
+                cache.insert(address, code);
+            }
+        }
+    }
+    
+    /// Remember fetched code in the persistent cache, failing to do so only costs a refetch later
+    fn store_cached_code(&mut self, address: H160, version: CodeVersion, code: &Bytes) {
+        if let Some(code_cache) = self.code_cache.as_mut() {
+            if let Err(e) = code_cache.insert(address, version, code) {
+                println!("⚠️ Could not cache code of {:?}: {}", address, e);
+            }
+        }
+    }
+    
     /// Generate CFG for each contract
     pub fn generate_contract_cfgs(&mut self) -> Result<()> {
         // Create empty objects to prevent ownership issues
         let mut contract_cfgs = HashMap::new();
         
-        for (address, bytecode) in &self.bytecode_cache.cache {
-            let contract_cfg = self.generate_single_contract_cfg(address, bytecode)?;
-            contract_cfgs.insert(*address, contract_cfg);
+        // Code recovered from the trace wins over fetched code, it is what actually ran
+        let mut subjects: HashMap<CodeId, &Bytes> = self
+            .bytecode_cache
+            .cache
+            .iter()
+            .map(|(address, bytecode)| (CodeId::runtime(*address), bytecode))
+            .collect();
+        subjects.extend(self.trace_code.iter().map(|(code_id, bytecode)| (*code_id, bytecode)));
+        
+        for (code_id, bytecode) in subjects {
+            if bytecode.0.is_empty() {
+                continue;
+            }
+            let contract_cfg = self.generate_single_contract_cfg(&code_id, bytecode)?;
+            contract_cfgs.insert(code_id, contract_cfg);
         }
         
         self.contract_cfgs = contract_cfgs;
@@ -107,7 +387,11 @@ impl TransactionAnalyzer {
     }
     
     /// Generate CFG for a single contract
-    fn generate_single_contract_cfg(&self, address: &H160, bytecode: &Bytes) -> Result<ContractCFG> {
+    fn generate_single_contract_cfg(&self, code_id: &CodeId, bytecode: &Bytes) -> Result<ContractCFG> {
+        if eof::is_eof(bytecode) {
+            return self.generate_eof_contract_cfg(code_id, bytecode);
+        }
+        
         // Convert to the format required by revm
         let contract_data = bytecode.to_vec().into();
         let bytecode_analysed = to_analysed(RevmBytecode::new_raw(contract_data));
@@ -116,19 +400,25 @@ impl TransactionAnalyzer {
         let revm_jumptable = bytecode_analysed.legacy_jump_table()
             .ok_or_else(|| eyre!("revm bytecode analysis failed"))?;
             
+        // Metadata and data regions are never executed, keep them out of the CFG
+        let layout = BytecodeLayout::analyze(bytecode, self.spec_id);
+        
         let mut set_all_valid_jumpdests: HashSet<u16, FnvBuildHasher> = HashSet::default();
         let slice = revm_jumptable.as_slice();
         for (byte_index, &byte) in slice.iter().enumerate() {
             for bit_index in 0..8 {
                 if byte & (1 << bit_index) != 0 {
                     let pc = (byte_index * 8 + bit_index) as u16;
-                    set_all_valid_jumpdests.insert(pc);
+                    if layout.code.contains(&(pc as usize)) {
+                        set_all_valid_jumpdests.insert(pc);
+                    }
                 }
             }
         }
         
         // Parse instruction blocks
-        let mut instruction_blocks = dasm::disassemble(bytecode_analysed.original_byte_slice().into());
+        let code = &bytecode_analysed.original_byte_slice()[..layout.code.end];
+        let mut instruction_blocks = dasm::disassemble(code, self.spec_id);
         for block in &mut instruction_blocks {
             block.analyze_stack_info();
         }
@@ -139,9 +429,7 @@ impl TransactionAnalyzer {
             .map(|block| ((block.start_pc, block.end_pc), block.clone()))
             .collect();
             
-        // Get execution steps for this contract
-        let filtered_steps = trace::filter_steps_by_address(&self.trace_steps, address);
-        let executed_pcs = trace::get_executed_pcs(&filtered_steps);
+        let executed_pcs = self.executed_pcs_of(code_id);
         
         // Create CFG
         let mut cfg_runner = CFGRunner::new(
@@ -165,20 +453,140 @@ impl TransactionAnalyzer {
             false,
         );
         
+        // Recover external functions, once jumps are resolved
+        if let Some(dispatcher) = Dispatcher::recover(&cfg_runner) {
+            cfg_runner.set_dispatcher(dispatcher);
+        }
+        cfg_runner.set_annotations(self.annotations_of(code_id));
+        let block_paths = self.block_paths_of(code_id, &cfg_runner);
+        cfg_runner.set_block_paths(&block_paths);
+        cfg_runner.set_gas_by_pc(self.gas_by_pc_of(code_id));
+        cfg_runner.set_gas_heatmap(self.gas_heatmap);
+        
+        Ok(ContractCFG {
+            code_id: *code_id,
+            cfg_runner,
+            executed_pcs,
+            layout,
+        })
+    }
+    
+    /// Generate CFG for an EOF container, one subgraph per code section
+    ///
+    /// EOF has no dynamic jumps, every edge is known from the immediates so
+    /// there is nothing for the symbolic stack solver to resolve.
+    fn generate_eof_contract_cfg(&self, code_id: &CodeId, bytecode: &Bytes) -> Result<ContractCFG> {
+        let container = eof::parse_container(bytecode)
+            .map_err(|e| eyre!("{} of {:?}", e, code_id.address))?;
+        
+        let layout = BytecodeLayout::analyze(bytecode, self.spec_id);
+        let mut instruction_blocks = eof::disassemble_container(&container, self.spec_id);
+        for block in &mut instruction_blocks {
+            block.analyze_stack_info();
+        }
+        let map_to_instructionblocks: BTreeMap<(u16, u16), InstructionBlock> = instruction_blocks
+            .into_iter()
+            .map(|block| ((block.start_pc, block.end_pc), block))
+            .collect();
+        
+        let executed_pcs = self.executed_pcs_of(code_id);
+        let mut cfg_runner = CFGRunner::new(bytecode.to_vec(), Box::leak(Box::new(map_to_instructionblocks)));
+        cfg_runner.set_eof_container(container);
+        cfg_runner.set_executed_pcs(executed_pcs.clone());
+        cfg_runner.form_eof_connections();
+        cfg_runner.remove_unreachable_instruction_blocks();
+        if let Some(dispatcher) = Dispatcher::recover(&cfg_runner) {
+            cfg_runner.set_dispatcher(dispatcher);
+        }
+        cfg_runner.set_annotations(self.annotations_of(code_id));
+        let block_paths = self.block_paths_of(code_id, &cfg_runner);
+        cfg_runner.set_block_paths(&block_paths);
+        cfg_runner.set_gas_by_pc(self.gas_by_pc_of(code_id));
+        cfg_runner.set_gas_heatmap(self.gas_heatmap);
+        
         Ok(ContractCFG {
-            address: *address,
+            code_id: *code_id,
             cfg_runner,
             executed_pcs,
+            layout,
         })
     }
     
+    /// Ordered block visits of every frame that ran the code, in the order the frames were entered
+    fn block_paths_of(&self, code_id: &CodeId, cfg_runner: &CFGRunner) -> Vec<Vec<(u16, u16)>> {
+        self.call_frames
+            .iter()
+            .filter(|frame| &frame.code_id() == code_id)
+            .map(|frame| cfg_runner.block_path(&frame.block_entries))
+            .collect()
+    }
+    
+    /// Gas spent at each pc of a contract, summed over every frame that ran it
+    fn gas_by_pc_of(&self, code_id: &CodeId) -> HashMap<u16, u64> {
+        let mut gas_by_pc: HashMap<u16, u64> = HashMap::new();
+        for frame in self.call_frames.iter().filter(|frame| &frame.code_id() == code_id) {
+            for (pc, gas) in &frame.gas_by_pc {
+                *gas_by_pc.entry(*pc).or_default() += gas;
+            }
+        }
+        gas_by_pc
+    }
+    
+    /// Union of the PCs executed by every frame of a contract
+    fn executed_pcs_of(&self, code_id: &CodeId) -> HashSet<u16> {
+        self.call_frames
+            .iter()
+            .filter(|frame| &frame.code_id() == code_id)
+            .flat_map(|frame| frame.executed_pcs.iter().copied())
+            .collect()
+    }
+    
     /// Create global transaction graph
+    ///
+    /// Every call frame gets its own subgraph, so repeated entries into the same
+    /// contract are kept apart instead of being merged by address.
     pub fn build_global_transaction_graph(&mut self) -> Result<()> {
-        // Create global graph nodes for each node in contract CFGs
-        for (address, contract_cfg) in &self.contract_cfgs {
+        let hash_preimages = self.hash_preimages();
+        let mut storage_effects: HashMap<usize, Vec<(StorageEffect, Option<String>)>> = HashMap::new();
+        for effect in self.storage_timeline().into_values().flatten() {
+            let variable = self.slot_name(&effect, &hash_preimages);
+            storage_effects.entry(effect.frame_id).or_default().push((effect, variable));
+        }
+        
+        // Create global graph nodes for the executed blocks of each frame
+        for frame in &self.call_frames {
+            // Without opcode-level data a frame is represented by a single entry node
+            if frame.step_count == 0 {
+                let mut instruction = format!("{} {:?}", frame.call_type, frame.code_address);
+                if frame.is_delegated() {
+                    instruction.push_str(&format!("\\nstorage: {:?}", frame.context_address));
+                }
+                if let Some(error) = &frame.error {
+                    instruction.push_str(&format!("\\nerror: {}", error));
+                }
+                for log in &frame.logs {
+                    instruction.push_str(&format!("\\n{}", self.describe_log(log)));
+                }
+                let tx_node = TransactionNode {
+                    contract_address: frame.code_address,
+                    context_address: frame.context_address,
+                    frame_id: frame.id,
+                    instruction,
+                    ..Default::default()
+                };
+                let node_idx = self.global_graph.add_node(tx_node);
+                self.node_mapping.insert((frame.id, 0), node_idx);
+                continue;
+            }
+            
+            let contract_cfg = match self.contract_cfgs.get(&frame.code_id()) {
+                Some(contract_cfg) => contract_cfg,
+                None => continue, // No bytecode for this frame (e.g. precompile)
+            };
+            
             for node in contract_cfg.cfg_runner.cfg_dag.nodes() {
-                // Only add executed nodes
-                if contract_cfg.executed_pcs.contains(&node.0) {
+                // Only add nodes executed in this frame, including blocks entered mid-way
+                if contract_cfg.cfg_runner.block_ran(node, &frame.executed_pcs) {
                     let instruction_block = contract_cfg.cfg_runner.map_to_instructionblock.get(&node).unwrap();
                     let pc = instruction_block.start_pc;
                     
@@ -191,98 +599,293 @@ impl TransactionAnalyzer {
                         *op == 0x03    // SUB opcode
                     );
                     
+                    // Events emitted by the block, in execution order
+                    let mut instruction = instruction_block.to_string();
+                    for log in &frame.logs {
+                        if log.pc.is_some_and(|log_pc| (instruction_block.start_pc..=instruction_block.end_pc).contains(&log_pc)) {
+                            instruction.push_str(&format!("{}\n", self.describe_log(log)));
+                        }
+                    }
+                    
+                    // Storage read and written by the block, in execution order
+                    let block_effects: Vec<_> = storage_effects
+                        .get(&frame.id)
+                        .into_iter()
+                        .flatten()
+                        .filter(|(effect, _)| effect.pc.is_some_and(|effect_pc| (instruction_block.start_pc..=instruction_block.end_pc).contains(&effect_pc)))
+                        .collect();
+                    for (effect, variable) in block_effects.iter().take(MAX_STORAGE_PER_NODE) {
+                        instruction.push_str(&format!("{}\n", describe_storage(effect, variable.as_deref())));
+                    }
+                    if block_effects.len() > MAX_STORAGE_PER_NODE {
+                        instruction.push_str(&format!("… {} more storage accesses\n", block_effects.len() - MAX_STORAGE_PER_NODE));
+                    }
+                    
                     // Create transaction node
                     let tx_node = TransactionNode {
-                        contract_address: *address,
+                        contract_address: frame.code_address,
+                        context_address: frame.context_address,
+                        frame_id: frame.id,
                         pc,
-                        instruction: instruction_block.to_string(),
+                        instruction,
                         contains_sstore, // Set SSTORE flag
                         contains_add_or_sub, // Set ADD/SUB flag
+                        gas: block_gas(frame, instruction_block),
                     };
                     
                     // Add to global graph
                     let node_idx = self.global_graph.add_node(tx_node);
-                    self.node_mapping.insert((*address, pc), node_idx);
+                    self.node_mapping.insert((frame.id, pc), node_idx);
                 }
             }
         }
         
         // Add internal edges
-        for (address, contract_cfg) in &self.contract_cfgs {
-            for edge in contract_cfg.cfg_runner.cfg_dag.all_edges() {
-                let (from_node, to_node, edge_type) = edge;
-                let from_pc = from_node.0;
-                let to_pc = to_node.0;
-                
-                // Only add edges where both endpoints were executed
-                if contract_cfg.executed_pcs.contains(&from_pc) && contract_cfg.executed_pcs.contains(&to_pc) {
-                    if let (Some(from_idx), Some(to_idx)) = (
-                        self.node_mapping.get(&(*address, from_pc)),
-                        self.node_mapping.get(&(*address, to_pc))
-                    ) {
-                        // Add internal edge
-                        let edge_label = format!("{:?}", edge_type);
-                        self.global_graph.add_edge(
-                            *from_idx,
-                            *to_idx,
-                            TransactionEdge::Internal(edge_label),
-                        );
-                    }
+        for frame in &self.call_frames {
+            let contract_cfg = match self.contract_cfgs.get(&frame.code_id()) {
+                Some(contract_cfg) => contract_cfg,
+                None => continue,
+            };
+            
+            // Sequence numbers restart with every frame, each is its own subgraph
+            let block_path = contract_cfg.cfg_runner.block_path(&frame.block_entries);
+            let traversals = edge_traversals([&block_path]);
+            
+            // Only add the transitions this frame actually made
+            for edge in contract_cfg.cfg_runner.taken_edges(&traversals, &frame.executed_pcs) {
+                if let (Some(from_idx), Some(to_idx)) = (
+                    self.node_mapping.get(&(frame.id, edge.from.0)),
+                    self.node_mapping.get(&(frame.id, edge.to.0))
+                ) {
+                    // Add internal edge
+                    self.global_graph.add_edge(
+                        *from_idx,
+                        *to_idx,
+                        TransactionEdge::Internal { edge_type: edge.edge_type, traversal: edge.traversal },
+                    );
                 }
             }
         }
         
         // Add cross-contract call edges
         for edge in &self.call_edges {
+            let from_block_pc = match edge.from_pc {
+                Some(from_pc) => self.block_start_pc(edge.from_frame, from_pc),
+                None => Some(0), // Call site unknown, start from the caller's entry node
+            };
+            let to_entry_pc = self.entry_pc(edge.to_frame);
             if let (Some(from_idx), Some(to_idx)) = (
-                self.node_mapping.get(&(edge.from_addr, edge.from_pc)),
-                // Assume target contract's entry PC is 0
-                self.node_mapping.get(&(edge.to_addr, 0))
+                from_block_pc.and_then(|pc| self.node_mapping.get(&(edge.from_frame, pc))),
+                self.node_mapping.get(&(edge.to_frame, to_entry_pc))
             ) {
                 // Add external call edge
                 self.global_graph.add_edge(
                     *from_idx,
                     *to_idx,
-                    TransactionEdge::External(edge.call_type.clone()),
+                    TransactionEdge::External(self.describe_call(edge)),
+                );
+            }
+            
+            // Pair the call with its return: from the block that ended the callee to the
+            // caller's continuation block. Without opcode-level data both sides are the
+            // frames' entry nodes.
+            let exit_block_pc = match edge.exit_pc {
+                Some(exit_pc) => self.block_start_pc(edge.to_frame, exit_pc),
+                None => Some(to_entry_pc),
+            };
+            let return_block_pc = match edge.return_pc.or(edge.from_pc) {
+                Some(return_pc) => self.block_start_pc(edge.from_frame, return_pc),
+                None => Some(0),
+            };
+            if let (Some(from_idx), Some(to_idx)) = (
+                exit_block_pc.and_then(|pc| self.node_mapping.get(&(edge.to_frame, pc))),
+                return_block_pc.and_then(|pc| self.node_mapping.get(&(edge.from_frame, pc)))
+            ) {
+                self.global_graph.add_edge(
+                    *from_idx,
+                    *to_idx,
+                    TransactionEdge::Return { success: edge.error.is_none(), label: describe_return(edge) },
                 );
             }
         }
         
         Ok(())
     }
+
+    /// Edge label of a cross-contract call: call type, decoded function call, value and gas
+    ///
+    /// e.g. `CALL transfer(0x…, 1000)` when the selector is in the signature database,
+    /// `CALL 0xa9059cbb` when it is not and just `CALL` when the calldata is unknown.
+    pub fn describe_call(&self, edge: &CallEdge) -> String {
+        let mut label = edge.call_type.clone();
+        if let Some(selector) = edge.selector() {
+            let input = edge.input.as_deref().unwrap_or_default();
+            match self.signatures.decode_call(input) {
+                Some(call) if call.args.is_empty() && !call.signature.ends_with("()") => {
+                    label.push_str(&format!(" {}", call.signature)); // calldata does not decode, show the types
+                }
+                Some(call) => label.push_str(&format!(" {}({})", call.name, shorten_args(call.args))),
+                None => label.push_str(&format!(" 0x{}", hex::encode(selector))),
+            }
+        }
+        if let Some(value) = edge.value.filter(|value| !value.is_zero()) {
+            label.push_str(&format!("\\nvalue: {}", signatures::format_ether(value)));
+        }
+        if let Some(gas) = edge.gas {
+            label.push_str(&format!("\\ngas: {}", gas));
+        }
+        label
+    }
+    
+    /// Label of an emitted event, e.g. `emit Transfer(from=0x…, to=0x…, value=1000)`
+    ///
+    /// Unknown events show their topic, anonymous ones (LOG0) just `emit LOG0`.
+    pub fn describe_log(&self, log: &EmittedLog) -> String {
+        let Some(topic) = log.topics.first() else {
+            return "emit LOG0".to_string();
+        };
+        let data = log.data.as_deref().unwrap_or_default();
+        match self.signatures.decode_log(&log.topics, data) {
+            Some(decoded) if decoded.args.is_empty() && !decoded.signature.ends_with("()") => {
+                format!("emit {}", decoded.signature) // data unknown or not decodable, show the types
+            }
+            Some(decoded) => format!("emit {}({})", decoded.name, shorten_args(decoded.args)),
+            None => format!("emit {:?}", topic),
+        }
+    }
+    
+    /// Decoded calls and events at the pcs of a contract, over every frame that ran its code
+    ///
+    /// A call site or LOG executed many times (e.g. in a loop) keeps its first few
+    /// distinct labels and a count of the rest.
+    fn annotations_of(&self, code_id: &CodeId) -> BTreeMap<u16, Vec<String>> {
+        const MAX_PER_PC: usize = 3;
+        let frames: HashSet<usize> = self
+            .call_frames
+            .iter()
+            .filter(|frame| &frame.code_id() == code_id)
+            .map(|frame| frame.id)
+            .collect();
+        let calls = self
+            .call_edges
+            .iter()
+            .filter(|edge| frames.contains(&edge.from_frame))
+            .filter_map(|edge| Some((edge.from_pc?, self.describe_call(edge).replace("\\n", " "))));
+        let logs = self
+            .call_frames
+            .iter()
+            .filter(|frame| frames.contains(&frame.id))
+            .flat_map(|frame| &frame.logs)
+            .filter_map(|log| Some((log.pc?, self.describe_log(log))));
+        
+        let mut labels: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        for (pc, label) in calls.chain(logs) {
+            let pc_labels = labels.entry(pc).or_default();
+            if !pc_labels.contains(&label) {
+                pc_labels.push(label);
+            }
+        }
+        for pc_labels in labels.values_mut() {
+            if pc_labels.len() > MAX_PER_PC {
+                let more = pc_labels.len() - MAX_PER_PC;
+                pc_labels.truncate(MAX_PER_PC);
+                pc_labels.push(format!("… {} more", more));
+            }
+        }
+        labels
+    }
+    
+    /// Pc a frame's code starts executing at, 0 unless it is an EOF container
+    fn entry_pc(&self, frame_id: usize) -> u16 {
+        self.call_frames
+            .get(frame_id)
+            .and_then(|frame| self.contract_cfgs.get(&frame.code_id()))
+            .map_or(0, |contract_cfg| contract_cfg.cfg_runner.entry_pc())
+    }
+    
+    /// Find the start pc of the block containing `pc` in the given frame's contract
+    fn block_start_pc(&self, frame_id: usize, pc: u16) -> Option<u16> {
+        let frame = self.call_frames.get(frame_id)?;
+        let contract_cfg = self.contract_cfgs.get(&frame.code_id())?;
+        contract_cfg
+            .cfg_runner
+            .map_to_instructionblock
+            .range(..=(pc, u16::MAX))
+            .next_back()
+            .filter(|((start_pc, end_pc), _)| *start_pc <= pc && pc <= *end_pc)
+            .map(|((start_pc, _), _)| *start_pc)
+    }
     
     /// Export global transaction graph in DOT format
     pub fn export_global_graph_dot(&self) -> String {
         let mut dot_str = String::new();
         
         writeln!(&mut dot_str, "digraph G {{").unwrap();
+        if let Some(chain_id) = self.chain_id {
+            writeln!(&mut dot_str, "    // chain id: {}", chain_id).unwrap();
+        }
         writeln!(&mut dot_str, "    rankdir=TB;").unwrap();
         writeln!(&mut dot_str, "    node [shape=box, style=\"filled, rounded\", color=\"#565f89\", fontcolor=\"#c0caf5\", fontname=\"Helvetica\", fillcolor=\"#24283b\"];").unwrap();
         writeln!(&mut dot_str, "    edge [color=\"#414868\", fontcolor=\"#c0caf5\", fontname=\"Helvetica\"];").unwrap();
         writeln!(&mut dot_str, "    bgcolor=\"#1a1b26\";").unwrap();
         
-        // Add nodes
+        // Add nodes, grouped into one cluster per call frame
+        let max_gas = self.global_graph.node_weights().map(|node| node.gas).max().unwrap_or(0);
+        let mut nodes_by_frame: BTreeMap<usize, Vec<_>> = BTreeMap::new();
         for (idx, node) in self.global_graph.node_indices().zip(self.global_graph.node_weights()) {
-            let addr_str = format!("{:?}", node.contract_address);
-            let label = format!("{}\\nPC: {}\\n{}", addr_str, node.pc, node.instruction.replace('"', "\\\""));
-            
-            // Apply the same highlighting logic as in cfg_dot_str_highlighted_only
-            // Color priority: SSTORE > ADD/SUB > others
-            let fillcolor = if node.contains_sstore {
-                "#f7768e" // Pink for SSTORE
-            } else if node.contains_add_or_sub {
-                "#ff9e64" // Orange for ADD/SUB
-            } else {
-                "#9ece6a" // Green for others
-            };
-            
+            nodes_by_frame.entry(node.frame_id).or_default().push((idx, node));
+        }
+        
+        for (frame_id, nodes) in nodes_by_frame {
+            let frame = &self.call_frames[frame_id];
+            writeln!(&mut dot_str, "    subgraph cluster_frame_{} {{", frame_id).unwrap();
+            let mut frame_label = format!("[{}] {} {:?}", frame.call_path_str(), frame.call_type, frame.code_address);
+            if frame.is_delegated() {
+                frame_label.push_str(&format!(" (storage: {:?})", frame.context_address));
+            }
+            if frame.code_kind == CodeKind::Init {
+                frame_label.push_str(" (initcode)");
+            }
+            frame_label.push_str(&format!(" gas: {} ({} with calls)", frame.gas_used, frame.inclusive_gas(&self.call_frames)));
             writeln!(
                 &mut dot_str,
-                "    {} [label=\"{}\", fillcolor=\"{}\", fontcolor=\"#1a1b26\"];",
-                idx.index(),
-                label,
-                fillcolor
+                "        label=\"{}\"; fontcolor=\"#c0caf5\"; color=\"#565f89\"; style=\"dashed, rounded\";",
+                frame_label
             ).unwrap();
+            
+            for (idx, node) in nodes {
+                let mut addr_str = format!("{:?}", node.contract_address);
+                // Storage writes land in the context account, not the code account
+                if node.contains_sstore && node.context_address != node.contract_address {
+                    addr_str.push_str(&format!("\\nstorage: {:?}", node.context_address));
+                }
+                let mut label = format!("{}\\nPC: {}\\n{}", addr_str, node.pc, node.instruction.replace('"', "\\\""));
+                if node.gas > 0 {
+                    label.push_str(&format!("gas: {}\\n", node.gas));
+                }
+                
+                // Apply the same highlighting logic as in cfg_dot_str_highlighted_only
+                // Color priority: SSTORE > ADD/SUB > others
+                let fillcolor = if self.gas_heatmap {
+                    heat_color(node.gas, max_gas)
+                } else if node.contains_sstore {
+                    "#f7768e" // Pink for SSTORE
+                } else if node.contains_add_or_sub {
+                    "#ff9e64" // Orange for ADD/SUB
+                } else {
+                    "#9ece6a" // Green for others
+                };
+                
+                writeln!(
+                    &mut dot_str,
+                    "        {} [label=\"{}\", fillcolor=\"{}\", fontcolor=\"#1a1b26\"];",
+                    idx.index(),
+                    label,
+                    fillcolor
+                ).unwrap();
+            }
+            
+            writeln!(&mut dot_str, "    }}").unwrap();
         }
         
         // Add edges
@@ -290,17 +893,26 @@ impl TransactionAnalyzer {
             let (from, to) = (edge.source().index(), edge.target().index());
             
             match &edge.weight() {
-                TransactionEdge::Internal(edge_type) => {
-                    let style = match edge_type.as_str() {
-                        "ConditionTrue" => "color=\"#9ece6a\", label=\"True\"",
-                        "ConditionFalse" => "color=\"#f7768e\", label=\"False\"",
-                        "SymbolicJump" => "color=\"#e0af68\", style=\"dotted\", label=\"Symbolic\"",
+                TransactionEdge::Internal { edge_type, traversal } => {
+                    let style = match edge_type {
+                        Some(Edges::ConditionTrue) => "color=\"#9ece6a\", label=\"True\"",
+                        Some(Edges::ConditionFalse) => "color=\"#f7768e\", label=\"False\"",
+                        Some(Edges::SymbolicJump) => "color=\"#e0af68\", style=\"dotted\", label=\"Symbolic\"",
+                        None => "color=\"#e0af68\", style=\"dotted\", label=\"traced\"",
                         _ => "color=\"#414868\""
                     };
-                    writeln!(&mut dot_str, "    {} -> {} [{}];", from, to, style).unwrap();
+                    match traversal {
+                        Some(traversal) => writeln!(&mut dot_str, "    {} -> {} [{}, xlabel=\"{}\"];", from, to, style, traversal.label()).unwrap(),
+                        None => writeln!(&mut dot_str, "    {} -> {} [{}];", from, to, style).unwrap(),
+                    }
                 },
-                TransactionEdge::External(call_type) => {
-                    let style = "color=\"#7aa2f7\", style=\"bold\", penwidth=2, label=\"".to_owned() + call_type + "\"";
+                TransactionEdge::External(call) => {
+                    let style = "color=\"#7aa2f7\", style=\"bold\", penwidth=2, label=\"".to_owned() + &call.replace('"', "\\\"") + "\"";
+                    writeln!(&mut dot_str, "    {} -> {} [{}];", from, to, style).unwrap();
+                }
+                TransactionEdge::Return { success, label } => {
+                    let color = if *success { "#9ece6a" } else { "#f7768e" };
+                    let style = format!("color=\"{}\", style=\"dashed\", penwidth=2, label=\"{}\"", color, label.replace('"', "\\\""));
                     writeln!(&mut dot_str, "    {} -> {} [{}];", from, to, style).unwrap();
                 }
             }
@@ -318,6 +930,213 @@ impl TransactionAnalyzer {
         Ok(())
     }
     
+    /// Describe what was analyzed: network, transaction, block and executed contracts
+    pub fn export_metadata(&self, network_name: &str, network: &NetworkConfig) -> serde_json::Value {
+        let tx_hash = self.tx_hash.map(|tx_hash| format!("{:?}", tx_hash));
+        let block_number = self.block_number();
+        let mut contracts: Vec<String> = self.contract_addresses.iter().map(|address| format!("{:?}", address)).collect();
+        contracts.sort();
+        
+        serde_json::json!({
+            "network": network_name,
+            "chain_id": self.chain_id.unwrap_or(network.chain_id),
+            "tx_hash": tx_hash,
+            "block_number": block_number,
+            "hardfork": format!("{:?}", self.spec_id),
+            "explorer_url": tx_hash.as_deref().and_then(|tx_hash| network.explorer_tx_url(tx_hash)),
+            "call_frames": self.call_frames.len(),
+            "contracts": contracts,
+            "compilers": self.compiler_metadata(),
+            "functions": self.function_entries(),
+            "gas": self.gas_metadata(),
+        })
+    }
+    
+    /// Storage reads and writes of every account, in execution order
+    pub fn storage_timeline(&self) -> BTreeMap<H160, Vec<StorageEffect>> {
+        storage::storage_timeline(&self.call_frames, &self.prestate_storage)
+    }
+    
+    /// KECCAK256 preimages of every frame, mapping slots are hashes of a key and a slot
+    pub fn hash_preimages(&self) -> HashMap<H256, Bytes> {
+        self.call_frames
+            .iter()
+            .flat_map(|frame| frame.hash_preimages.iter().map(|(hash, input)| (*hash, input.clone())))
+            .collect()
+    }
+    
+    /// Name of the state variable a storage access touched, if a layout for the account or its code is loaded
+    pub fn slot_name(&self, effect: &StorageEffect, hash_preimages: &HashMap<H256, Bytes>) -> Option<String> {
+        if effect.op.is_transient() {
+            return None; // transient state variables are not part of the storage layout
+        }
+        let frame = &self.call_frames[effect.frame_id];
+        let layout = self
+            .storage_layouts
+            .get(&frame.context_address)
+            .or_else(|| self.storage_layouts.get(&frame.code_address))?;
+        layout.slot_name(effect.slot, hash_preimages)
+    }
+    
+    /// Storage timeline as JSON, keyed by the account whose storage was accessed
+    pub fn export_storage_timeline(&self) -> serde_json::Value {
+        let hash_preimages = self.hash_preimages();
+        let accounts: serde_json::Map<String, serde_json::Value> = self
+            .storage_timeline()
+            .into_iter()
+            .map(|(address, effects)| {
+                let effects: Vec<serde_json::Value> = effects
+                    .iter()
+                    .map(|effect| {
+                        let frame = &self.call_frames[effect.frame_id];
+                        serde_json::json!({
+                            "op": effect.op.name(),
+                            "slot": format!("{:?}", effect.slot),
+                            "variable": self.slot_name(effect, &hash_preimages),
+                            "old_value": effect.old_value.map(|value| format!("{:?}", value)),
+                            "new_value": effect.new_value.map(|value| format!("{:?}", value)),
+                            "pc": effect.pc,
+                            "frame": frame.call_path_str(),
+                            "code_address": format!("{:?}", frame.code_address),
+                            "reverted": effect.reverted,
+                        })
+                    })
+                    .collect();
+                (format!("{:?}", address), serde_json::Value::Array(effects))
+            })
+            .collect();
+        serde_json::Value::Object(accounts)
+    }
+    
+    /// Save the storage timeline as JSON
+    pub fn save_storage_timeline(&self, output_path: &str) -> Result<()> {
+        let timeline = self.export_storage_timeline();
+        std::fs::write(output_path, serde_json::to_string_pretty(&timeline)?)?;
+        Ok(())
+    }
+    
+    /// Gas spent by every frame and the hottest blocks
+    pub fn gas_metadata(&self) -> serde_json::Value {
+        const HOTSPOTS: usize = 20;
+        let frames: Vec<serde_json::Value> = self
+            .call_frames
+            .iter()
+            .map(|frame| {
+                serde_json::json!({
+                    "call_path": frame.call_path_str(),
+                    "address": format!("{:?}", frame.code_address),
+                    "gas": frame.gas_used,
+                    "gas_with_calls": frame.inclusive_gas(&self.call_frames),
+                })
+            })
+            .collect();
+        let hotspots: Vec<serde_json::Value> = self
+            .gas_hotspots()
+            .into_iter()
+            .take(HOTSPOTS)
+            .map(|hotspot| {
+                serde_json::json!({
+                    "call_path": self.call_frames[hotspot.frame_id].call_path_str(),
+                    "address": format!("{:?}", hotspot.code_id.address),
+                    "start_pc": hotspot.block.0,
+                    "end_pc": hotspot.block.1,
+                    "gas": hotspot.gas,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "execution": self.execution_gas(),
+            "frames": frames,
+            "hotspots": hotspots,
+        })
+    }
+    
+    /// Gas spent by all executed instructions, intrinsic transaction gas and refunds excluded
+    pub fn execution_gas(&self) -> u64 {
+        self.call_frames.iter().map(|frame| frame.gas_used).sum()
+    }
+    
+    /// Blocks of every frame by the gas they spent there, most expensive first
+    pub fn gas_hotspots(&self) -> Vec<GasHotspot> {
+        let mut hotspots = Vec::new();
+        for frame in &self.call_frames {
+            let Some(contract_cfg) = self.contract_cfgs.get(&frame.code_id()) else {
+                continue;
+            };
+            for (node, block) in contract_cfg.cfg_runner.map_to_instructionblock {
+                let gas = block_gas(frame, block);
+                if gas > 0 {
+                    hotspots.push(GasHotspot { frame_id: frame.id, code_id: frame.code_id(), block: *node, gas });
+                }
+            }
+        }
+        hotspots.sort_by(|a, b| b.gas.cmp(&a.gas).then(a.frame_id.cmp(&b.frame_id)).then(a.block.cmp(&b.block)));
+        hotspots
+    }
+    
+    /// Compiler metadata found in the trailer of each contract's code, sorted by code
+    pub fn compiler_metadata(&self) -> Vec<serde_json::Value> {
+        let mut contract_cfgs: Vec<&ContractCFG> = self.contract_cfgs.values().collect();
+        contract_cfgs.sort_by_key(|contract_cfg| contract_cfg.code_id);
+        contract_cfgs
+            .into_iter()
+            .filter_map(|contract_cfg| {
+                let metadata = contract_cfg.layout.metadata.as_ref()?;
+                Some(serde_json::json!({
+                    "address": format!("{:?}", contract_cfg.code_id.address),
+                    "code": format!("{:?}", contract_cfg.code_id.kind).to_lowercase(),
+                    "compiler": metadata.to_string(),
+                    "ipfs": metadata.ipfs,
+                    "swarm": metadata.swarm,
+                }))
+            })
+            .collect()
+    }
+    
+    /// External functions recovered from each contract's dispatcher, and whether they ran
+    pub fn function_entries(&self) -> Vec<serde_json::Value> {
+        let mut contract_cfgs: Vec<&ContractCFG> = self.contract_cfgs.values().collect();
+        contract_cfgs.sort_by_key(|contract_cfg| contract_cfg.code_id);
+        contract_cfgs
+            .into_iter()
+            .flat_map(|contract_cfg| {
+                let dispatcher = contract_cfg.cfg_runner.dispatcher.as_ref();
+                dispatcher.into_iter().flat_map(|dispatcher| &dispatcher.entries).map(|(function, entry)| {
+                    serde_json::json!({
+                        "address": format!("{:?}", contract_cfg.code_id.address),
+                        "code": format!("{:?}", contract_cfg.code_id.kind).to_lowercase(),
+                        "function": function.to_string(),
+                        "entry_pc": entry.0,
+                        "executed": contract_cfg.cfg_runner.block_ran(*entry, &contract_cfg.executed_pcs),
+                    })
+                })
+            })
+            .collect()
+    }
+    
+    /// External functions of a contract that ran during the transaction
+    pub fn executed_functions(&self, code_id: &CodeId) -> Vec<FunctionId> {
+        let Some(contract_cfg) = self.contract_cfgs.get(code_id) else {
+            return Vec::new();
+        };
+        let Some(dispatcher) = contract_cfg.cfg_runner.dispatcher.as_ref() else {
+            return Vec::new();
+        };
+        dispatcher
+            .entries
+            .iter()
+            .filter(|(_, entry)| contract_cfg.cfg_runner.block_ran(**entry, &contract_cfg.executed_pcs))
+            .map(|(function, _)| *function)
+            .collect()
+    }
+    
+    /// Save the analysis metadata as JSON
+    pub fn save_metadata(&self, output_path: &str, network_name: &str, network: &NetworkConfig) -> Result<()> {
+        let metadata = self.export_metadata(network_name, network);
+        std::fs::write(output_path, serde_json::to_string_pretty(&metadata)?)?;
+        Ok(())
+    }
+    
     /// Convert to other formats (PNG, SVG, etc.)
     pub fn convert_to_image(&self, dot_path: &str, output_path: &str) -> Result<()> {
         let ext = Path::new(output_path).extension().and_then(|s| s.to_str()).unwrap_or("png");
@@ -337,12 +1156,12 @@ impl TransactionAnalyzer {
     }
 
     /// Export individual contract CFGs with only highlighted nodes and edges
-    pub fn export_contract_highlighted_cfgs(&self) -> HashMap<H160, String> {
+    pub fn export_contract_highlighted_cfgs(&self) -> HashMap<CodeId, String> {
         let mut results = HashMap::new();
         
-        for (address, contract_cfg) in &self.contract_cfgs {
+        for (code_id, contract_cfg) in &self.contract_cfgs {
             let dot_str = contract_cfg.cfg_runner.cfg_dot_str_highlighted_only();
-            results.insert(*address, dot_str);
+            results.insert(*code_id, dot_str);
         }
         
         results
@@ -355,9 +1174,8 @@ impl TransactionAnalyzer {
         // Create output directory if it doesn't exist
         std::fs::create_dir_all(output_dir)?;
         
-        for (address, dot_str) in self.export_contract_highlighted_cfgs() {
-            let address_str = format!("{:x}", address);
-            let output_path = format!("{}/{}.dot", output_dir, address_str);
+        for (code_id, dot_str) in self.export_contract_highlighted_cfgs() {
+            let output_path = format!("{}/{}.dot", output_dir, code_id.file_stem());
             std::fs::write(&output_path, dot_str)?;
             saved_files.push(output_path);
         }
@@ -365,3 +1183,56 @@ impl TransactionAnalyzer {
         Ok(saved_files)
     }
 }
+
+/// Storage accesses listed in a global graph node, a loop's are summed up after that
+const MAX_STORAGE_PER_NODE: usize = 4;
+
+/// Node label line of a storage access, e.g. `SSTORE [0x1] 0x0 → 0x5` or `SSTORE totalSupply 0x0 → 0x5`
+fn describe_storage(effect: &StorageEffect, variable: Option<&str>) -> String {
+    let word = |value: Option<H256>| match value {
+        Some(value) => format!("{:#x}", U256::from_big_endian(value.as_bytes())),
+        None => "?".to_string(),
+    };
+    let slot = match variable {
+        Some(variable) => variable.to_string(),
+        None => format!("[{}]", word(Some(effect.slot))),
+    };
+    let mut label = if effect.op.is_write() {
+        format!("{} {} {} → {}", effect.op.name(), slot, word(effect.old_value), word(effect.new_value))
+    } else {
+        format!("{} {} = {}", effect.op.name(), slot, word(effect.new_value))
+    };
+    if effect.reverted && effect.op.is_write() {
+        label.push_str(" (reverted)");
+    }
+    label
+}
+
+/// Gas spent by a block within one frame
+fn block_gas(frame: &CallFrame, block: &InstructionBlock) -> u64 {
+    block.ops.iter().filter_map(|(pc, _, _)| frame.gas_by_pc.get(pc)).sum()
+}
+
+/// Edge label of a return to the caller, e.g. `success\nreturndata: 32 bytes`
+fn describe_return(edge: &CallEdge) -> String {
+    let mut label = match &edge.error {
+        None => "success".to_string(),
+        Some(error) => format!("failure: {}", error),
+    };
+    if let Some(size) = edge.return_data_size {
+        label.push_str(&format!("\\nreturndata: {} bytes", size));
+    }
+    label
+}
+
+/// Join decoded arguments, cutting long ones (large arrays, bytes) to keep graph labels readable
+fn shorten_args(args: Vec<String>) -> String {
+    const MAX_ARG_LEN: usize = 66; // a bytes32 in hex
+    args.into_iter()
+        .map(|arg| match arg.char_indices().nth(MAX_ARG_LEN) {
+            Some((cut, _)) => format!("{}…", &arg[..cut]),
+            None => arg,
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
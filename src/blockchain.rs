@@ -2,19 +2,122 @@ use async_trait::async_trait;
 use ethers::{
     providers::{Http, Middleware, Provider},
     types::{H160, BlockId, BlockNumber, Bytes, H256},
+    utils::keccak256,
 };
 use eyre::{Result, eyre};
-use std::collections::HashMap;
+use crate::cfg_gen::local_exec::{self, ExecutionSnapshot, PrestateAccount, SnapshotBlock, SnapshotTransaction};
+use crate::cfg_gen::trace::{self, CallFrame, CallFrameBuilder, TraceStep};
+use futures::stream::{self, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 
 #[async_trait]
-pub trait BlockchainService {
-    async fn get_code(&self, address: H160) -> Result<Bytes>;
-    async fn get_transaction_trace(&self, tx_hash: H256) -> Result<String>;
+pub trait BlockchainService: Send + Sync {
+    /// Get the code currently deployed at `address`
+    async fn get_code(&self, address: H160) -> Result<Bytes> {
+        self.get_code_at(address, BlockId::Number(BlockNumber::Latest)).await
+    }
+
+    /// Get the code deployed at `address` as of `block`
+    async fn get_code_at(&self, address: H160, block: BlockId) -> Result<Bytes>;
+
+    /// Get the code of several addresses as of `block`, one result per address in order
+    async fn get_codes_at(&self, addresses: &[H160], block: BlockId) -> Vec<Result<Bytes>> {
+        let mut codes = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            codes.push(self.get_code_at(*address, block).await);
+        }
+        codes
+    }
+
+    /// Get the hash of the code deployed at `address` as of `block`
+    async fn get_code_hash_at(&self, address: H160, block: BlockId) -> Result<H256> {
+        Ok(H256(keccak256(self.get_code_at(address, block).await?)))
+    }
+
+    /// Get the number of the block a transaction was included in
+    async fn get_transaction_block(&self, tx_hash: H256) -> Result<u64>;
+
+    /// Get the timestamp of a block, which decides the hardfork after the merge
+    async fn get_block_timestamp(&self, block_number: u64) -> Result<u64>;
+
+    /// Get the code of every account touched by a transaction, as it was right
+    /// before that transaction ran (i.e. after the preceding transactions of its block)
+    async fn get_prestate_code(&self, tx_hash: H256) -> Result<HashMap<H160, Bytes>>;
+
+    /// Get everything needed to re-execute a transaction locally: the transaction,
+    /// its block header and the pre-state of every account it touched
+    async fn get_execution_snapshot(&self, tx_hash: H256) -> Result<ExecutionSnapshot>;
+
+    /// Save the struct log trace of a transaction to `trace_path`, folding it into call frames on the way
+    async fn save_transaction_trace(&self, tx_hash: H256, trace_path: &Path) -> Result<Vec<CallFrame>>;
+}
+
+/// How code is fetched from the RPC node
+#[derive(Debug, Clone)]
+pub struct FetchOptions {
+    pub concurrency: usize,               // requests (or batches) in flight at once
+    pub batch_size: usize,                // addresses per JSON-RPC batch, 1 disables batching
+    pub max_retries: u32,                 // retries of a failed request before giving up on it
+    pub base_backoff: Duration,           // delay before the first retry, doubled on every further one
+    pub requests_per_second: Option<f64>, // calls allowed per second, unlimited if unset
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            batch_size: 20,
+            max_retries: 4,
+            base_backoff: Duration::from_millis(250),
+            requests_per_second: None,
+        }
+    }
+}
+
+/// Spaces out RPC calls so they stay under a requests-per-second quota
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until `calls` more calls may be made
+    async fn acquire(&self, calls: u32) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval * calls;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
 }
 
 pub struct EthersBlockchainService {
     provider: Arc<Provider<Http>>,
+    rpc_url: String,
+    http_client: reqwest::Client,
+    enable_memory: bool,
+    fetch_options: FetchOptions,
+    rate_limiter: Option<RateLimiter>,
+    batch_supported: AtomicBool, // cleared once the node rejects a batch request
 }
 
 impl EthersBlockchainService {
@@ -22,110 +125,582 @@ impl EthersBlockchainService {
         let provider = Provider::<Http>::try_from(rpc_url)?;
         Ok(Self {
             provider: Arc::new(provider),
+            rpc_url: rpc_url.to_string(),
+            http_client: reqwest::Client::new(),
+            enable_memory: false,
+            fetch_options: FetchOptions::default(),
+            rate_limiter: None,
+            batch_supported: AtomicBool::new(true),
         })
     }
+
+    /// Set parallelism and batching of code fetching, and retries and rate limiting of every call
+    ///
+    /// Fails when the requests per second are not a positive number.
+    pub fn with_fetch_options(mut self, fetch_options: FetchOptions) -> Result<Self> {
+        if let Some(rps) = fetch_options.requests_per_second.filter(|rps| rps.is_nan() || *rps <= 0.0) {
+            return Err(eyre!("Requests per second must be positive, got {}", rps));
+        }
+        self.rate_limiter = fetch_options.requests_per_second.map(RateLimiter::new);
+        self.fetch_options = fetch_options;
+        Ok(self)
+    }
+
+    async fn throttle(&self, calls: u32) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(calls).await;
+        }
+    }
+
+    /// Make a call, retrying failures with exponential backoff the way code fetching does
+    ///
+    /// Every attempt waits for the rate limiter.
+    async fn with_retries<T, F, Fut>(&self, call: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            self.throttle(1).await;
+            match call().await {
+                Err(_) if attempt < self.fetch_options.max_retries => {
+                    tokio::time::sleep(self.fetch_options.base_backoff * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// JSON-RPC call through the provider, with retries
+    async fn request<P, T>(&self, method: &str, params: P) -> Result<T>
+    where
+        P: Serialize + Clone + std::fmt::Debug + Send + Sync,
+        T: Serialize + DeserializeOwned + std::fmt::Debug + Send,
+    {
+        self.with_retries(|| async { Ok(self.provider.request(method, params.clone()).await?) })
+            .await
+    }
+
+    /// Fetch the code of a chunk of addresses, retrying failed ones with exponential backoff
+    async fn fetch_code_chunk(&self, addresses: &[H160], block: BlockId) -> Vec<Result<Bytes>> {
+        let mut codes: Vec<Option<Result<Bytes>>> = addresses.iter().map(|_| None).collect();
+        let mut pending: Vec<usize> = (0..addresses.len()).collect();
+        let mut attempt = 0;
+
+        loop {
+            let batch: Vec<H160> = pending.iter().map(|&i| addresses[i]).collect();
+            let results = if batch.len() > 1 && self.batch_supported.load(Ordering::Relaxed) {
+                match self.batch_get_code(&batch, block).await {
+                    Ok(results) => results,
+                    Err(e) => batch.iter().map(|_| Err(eyre!("Batch request failed: {}", e))).collect(),
+                }
+            } else {
+                let mut results = Vec::with_capacity(batch.len());
+                for address in &batch {
+                    self.throttle(1).await;
+                    results.push(self.provider.get_code(*address, Some(block)).await.map_err(Into::into));
+                }
+                results
+            };
+
+            let mut failed = Vec::new();
+            for (i, result) in pending.into_iter().zip(results) {
+                if result.is_err() {
+                    failed.push(i);
+                }
+                codes[i] = Some(result);
+            }
+            if failed.is_empty() || attempt >= self.fetch_options.max_retries {
+                break;
+            }
+
+            tokio::time::sleep(self.fetch_options.base_backoff * 2u32.pow(attempt)).await;
+            attempt += 1;
+            pending = failed;
+        }
+
+        codes
+            .into_iter()
+            .map(|code| code.unwrap_or_else(|| Err(eyre!("Code was never fetched"))))
+            .collect()
+    }
+
+    /// Fetch the code of several addresses in a single JSON-RPC batch request
+    ///
+    /// Nodes that do not support batches answer with a single error object instead of
+    /// an array, in which case batching is turned off and the call fails so it is retried
+    /// one address at a time.
+    async fn batch_get_code(&self, addresses: &[H160], block: BlockId) -> Result<Vec<Result<Bytes>>> {
+        let requests: Vec<serde_json::Value> = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, address)| {
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": "eth_getCode",
+                    "params": [address, block],
+                })
+            })
+            .collect();
+
+        self.throttle(addresses.len() as u32).await;
+        let response = self
+            .http_client
+            .post(&self.rpc_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&requests)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        let responses: Vec<serde_json::Value> = match serde_json::from_slice(&response)? {
+            serde_json::Value::Array(responses) => responses,
+            other => {
+                // A single error can also be a transient one (e.g. rate limiting) that applies to the whole batch
+                let code = other.pointer("/error/code").and_then(|code| code.as_i64());
+                let message = other.pointer("/error/message").and_then(|m| m.as_str()).unwrap_or_default();
+                if matches!(code, Some(-32600) | Some(-32601)) || message.to_lowercase().contains("batch") {
+                    self.batch_supported.store(false, Ordering::Relaxed);
+                    return Err(eyre!("Node does not support batch requests: {}", other));
+                }
+                return Err(eyre!("Batch rejected: {}", other));
+            }
+        };
+
+        // Responses may come back in any order, match them up by id
+        let mut codes: Vec<Result<Bytes>> = addresses.iter().map(|_| Err(eyre!("Missing from batch response"))).collect();
+        for response in responses {
+            let Some(id) = response.get("id").and_then(|id| id.as_u64()).map(|id| id as usize) else {
+                continue;
+            };
+            if id >= codes.len() {
+                continue;
+            }
+            codes[id] = match (response.get("result"), response.get("error")) {
+                (Some(result), _) => serde_json::from_value(result.clone()).map_err(Into::into),
+                (None, Some(error)) => Err(eyre!("RPC error: {}", error)),
+                (None, None) => Err(eyre!("Malformed batch response: {}", response)),
+            };
+        }
+        Ok(codes)
+    }
+
+    /// Get the `prestateTracer` result of a transaction
+    async fn get_prestate(&self, tx_hash: H256) -> Result<HashMap<H160, PrestateAccount>> {
+        // prestateTracer returns { address: { balance, nonce, code, storage } } for every touched account
+        let params = serde_json::json!([
+            tx_hash,
+            { "tracer": "prestateTracer" }
+        ]);
+        self.request("debug_traceTransaction", params).await
+    }
+
+    /// Hashes of the earlier blocks a transaction reads with BLOCKHASH
+    ///
+    /// A JS tracer collects the block numbers BLOCKHASH is called with, only those among
+    /// the 256 blocks the opcode can see are looked up.
+    async fn get_read_block_hashes(&self, tx_hash: H256, block_number: u64) -> Result<HashMap<u64, H256>> {
+        let blockhash_tracer = r#"
+        {
+          numbers: [],
+          step: function(log) {
+            if (log.op.toString() == "BLOCKHASH") {
+              this.numbers.push(log.stack.peek(0).toString(10));
+            }
+          },
+          fault: function(log) {},
+          result: function() { return this.numbers; }
+        }
+        "#;
+        let params = serde_json::json!([
+            tx_hash,
+            { "tracer": blockhash_tracer }
+        ]);
+        let numbers: Vec<String> = self.request("debug_traceTransaction", params).await?;
+        let visible: BTreeSet<u64> = numbers
+            .iter()
+            .filter_map(|number| number.parse::<u64>().ok())
+            .filter(|number| *number < block_number && block_number - number <= 256)
+            .collect();
+
+        let mut block_hashes = HashMap::new();
+        for number in visible {
+            let block = self
+                .with_retries(|| async { Ok(self.provider.get_block(number).await?) })
+                .await?
+                .ok_or_else(|| eyre!("Block {} not found", number))?;
+            let hash = block.hash.ok_or_else(|| eyre!("Block {} has no hash", number))?;
+            block_hashes.insert(number, hash);
+        }
+        Ok(block_hashes)
+    }
+
+    /// Get the id of the chain the node serves
+    pub async fn chain_id(&self) -> Result<u64> {
+        let chain_id = self.with_retries(|| async { Ok(self.provider.get_chainid().await?) }).await?;
+        Ok(chain_id.as_u64())
+    }
+
+    /// Include memory in fetched traces. Needed to recover initcode, created runtime
+    /// code and calldata, but makes traces considerably larger.
+    pub fn with_memory(mut self, enable_memory: bool) -> Self {
+        self.enable_memory = enable_memory;
+        self
+    }
 }
 
 #[async_trait]
 impl BlockchainService for EthersBlockchainService {
-    async fn get_code(&self, address: H160) -> Result<Bytes> {
-        let code = self
-            .provider
-            .get_code(address, Some(BlockId::Number(BlockNumber::Latest)))
+    async fn get_code_at(&self, address: H160, block: BlockId) -> Result<Bytes> {
+        self.with_retries(|| async { Ok(self.provider.get_code(address, Some(block)).await?) })
+            .await
+    }
+
+    async fn get_code_hash_at(&self, address: H160, block: BlockId) -> Result<H256> {
+        // The account proof carries the code hash without the code itself
+        let proof = self
+            .with_retries(|| async { Ok(self.provider.get_proof(address, Vec::new(), Some(block)).await?) })
             .await?;
-        Ok(code)
+        Ok(proof.code_hash)
+    }
+
+    async fn get_codes_at(&self, addresses: &[H160], block: BlockId) -> Vec<Result<Bytes>> {
+        let batch_size = self.fetch_options.batch_size.max(1);
+        let fetches: Vec<_> = addresses
+            .chunks(batch_size)
+            .map(|chunk| self.fetch_code_chunk(chunk, block))
+            .collect();
+        let chunks: Vec<Vec<Result<Bytes>>> = stream::iter(fetches)
+            .buffered(self.fetch_options.concurrency.max(1))
+            .collect()
+            .await;
+        chunks.into_iter().flatten().collect()
+    }
+
+    async fn get_transaction_block(&self, tx_hash: H256) -> Result<u64> {
+        let tx = self
+            .with_retries(|| async { Ok(self.provider.get_transaction(tx_hash).await?) })
+            .await?
+            .ok_or_else(|| eyre!("Transaction {:?} not found", tx_hash))?;
+        let block_number = tx
+            .block_number
+            .ok_or_else(|| eyre!("Transaction {:?} is still pending", tx_hash))?;
+        Ok(block_number.as_u64())
+    }
+
+    async fn get_block_timestamp(&self, block_number: u64) -> Result<u64> {
+        let block = self
+            .with_retries(|| async { Ok(self.provider.get_block(block_number).await?) })
+            .await?
+            .ok_or_else(|| eyre!("Block {} not found", block_number))?;
+        Ok(block.timestamp.as_u64())
+    }
+
+    async fn get_prestate_code(&self, tx_hash: H256) -> Result<HashMap<H160, Bytes>> {
+        let prestate = self.get_prestate(tx_hash).await?;
+        Ok(prestate
+            .into_iter()
+            .filter_map(|(address, account)| account.code.map(|code| (address, code)))
+            .collect())
+    }
+
+    async fn get_execution_snapshot(&self, tx_hash: H256) -> Result<ExecutionSnapshot> {
+        let transaction: Option<SnapshotTransaction> = self.request("eth_getTransactionByHash", [tx_hash]).await?;
+        let transaction = transaction.ok_or_else(|| eyre!("Transaction {:?} not found", tx_hash))?;
+        
+        let block_number = self.get_transaction_block(tx_hash).await?;
+        let block_params = serde_json::json!([format!("{:#x}", block_number), false]);
+        let block: Option<SnapshotBlock> = self.request("eth_getBlockByNumber", block_params).await?;
+        let block = block.ok_or_else(|| eyre!("Block {} not found", block_number))?;
+        
+        let prestate = self.get_prestate(tx_hash).await?;
+        let block_hashes = self.get_read_block_hashes(tx_hash, block_number).await?;
+        Ok(ExecutionSnapshot { transaction, block, prestate, block_hashes })
     }
     
-    async fn get_transaction_trace(&self, tx_hash: H256) -> Result<String> {
-        // Custom JS tracer to get address information for execution steps
+    async fn save_transaction_trace(&self, tx_hash: H256, trace_path: &Path) -> Result<Vec<CallFrame>> {
+        // Custom JS tracer reporting the storage context whenever the depth changes, i.e. when a
+        // frame is entered or returned to, emitted as 0x hex rather than the byte map a raw
+        // address serializes to
         let address_tracer = r#"
         {
           data: [],
+          steps: 0,
+          depth: 0,
           step: function(log) {
-            this.data.push({
-              depth: log.getDepth(),
-              address: log.contract ? (log.contract.getAddress ? log.contract.getAddress() : log.contract.address) : null
-            });
+            if (log.getDepth() != this.depth) {
+              this.depth = log.getDepth();
+              this.data.push({
+                step: this.steps,
+                address: log.contract ? toHex(log.contract.getAddress()) : null
+              });
+            }
+            this.steps++;
           },
           fault: function(log) {},
           result: function() { return this.data; }
         }
         "#;
         
-        // 1. Get standard trace (structured logs)
-        let trace_params = serde_json::json!([tx_hash]);
-        let trace_result: serde_json::Value = self.provider.request("debug_traceTransaction", trace_params).await?;
-        
-        // Extract structLogs from result
-        let struct_logs = trace_result.get("structLogs")
-            .ok_or_else(|| eyre!("Invalid trace result: missing structLogs field"))?
-            .as_array()
-            .ok_or_else(|| eyre!("structLogs is not an array"))?;
-        
-        // 2. Get address information (using custom tracer)
+        // 1. Get address information (using custom tracer)
         let address_params = serde_json::json!([
             tx_hash,
             { "tracer": address_tracer }
         ]);
-        let address_trace: Vec<serde_json::Value> = self.provider.request("debug_traceTransaction", address_params).await?;
+        let frame_addresses: Vec<FrameAddress> = self.request("debug_traceTransaction", address_params).await?;
         
-        // 3. Merge data from both traces
-        let mut merged_steps = Vec::new();
+        // 2. Get standard trace (structured logs), the storage map gives the values of SLOAD
+        // and SSTORE, it is requested explicitly since not every client records it by default
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "debug_traceTransaction",
+            "params": [tx_hash, { "enableMemory": self.enable_memory, "disableStorage": false }],
+        });
+        let body = serde_json::to_vec(&request)?;
+        // Only getting the response started is retried, a stream cut off halfway fails the fetch
+        let mut response = self
+            .with_retries(|| async {
+                let response = self
+                    .http_client
+                    .post(&self.rpc_url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+                    .send()
+                    .await?;
+                Ok(response.error_for_status()?)
+            })
+            .await?;
         
-        for (i, log) in struct_logs.iter().enumerate() {
-            // Extract necessary fields from standard trace
-            let pc = log.get("pc").and_then(|v| v.as_u64()).unwrap_or(0);
-            let op = log.get("op").and_then(|v| v.as_str()).unwrap_or("").to_string();
-            
-            // Get stack data, ensure format consistency
-            let stack = log.get("stack")
-                .and_then(|v| v.as_array())
-                .map(|arr| arr.iter().map(|item| item.as_str().unwrap_or("").to_string()).collect::<Vec<String>>())
-                .unwrap_or_default();
-            
-            // Get depth
-            let depth = log.get("depth").and_then(|v| v.as_u64()).unwrap_or(0);
-            
-            // Get gas related information
-            let gas = log.get("gas").and_then(|v| v.as_u64());
-            let gas_cost = log.get("gasCost").and_then(|v| v.as_u64());
-            
-            // Get address information from address trace
-            let address = if i < address_trace.len() {
-                address_trace[i].get("address").cloned()
-            } else {
-                None
+        // 3. Struct logs can be several GB, hand the response over chunk by chunk to be folded
+        // into frames and written out while it is still being received
+        let (sender, receiver) = tokio::sync::mpsc::channel(TRACE_CHUNK_BUFFER);
+        let trace_path = trace_path.to_path_buf();
+        let fold = tokio::task::spawn_blocking(move || {
+            save_struct_logs(ChunkReader::new(receiver), &trace_path, &frame_addresses)
+        });
+        loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => Ok(chunk.to_vec()),
+                Ok(None) => break,
+                Err(e) => Err(std::io::Error::other(e)),
             };
-            
-            // Create merged step object
-            let mut step = serde_json::json!({
-                "pc": pc,
-                "op": op,
-                "stack": stack,
-                "depth": depth
-            });
-            
-            // Add optional fields
-            if let Some(g) = gas {
-                step["gas"] = serde_json::json!(g);
+            let failed = chunk.is_err();
+            // A closed channel means parsing already failed, its error is returned below
+            if sender.send(chunk).await.is_err() || failed {
+                break;
             }
-            
-            if let Some(gc) = gas_cost {
-                step["gasCost"] = serde_json::json!(gc);
+        }
+        drop(sender);
+        fold.await?
+    }
+}
+
+/// Chunks of a trace response buffered between receiving and parsing it
+const TRACE_CHUNK_BUFFER: usize = 64;
+
+/// Storage context of the steps from `step` on, until the depth changes again
+#[derive(Debug, Serialize, Deserialize)]
+struct FrameAddress {
+    step: usize,
+    address: Option<H160>,
+}
+
+/// Blocking reader over response chunks received on an async task
+struct ChunkReader {
+    receiver: tokio::sync::mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl ChunkReader {
+    fn new(receiver: tokio::sync::mpsc::Receiver<std::io::Result<Vec<u8>>>) -> Self {
+        Self { receiver, chunk: Vec::new(), position: 0 }
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                None => return Ok(0),
             }
-            
-            if let Some(addr) = address {
-                step["address"] = addr;
+        }
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Fold struct log steps into call frames while writing them to `trace_path`
+///
+/// Every step is written as the node sent it, including `error` and the `storage` map,
+/// only the storage context from `frame_addresses` is added to steps that lack one.
+fn save_struct_logs(reader: impl Read, trace_path: &Path, frame_addresses: &[FrameAddress]) -> Result<Vec<CallFrame>> {
+    let mut writer = BufWriter::new(std::fs::File::create(trace_path)?);
+    writer.write_all(b"[")?;
+    
+    let mut builder = CallFrameBuilder::new();
+    let mut frame_addresses = frame_addresses.iter().peekable();
+    let mut address = None;
+    let mut failure: Option<eyre::Report> = None;
+    trace::stream_trace_as(reader, |raw: Box<RawValue>| {
+        if failure.is_some() {
+            return;
+        }
+        let i = builder.step_count();
+        while let Some(frame_address) = frame_addresses.next_if(|frame_address| frame_address.step <= i) {
+            address = frame_address.address;
+        }
+        let mut step: TraceStep = match serde_json::from_str(raw.get()) {
+            Ok(step) => step,
+            Err(e) => {
+                failure = Some(eyre!("Invalid trace step {}: {}", i, e));
+                return;
+            }
+        };
+        let written = match address.filter(|_| step.address.is_none()) {
+            Some(address) => {
+                step.address = Some(address);
+                let body = raw.get().trim_start().trim_start_matches('{').trim_start();
+                let separator = if body.starts_with('}') { "" } else { "," };
+                write!(writer, "{}\n{{\"address\":\"{:?}\"{}{}", if i == 0 { "" } else { "," }, address, separator, body)
             }
-            
-            merged_steps.push(step);
+            None => write!(writer, "{}\n{}", if i == 0 { "" } else { "," }, raw.get()),
+        };
+        if let Err(e) = written {
+            failure = Some(e.into());
+            return;
         }
-        
-        // Convert merged steps to JSON string
-        Ok(serde_json::to_string_pretty(&merged_steps)?)
+        builder.push(step);
+    })?;
+    if let Some(failure) = failure {
+        return Err(failure);
     }
+    
+    writer.write_all(b"\n]\n")?;
+    writer.flush()?;
+    Ok(builder.finish())
 }
 
+/// Serves code and traces from files saved by an earlier run, for analysis without an RPC node
+///
+/// Expects the layout written to `Results/<tx>/`:
+/// - `code/<address>.hex`: deployed code of an account, as of the transaction's pre-state
+/// - `Trace_<tx>.txt`: the transaction trace
+/// - `Snapshot_<tx>.json`: the execution snapshot used for local re-execution
+///
+/// Code is not versioned by block, every lookup returns the saved code regardless of `block`.
+pub struct FileBlockchainService {
+    root: PathBuf,
+}
+
+impl FileBlockchainService {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn code_dir(&self) -> PathBuf {
+        self.root.join(CODE_DIR)
+    }
+
+    fn trace_path(&self, tx_hash: H256) -> PathBuf {
+        self.root.join(format!("Trace_{:?}.txt", tx_hash))
+    }
+
+    fn snapshot_path(&self, tx_hash: H256) -> PathBuf {
+        self.root.join(format!("Snapshot_{:?}.json", tx_hash))
+    }
+
+    fn read_snapshot(&self, tx_hash: H256) -> Result<ExecutionSnapshot> {
+        let path = self.snapshot_path(tx_hash);
+        if !path.exists() {
+            return Err(eyre!("No execution snapshot for {:?} at {}", tx_hash, path.display()));
+        }
+        local_exec::parse_snapshot_file(&path.to_string_lossy())
+    }
+}
+
+#[async_trait]
+impl BlockchainService for FileBlockchainService {
+    async fn get_code_at(&self, address: H160, _block: BlockId) -> Result<Bytes> {
+        // Accept file names with and without the 0x prefix
+        let code_dir = self.code_dir();
+        let path = [format!("{:x}.hex", address), format!("{:?}.hex", address)]
+            .into_iter()
+            .map(|name| code_dir.join(name))
+            .find(|path| path.exists())
+            .ok_or_else(|| eyre!("No saved code for {:?} in {}", address, code_dir.display()))?;
+        read_code_file(&path)
+    }
+
+    async fn get_transaction_block(&self, tx_hash: H256) -> Result<u64> {
+        Ok(self.read_snapshot(tx_hash)?.block.number.as_u64())
+    }
+
+    async fn get_block_timestamp(&self, block_number: u64) -> Result<u64> {
+        Err(eyre!("The timestamp of block {} is not saved in {}", block_number, self.root.display()))
+    }
+
+    async fn get_prestate_code(&self, _tx_hash: H256) -> Result<HashMap<H160, Bytes>> {
+        let mut codes = HashMap::new();
+        for entry in std::fs::read_dir(self.code_dir())? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("hex") {
+                continue;
+            }
+            let Some(address) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<H160>().ok()) else {
+                continue;
+            };
+            codes.insert(address, read_code_file(&path)?);
+        }
+        Ok(codes)
+    }
+
+    async fn get_execution_snapshot(&self, tx_hash: H256) -> Result<ExecutionSnapshot> {
+        self.read_snapshot(tx_hash)
+    }
+
+    async fn save_transaction_trace(&self, tx_hash: H256, trace_path: &Path) -> Result<Vec<CallFrame>> {
+        let path = self.trace_path(tx_hash);
+        if !path.exists() {
+            return Err(eyre!("No saved trace for {:?} at {}", tx_hash, path.display()));
+        }
+        if path != trace_path {
+            std::fs::copy(&path, trace_path)?;
+        }
+        let mut builder = CallFrameBuilder::new();
+        trace::stream_trace_file(&trace_path.to_string_lossy(), |step| builder.push(step))?;
+        Ok(builder.finish())
+    }
+}
+
+/// Directory under a transaction's results that holds the code of every executed contract
+pub const CODE_DIR: &str = "code";
+
+fn read_code_file(path: &Path) -> Result<Bytes> {
+    let hex_code = std::fs::read_to_string(path)?;
+    hex_code
+        .trim()
+        .parse()
+        .map_err(|e| eyre!("Invalid code in {}: {}", path.display(), e))
+}
+
+#[derive(Default)]
 pub struct BytecodeCache {
     pub cache: HashMap<H160, Bytes>,
 }
@@ -144,35 +719,34 @@ impl BytecodeCache {
     pub fn insert(&mut self, address: H160, bytecode: Bytes) {
         self.cache.insert(address, bytecode);
     }
+
+    /// Save every cached code as `<address>.hex` so [`FileBlockchainService`] can serve it later
+    pub fn save_to_dir(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        for (address, bytecode) in &self.cache {
+            std::fs::write(dir.join(format!("{:x}.hex", address)), format!("{}", bytecode))?;
+        }
+        Ok(())
+    }
 }
 
 pub async fn fetch_all_bytecodes(
     addresses: &[H160],
-    blockchain_service: &impl BlockchainService,
+    block: BlockId,
+    blockchain_service: &dyn BlockchainService,
 ) -> Result<BytecodeCache> {
     let mut cache = BytecodeCache::new();
 
-    for address in addresses {
-        let bytecode = blockchain_service.get_code(*address).await?;
-        
-        // Only save non-empty contracts
-        if !bytecode.0.is_empty() {
-            cache.insert(*address, bytecode);
+    let codes = blockchain_service.get_codes_at(addresses, block).await;
+    for (address, bytecode) in addresses.iter().zip(codes) {
+        match bytecode {
+            // Only save non-empty contracts
+            Ok(bytecode) if !bytecode.0.is_empty() => cache.insert(*address, bytecode),
+            Ok(_) => {}
+            // One contract whose code could not be fetched should not sink the whole analysis
+            Err(e) => println!("⚠️ Could not fetch code of {:?}, skipping it: {}", address, e),
         }
     }
 
     Ok(cache)
 }
-
-// Save transaction trace to file (returns trace json string only)
-pub async fn save_transaction_trace(
-    tx_hash: H256,
-    blockchain_service: &impl BlockchainService,
-) -> Result<String> {
-    // Get transaction trace
-    let trace_json = blockchain_service.get_transaction_trace(tx_hash).await?;
-    
-    // Return the trace JSON without saving to file
-    // The main.rs will handle saving to the correct location
-    Ok(trace_json)
-}
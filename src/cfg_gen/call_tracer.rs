@@ -0,0 +1,154 @@
+use crate::cfg_gen::trace::{CallFrame, CodeKind, EmittedLog};
+use ethers::types::{Bytes, H160, H256, U256};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/// One call in the nested output of geth's built-in `callTracer`
+#[derive(Debug, Deserialize, Clone)]
+pub struct CallTrace {
+    #[serde(rename = "type")]
+    pub call_type: String,
+    pub from: H160,
+    pub to: Option<H160>,
+    #[serde(default)]
+    pub input: Bytes,
+    pub output: Option<Bytes>,
+    pub gas: Option<U256>,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: Option<U256>,
+    pub value: Option<U256>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub calls: Vec<CallTrace>,
+    #[serde(default)]
+    pub logs: Vec<CallTraceLog>, // only with the tracer's `withLog` option
+}
+
+/// Log emitted by a call, see [`CallTrace::logs`]
+#[derive(Debug, Deserialize, Clone)]
+pub struct CallTraceLog {
+    pub address: Option<H160>,
+    #[serde(default)]
+    pub topics: Vec<H256>,
+    #[serde(default)]
+    pub data: Bytes,
+}
+
+/// JSON-RPC response wrapper, for traces saved straight from `debug_traceTransaction`
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: CallTrace,
+}
+
+impl CallTrace {
+    /// Determine if this call created a contract
+    pub fn is_create(&self) -> bool {
+        self.call_type == "CREATE" || self.call_type == "CREATE2"
+    }
+
+    /// Get the 4-byte function selector of the calldata, if any
+    pub fn selector(&self) -> Option<[u8; 4]> {
+        if self.is_create() || self.input.len() < 4 {
+            return None;
+        }
+        let mut selector = [0u8; 4];
+        selector.copy_from_slice(&self.input[..4]);
+        Some(selector)
+    }
+}
+
+/// Parse a `callTracer` result, either bare or wrapped in a JSON-RPC response
+pub fn parse_call_trace(data: &str) -> eyre::Result<CallTrace> {
+    match serde_json::from_str::<CallTrace>(data) {
+        Ok(trace) => Ok(trace),
+        Err(_) => {
+            let response: RpcResponse = serde_json::from_str(data)?;
+            Ok(response.result)
+        }
+    }
+}
+
+/// Parse a `callTracer` trace file
+pub fn parse_call_trace_file(path: &str) -> eyre::Result<CallTrace> {
+    let data = std::fs::read_to_string(path)?;
+    parse_call_trace(&data)
+}
+
+/// Flatten the call tree into call frames, in execution order
+///
+/// callTracer reports `from`/`to` the way the EVM sees them: for DELEGATECALL and
+/// CALLCODE `to` is the code address while the frame keeps running on the caller's
+/// storage. No opcode-level data is available, so the frames carry no executed PCs.
+pub fn extract_call_frames(root: &CallTrace) -> Vec<CallFrame> {
+    let mut frames = Vec::new();
+    push_frame(root, None, vec![0], 1, &mut frames);
+    frames
+}
+
+fn push_frame(
+    call: &CallTrace,
+    parent: Option<usize>,
+    call_path: Vec<usize>,
+    depth: u64,
+    frames: &mut Vec<CallFrame>,
+) -> usize {
+    let code_address = call.to.unwrap_or_default();
+    let context_address = match (call.call_type.as_str(), parent) {
+        ("DELEGATECALL" | "CALLCODE", Some(parent_id)) => frames[parent_id].context_address,
+        ("DELEGATECALL" | "CALLCODE", None) => call.from,
+        _ => code_address,
+    };
+    let is_create = call.is_create();
+
+    let id = frames.len();
+    frames.push(CallFrame {
+        id,
+        parent,
+        call_path: call_path.clone(),
+        depth,
+        code_address,
+        context_address,
+        code_kind: if is_create { CodeKind::Init } else { CodeKind::Runtime },
+        call_type: call.call_type.clone(),
+        call_pc: None,
+        children: Vec::new(),
+        step_ranges: Vec::new(),
+        executed_pcs: HashSet::new(),
+        block_entries: Vec::new(),
+        gas_by_pc: HashMap::new(),
+        // gasUsed covers the children too, take them out below
+        gas_used: call.gas_used.map_or(0, |gas_used| gas_used.low_u64()),
+        step_count: 0,
+        // For creations the input is the initcode and the output is the deployed code
+        initcode: if is_create { Some(call.input.clone()) } else { None },
+        deployed_code: if is_create && call.error.is_none() { call.output.clone() } else { None },
+        error: call.error.clone(),
+        input: if is_create { None } else { Some(call.input.clone()) },
+        value: call.value,
+        gas: call.gas,
+        logs: call
+            .logs
+            .iter()
+            .map(|log| EmittedLog { pc: None, topics: log.topics.clone(), data: Some(log.data.clone()) })
+            .collect(),
+        storage_accesses: Vec::new(), // callTracer does not trace storage
+        hash_preimages: HashMap::new(),
+        exit_pc: None,
+        return_pc: None,
+        return_data_size: Some(call.output.as_ref().map_or(0, |output| output.len())),
+    });
+
+    for (i, child) in call.calls.iter().enumerate() {
+        let mut child_path = call_path.clone();
+        child_path.push(i);
+        let child_id = push_frame(child, Some(id), child_path, depth + 1, frames);
+        frames[id].children.push(child_id);
+        // The unused part of a stipend goes back to the caller as if it had paid for it, so
+        // the caller's own cost is what it used plus the stipend, minus what the child used
+        let child_gas = child.gas_used.map_or(0, |gas_used| gas_used.low_u64());
+        let stipend = frames[child_id].stipend();
+        frames[id].gas_used = (frames[id].gas_used + stipend).saturating_sub(child_gas);
+    }
+
+    id
+}
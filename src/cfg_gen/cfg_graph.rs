@@ -1,4 +1,7 @@
 use crate::cfg_gen::dasm::*; 
+use crate::cfg_gen::dispatcher::{Dispatcher, FunctionId};
+use crate::cfg_gen::eof::{self, EofContainer};
+use revm::interpreter::opcode::{CALLF, JUMPF, RETF, RJUMP, RJUMPI, RJUMPV};
 use itertools::Itertools; // Contains many useful collection operations, such as sorting, grouping, etc.
 use lazy_static::lazy_static; // Allows us to define "global variables" that are initialized only once and can be used later.
 use petgraph::dot::Dot;
@@ -9,8 +12,6 @@ use std::{
     hash::Hash,
 };
 
-use super::BLOCK_ENDERS_U8;
-
 lazy_static! {
     pub static ref TOKYO_NIGHT_COLORS: HashMap<&'static str, &'static str> = {
         let mut m = HashMap::new();
@@ -35,6 +36,10 @@ pub enum Edges {
     ConditionTrue,  // Conditional jumpi, true branch
     ConditionFalse, // Conditional jumpi, false branch
     SymbolicJump,   // Jump to a symbolic value
+    Case(u8),       // EOF RJUMPV, taken for the given index
+    CallF,          // EOF CALLF, into the called code section
+    RetF,           // EOF RETF, back to the instruction after a CALLF
+    JumpF,          // EOF JUMPF, tail call into another code section
 } // Defines different types of edges in the control flow graph
 
 impl Debug for Edges {
@@ -44,11 +49,73 @@ impl Debug for Edges {
             Edges::ConditionTrue => write!(f, "True"),
             Edges::ConditionFalse => write!(f, "False"),
             Edges::SymbolicJump => write!(f, "Symbolic"),
+            Edges::Case(index) => write!(f, "Case {}", index),
+            Edges::CallF => write!(f, "CALLF"),
+            Edges::RetF => write!(f, "RETF"),
+            Edges::JumpF => write!(f, "JUMPF"),
         }
     }
 } // Defines how each edge type is displayed when printed.
 
 type CFGDag = GraphMap<(u16, u16), Edges, Directed>; // Defines a directed graph type CFGDag
+pub type EdgeTraversals = HashMap<((u16, u16), (u16, u16)), EdgeTraversal>; // (from, to) node -> traversal
+
+/// When an edge was first taken during execution and how often
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EdgeTraversal {
+    pub first_visit: usize, // sequence number of the block visit the edge first led to, from 1
+    pub count: usize,
+}
+
+impl EdgeTraversal {
+    /// Edge label, e.g. `#12 ×40` for an edge first taken into the 12th block visit and taken 40 times
+    pub fn label(&self) -> String {
+        match self.count {
+            1 => format!("#{}", self.first_visit),
+            count => format!("#{} ×{}", self.first_visit, count),
+        }
+    }
+}
+
+/// Transition between two blocks that happened during execution
+#[derive(Clone, Copy, Debug)]
+pub struct TakenEdge {
+    pub from: (u16, u16),
+    pub to: (u16, u16),
+    pub edge_type: Option<Edges>, // None when static analysis missed the edge (unresolved jump)
+    pub traversal: Option<EdgeTraversal>, // None when no block paths were recorded
+}
+
+/// Heat-map fill color of a block that spent `gas` out of the `max_gas` of the hottest block
+///
+/// Buckets are on a log scale, gas use spans orders of magnitude (a few JUMPs vs cold SSTOREs).
+pub fn heat_color(gas: u64, max_gas: u64) -> &'static str {
+    const HEAT: [&str; 5] = ["#7aa2f7", "#9ece6a", "#e0af68", "#ff9e64", "#f7768e"]; // cold to hot
+    if gas == 0 || max_gas == 0 {
+        return HEAT[0];
+    }
+    let ratio = (gas as f64).ln_1p() / (max_gas as f64).ln_1p();
+    HEAT[((ratio * HEAT.len() as f64) as usize).min(HEAT.len() - 1)]
+}
+
+/// Traversal of every edge taken along ordered block paths, visits numbered across all paths
+pub fn edge_traversals<'a>(
+    paths: impl IntoIterator<Item = &'a Vec<(u16, u16)>>,
+) -> EdgeTraversals {
+    let mut traversals = EdgeTraversals::new();
+    let mut visit = 0;
+    for path in paths {
+        visit += 1; // the path's first block is entered from another frame, not through an edge
+        for (from, to) in path.iter().tuple_windows() {
+            visit += 1;
+            traversals
+                .entry((*from, *to))
+                .and_modify(|traversal| traversal.count += 1)
+                .or_insert(EdgeTraversal { first_visit: visit, count: 1 });
+        }
+    }
+    traversals
+}
 
 pub struct CFGRunner<'a> {
     pub cfg_dag: CFGDag,
@@ -58,6 +125,12 @@ pub struct CFGRunner<'a> {
     pub bytecode: Vec<u8>, // Stores the entire contract bytecode
     pub map_to_instructionblock: &'a BTreeMap<(u16, u16), InstructionBlock>, // This mapping maps (start_pc, end_pc) to instruction blocks
     pub executed_pcs: Option<HashSet<u16>>, // New: records executed PCs
+    pub eof: Option<EofContainer>, // Set for EOF code, whose blocks are split into code sections
+    pub dispatcher: Option<Dispatcher>, // Selector dispatcher of legacy code, when one was recognized
+    pub annotations: BTreeMap<u16, Vec<String>>, // Notes shown under the instruction at a pc, e.g. decoded calls and events
+    pub edge_traversals: EdgeTraversals, // Order and count of the edges taken during execution
+    pub gas_by_pc: HashMap<u16, u64>, // Gas spent at each pc during execution, forwarded gas excluded
+    pub gas_heatmap: bool, // Fill executed blocks by the gas they spent instead of by their operations
 } // Defines the CFGRunner struct, which contains the DAG of the control flow graph, the last node, jumpi edge, bytecode, and mapping to instruction blocks.
 
 impl<'main> CFGRunner<'main> {
@@ -82,6 +155,12 @@ impl<'main> CFGRunner<'main> {
             bytecode,
             map_to_instructionblock,
             executed_pcs: None, // Initialize the new field as None
+            eof: None,
+            dispatcher: None,
+            annotations: BTreeMap::new(),
+            edge_traversals: HashMap::new(),
+            gas_by_pc: HashMap::new(),
+            gas_heatmap: false,
         } // Return a new CFGRunner instance
     }
 
@@ -138,12 +217,12 @@ impl<'main> CFGRunner<'main> {
                         .add_edge((start_pc, end_pc), next_node, Edges::ConditionFalse);
                 }
             }
-            if instruction_block.indirect_jump.is_none() && direct_push_val.is_some() {
+            if let (None, Some(direct_push_val)) = (instruction_block.indirect_jump, direct_push_val) {
                 // we know this is a direct jump
                 // Case 2: Direct Jumpi true
                 if last_op_code == 0x57 {
                     // Jumpi true
-                    let next_pc = format!("{}", direct_push_val.unwrap())
+                    let next_pc = format!("{}", direct_push_val)
                         .parse::<u16>()
                         .unwrap(); // this is so stupid but its only done once
                     let next_node = self.get_node_from_pc(next_pc);
@@ -154,7 +233,7 @@ impl<'main> CFGRunner<'main> {
                 // Case 3: Direct Jump
                 if last_op_code == 0x56 {
                     // Jump
-                    let next_pc = format!("{}", direct_push_val.unwrap())
+                    let next_pc = format!("{}", direct_push_val)
                         .parse::<u16>()
                         .unwrap(); // this is so stupid but its only done once
                     let next_node = self.get_node_from_pc(next_pc);
@@ -163,9 +242,7 @@ impl<'main> CFGRunner<'main> {
                 }
             }
 
-            if !BLOCK_ENDERS_U8.contains(&last_op_code)
-                && super::opcode(last_op_code).name != "unknown"
-            {
+            if instruction_block.falls_through() {
                 // Block ender is a basic instruction, but not exiting
                 let next_pc = end_pc + 1;
 
@@ -181,6 +258,269 @@ impl<'main> CFGRunner<'main> {
         }
     } 
 
+    /// Connect the blocks of an EOF container
+    ///
+    /// EOF has no dynamic jumps: RJUMP, RJUMPI and RJUMPV carry their targets as
+    /// immediates, CALLF and JUMPF name a code section, and RETF returns to the
+    /// instruction after every CALLF into its section (or into a section that
+    /// JUMPFs to it, since JUMPF returns to its own caller's caller).
+    pub fn form_eof_connections(&mut self) {
+        let Some(container) = self.eof.as_ref() else {
+            return;
+        };
+        let section_entries: Vec<u16> = container.sections.iter().map(|section| section.offset).collect();
+        let mut return_sites: Vec<Vec<(u16, u16)>> = vec![Vec::new(); section_entries.len()];
+        let mut tail_calls: Vec<(usize, usize)> = Vec::new(); // (from section, to section)
+        let mut edges = Vec::new();
+        let mut retf_nodes = Vec::new();
+
+        for (node, instruction_block) in self.map_to_instructionblock.iter() {
+            let Some((pc, op, immediates)) = instruction_block.ops.last() else {
+                continue;
+            };
+            let section = instruction_block.eof_section.unwrap_or_default() as usize;
+            let immediates = immediates.as_deref().unwrap_or_default();
+            let next_node = self.node_starting_at(instruction_block.end_pc + 1)
+                .filter(|next| container.section_at(next.0).map(|next_section| next_section.index as usize) == Some(section));
+
+            match *op {
+                RJUMP => {
+                    for target in eof::relative_jump_targets(*pc, *op, immediates) {
+                        edges.push((*node, self.node_starting_at(target), Edges::Jump));
+                    }
+                }
+                RJUMPI => {
+                    for target in eof::relative_jump_targets(*pc, *op, immediates) {
+                        edges.push((*node, self.node_starting_at(target), Edges::ConditionTrue));
+                    }
+                    edges.push((*node, next_node, Edges::ConditionFalse));
+                }
+                RJUMPV => {
+                    for (case, target) in eof::relative_jump_targets(*pc, *op, immediates).into_iter().enumerate() {
+                        edges.push((*node, self.node_starting_at(target), Edges::Case(case as u8)));
+                    }
+                    // An out of range index falls through
+                    edges.push((*node, next_node, Edges::ConditionFalse));
+                }
+                CALLF => {
+                    if let Some(target) = eof::function_target(immediates).filter(|target| (*target as usize) < section_entries.len()) {
+                        edges.push((*node, self.node_starting_at(section_entries[target as usize]), Edges::CallF));
+                        if let Some(next_node) = next_node {
+                            return_sites[target as usize].push(next_node);
+                        }
+                    }
+                }
+                JUMPF => {
+                    if let Some(target) = eof::function_target(immediates).filter(|target| (*target as usize) < section_entries.len()) {
+                        edges.push((*node, self.node_starting_at(section_entries[target as usize]), Edges::JumpF));
+                        tail_calls.push((section, target as usize));
+                    }
+                }
+                RETF => retf_nodes.push((*node, section)),
+                _ => {
+                    if instruction_block.falls_through() {
+                        edges.push((*node, next_node, Edges::Jump));
+                    }
+                }
+            }
+        }
+
+        // A section reached by JUMPF returns wherever the jumping section would have
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (from, to) in &tail_calls {
+                for site in return_sites[*from].clone() {
+                    if !return_sites[*to].contains(&site) {
+                        return_sites[*to].push(site);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        for (node, section) in retf_nodes {
+            for site in &return_sites[section] {
+                edges.push((node, Some(*site), Edges::RetF));
+            }
+        }
+
+        for (from, to, edge) in edges {
+            if let Some(to) = to {
+                self.cfg_dag.add_edge(from, to, edge);
+            }
+        }
+    }
+
+    /// Pc of the first instruction executed when the code is called
+    pub fn entry_pc(&self) -> u16 {
+        self.eof.as_ref().map_or(0, |container| container.entry_pc())
+    }
+
+    /// Node of the block starting exactly at `pc`, if any
+    fn node_starting_at(&self, pc: u16) -> Option<(u16, u16)> {
+        self.map_to_instructionblock
+            .range((pc, 0)..=(pc, u16::MAX))
+            .next()
+            .map(|(node, _)| *node)
+    }
+
+    pub fn set_eof_container(&mut self, container: EofContainer) {
+        self.eof = Some(container);
+    }
+
+    pub fn set_dispatcher(&mut self, dispatcher: Dispatcher) {
+        self.dispatcher = Some(dispatcher);
+    }
+
+    pub fn set_annotations(&mut self, annotations: BTreeMap<u16, Vec<String>>) {
+        self.annotations = annotations;
+    }
+
+    /// Blocks visited in order, from the pcs at which each visit started
+    ///
+    /// Pcs that do not start a block (the trace and the disassembly disagree, e.g. on
+    /// data regions) are folded into the block already being visited.
+    pub fn block_path(&self, block_entries: &[u16]) -> Vec<(u16, u16)> {
+        let mut path: Vec<(u16, u16)> = Vec::with_capacity(block_entries.len());
+        for pc in block_entries {
+            let Some((node, _)) = self
+                .map_to_instructionblock
+                .range(..=(*pc, u16::MAX))
+                .next_back()
+                .filter(|((start_pc, end_pc), _)| start_pc <= pc && pc <= end_pc)
+            else {
+                continue;
+            };
+            if node.0 != *pc && path.last() == Some(node) {
+                continue;
+            }
+            path.push(*node);
+        }
+        path
+    }
+
+    /// Whether any instruction of the block is in `pcs`
+    ///
+    /// Checking the start pc alone misses blocks entered mid-way, e.g. when only the
+    /// instructions after a call that returned into the block are covered.
+    pub fn block_ran(&self, node: (u16, u16), pcs: &HashSet<u16>) -> bool {
+        match self.map_to_instructionblock.get(&node) {
+            Some(block) => block.ops.iter().any(|(pc, _, _)| pcs.contains(pc)),
+            None => (node.0..=node.1).any(|pc| pcs.contains(&pc)),
+        }
+    }
+
+    /// Whether the block ran in any execution of the code
+    pub fn is_block_executed(&self, node: (u16, u16)) -> bool {
+        self.executed_pcs.as_ref().is_some_and(|pcs| self.block_ran(node, pcs))
+    }
+
+    /// Edges taken according to `traversals`, in the order they were first taken
+    ///
+    /// Without recorded traversals (no block paths in the trace) any CFG edge between
+    /// two blocks that ran is assumed taken, which overcounts e.g. both sides of a JUMPI
+    /// taken on different iterations.
+    pub fn taken_edges(&self, traversals: &EdgeTraversals, pcs: &HashSet<u16>) -> Vec<TakenEdge> {
+        if traversals.is_empty() {
+            return self
+                .cfg_dag
+                .all_edges()
+                .filter(|(from, to, _)| self.block_ran(*from, pcs) && self.block_ran(*to, pcs))
+                .map(|(from, to, edge_type)| TakenEdge { from, to, edge_type: Some(*edge_type), traversal: None })
+                .collect();
+        }
+        traversals
+            .iter()
+            .sorted_by_key(|(_, traversal)| traversal.first_visit)
+            .map(|((from, to), traversal)| TakenEdge {
+                from: *from,
+                to: *to,
+                edge_type: self.cfg_dag.edge_weight(*from, *to).copied(),
+                traversal: Some(*traversal),
+            })
+            .collect()
+    }
+
+    pub fn set_gas_by_pc(&mut self, gas_by_pc: HashMap<u16, u64>) {
+        self.gas_by_pc = gas_by_pc;
+    }
+
+    pub fn set_gas_heatmap(&mut self, gas_heatmap: bool) {
+        self.gas_heatmap = gas_heatmap;
+    }
+
+    /// Gas spent by the block's instructions over all executions
+    pub fn block_gas(&self, node: (u16, u16)) -> u64 {
+        match self.map_to_instructionblock.get(&node) {
+            Some(block) => block.ops.iter().filter_map(|(pc, _, _)| self.gas_by_pc.get(pc)).sum(),
+            None => 0,
+        }
+    }
+
+    /// Gas of the hottest executed block, what the heat map is scaled to
+    fn max_block_gas(&self) -> u64 {
+        self.map_to_instructionblock.keys().map(|node| self.block_gas(*node)).max().unwrap_or(0)
+    }
+
+    /// Record the ordered block paths of every execution of the code, see [`EdgeTraversal`]
+    pub fn set_block_paths(&mut self, paths: &[Vec<(u16, u16)>]) {
+        self.edge_traversals = edge_traversals(paths);
+    }
+
+    /// Node label of a block: its instructions followed by the annotations of its pcs
+    fn block_label(&self, block: &InstructionBlock) -> String {
+        let mut label = block.to_string();
+        for (_, notes) in self.annotations.range(block.start_pc..=block.end_pc) {
+            for note in notes {
+                label.push_str(note);
+                label.push('\n');
+            }
+        }
+        let gas = self.block_gas((block.start_pc, block.end_pc));
+        if gas > 0 {
+            label.push_str(&format!("gas: {}\n", gas));
+        }
+        label.replace('"', "\\\"")
+    }
+
+    /// Labeled groups of nodes drawn as clusters: the code sections of EOF code, or for
+    /// legacy code the executed blocks of each external function found by the dispatcher
+    ///
+    /// Blocks reached from several functions (shared helpers) are left out of every cluster.
+    fn node_clusters(&self) -> Vec<(String, Vec<(u16, u16)>)> {
+        let Some(container) = self.eof.as_ref() else {
+            let Some(dispatcher) = self.dispatcher.as_ref() else {
+                return Vec::new();
+            };
+            let mut clusters: BTreeMap<FunctionId, Vec<(u16, u16)>> = BTreeMap::new();
+            for (node, functions) in dispatcher.block_functions(self, self.executed_pcs.as_ref()) {
+                if let (1, Some(function)) = (functions.len(), functions.first()) {
+                    clusters.entry(*function).or_default().push(node);
+                }
+            }
+            return clusters
+                .into_iter()
+                .map(|(function, nodes)| (function, nodes.into_iter().sorted().collect()))
+                .map(|(function, nodes)| match function {
+                    FunctionId::Selector(_) => (format!("function {}", function), nodes),
+                    _ => (function.to_string(), nodes),
+                })
+                .collect();
+        };
+        container
+            .sections
+            .iter()
+            .map(|section| {
+                let nodes = self
+                    .cfg_dag
+                    .nodes()
+                    .filter(|node| section.offset <= node.0 && node.0 < section.end())
+                    .collect();
+                (section.label(), nodes)
+            })
+            .collect()
+    }
+
     pub fn remove_unreachable_instruction_blocks(&mut self) {
         // We need to iterate over the nodes in self.map_to_instructionblock, and remove any that have no incoming/outgoing edges and do not begin with a jumpdest
         let mut to_remove: Vec<(u16, u16)> = Vec::new();
@@ -192,7 +532,9 @@ impl<'main> CFGRunner<'main> {
                 .edges_directed((start_pc, end_pc), Direction::Incoming);
             if incoming_edges.count() == 0 {
                 // This node has no incoming edges, so it is unreachable
-                if instruction_block.ops[0].1 != 0x5b && start_pc != 0 {
+                // EOF code sections are only entered through CALLF and JUMPF
+                let is_jumpdest = instruction_block.eof_section.is_none() && instruction_block.ops[0].1 == 0x5b;
+                if !is_jumpdest && start_pc != self.entry_pc() {
                     // This node does not begin with a jumpdest, so it is unreachable
                     to_remove.push((start_pc, end_pc));
                 }
@@ -210,8 +552,7 @@ impl<'main> CFGRunner<'main> {
             if val
                 .ops
                 .iter()
-                .map(|(instruction_pc, _op, _push_val)| *instruction_pc == pc)
-                .any(|x| x)
+                .any(|(instruction_pc, _op, _push_val)| *instruction_pc == pc)
             {
                 return (val.start_pc, val.end_pc);
             }
@@ -257,6 +598,7 @@ impl<'main> CFGRunner<'main> {
     bgcolor="#1a1b26";"##; 
         dot_str.push(raw_start_str.to_string()); 
 
+        let max_gas = self.max_block_gas();
         let nodes_and_edges_str = format!(
             "{:?}",
             Dot::with_attr_getters(
@@ -268,19 +610,23 @@ impl<'main> CFGRunner<'main> {
                 ],
                 &|_graph, edge_ref| {
                     let (from, to, edge_type) = edge_ref;
-                    // Check if both from and to nodes are highlighted
-                    let highlight = if let Some(ref pcs) = self.executed_pcs {
-                        let from_block = self.map_to_instructionblock.get(&from).unwrap();
-                        let to_block = self.map_to_instructionblock.get(&to).unwrap();
-                        pcs.contains(&from_block.start_pc) && pcs.contains(&to_block.start_pc)
+                    // Highlight edges taken during execution
+                    let highlight = if self.edge_traversals.is_empty() {
+                        self.is_block_executed(from) && self.is_block_executed(to)
                     } else {
-                        false
+                        self.edge_traversals.contains_key(&(from, to))
                     };
                     if highlight {
-                        // Highlight edge (green)
+                        // Highlight edge (green), with when and how often it was taken
+                        let traversal = self
+                            .edge_traversals
+                            .get(&(from, to))
+                            .map(|traversal| format!(" {}", traversal.label()))
+                            .unwrap_or_default();
                         format!(
-                            "label = \"{:?}\" color = \"{}\" penwidth=3",
+                            "label = \"{:?}{}\" color = \"{}\" penwidth=3",
                             edge_type,
+                            traversal,
                             TOKYO_NIGHT_COLORS.get("green").unwrap()
                         )
                     } else {
@@ -302,25 +648,36 @@ impl<'main> CFGRunner<'main> {
                                 edge_type,
                                 TOKYO_NIGHT_COLORS.get("yellow").unwrap()
                             ),
+                            Edges::Case(_) => format!(
+                                "label = \"{:?}\" color = \"{}\"",
+                                edge_type,
+                                TOKYO_NIGHT_COLORS.get("cyan").unwrap()
+                            ),
+                            Edges::CallF | Edges::RetF | Edges::JumpF => format!(
+                                "label = \"{:?}\" color = \"{}\", style=\"dashed\"",
+                                edge_type,
+                                TOKYO_NIGHT_COLORS.get("purple").unwrap()
+                            ),
                         }
                     }
                 },
                 &|_graph, (_id, node_ref)| {
                     let mut node_str = String::new();
                     let instruction_block = self.map_to_instructionblock.get(node_ref).unwrap();
+                    let label = self.block_label(instruction_block);
                     let color = instruction_block.node_color();
                     match color {
                         Some(color) => {
                             node_str.push_str(&format!(
-                                "label = \"{instruction_block}\" color = \"{color}\""
+                                "label = \"{label}\" color = \"{color}\""
                             ));
                         }
                         None => {
-                            node_str.push_str(&format!("label = \"{instruction_block}\""));
+                            node_str.push_str(&format!("label = \"{label}\""));
                         }
                     }
                     // if the node has no incoming edges, fill the node with deepred
-                    if instruction_block.start_pc == 0 {
+                    if instruction_block.start_pc == self.entry_pc() {
                         node_str.push_str(" shape = invhouse");
                     } else if self.cfg_dag.neighbors_directed(*node_ref, Incoming).count() == 0 {
                         node_str.push_str(&format!(
@@ -329,19 +686,31 @@ impl<'main> CFGRunner<'main> {
                         ));
                     }
                     // New code: If the node has been executed, add highlight color
-                    if let Some(ref pcs) = self.executed_pcs {
-                        if pcs.contains(&instruction_block.start_pc) {
-                            node_str.push_str(&format!(
-                                " fillcolor = \"{}\" fontcolor = \"#1a1b26\"",
-                                TOKYO_NIGHT_COLORS.get("green").unwrap()
-                            ));
-                        }
+                    if self.is_block_executed(*node_ref) {
+                        let fillcolor = match self.gas_heatmap {
+                            true => heat_color(self.block_gas(*node_ref), max_gas),
+                            false => TOKYO_NIGHT_COLORS.get("green").unwrap(),
+                        };
+                        node_str.push_str(&format!(" fillcolor = \"{}\" fontcolor = \"#1a1b26\"", fillcolor));
                     }
                     node_str
                 }
             )
         );
         dot_str.push(nodes_and_edges_str);
+        // Group the blocks of each EOF code section or function, nodes are numbered in graph order
+        for (cluster_label, nodes) in self.node_clusters() {
+            let node_ids = self
+                .cfg_dag
+                .nodes()
+                .enumerate()
+                .filter(|(_, node)| nodes.contains(node))
+                .map(|(id, _)| format!("{id};"))
+                .join(" ");
+            dot_str.push(format!(
+                "    subgraph \"cluster_{cluster_label}\" {{ label = \"{cluster_label}\" fontcolor = \"#c0caf5\" color = \"#565f89\" style = \"dashed, rounded\"; {node_ids} }}"
+            ));
+        }
         let raw_end_str = r#"}"#;
         dot_str.push(raw_end_str.to_string());
         dot_str.join("\n")
@@ -357,15 +726,16 @@ impl<'main> CFGRunner<'main> {
         dot_str.push(raw_start_str.to_string());
 
         // Only output highlighted nodes
+        let max_gas = self.max_block_gas();
         if let Some(ref pcs) = self.executed_pcs {
             for ((start_pc, end_pc), block) in self.map_to_instructionblock.iter() {
-                if pcs.contains(start_pc) {
-                    let label = format!("{}", block);
+                if self.block_ran((*start_pc, *end_pc), pcs) {
+                    let label = self.block_label(block);
                     // Color priority: SSTORE > ADD/SUB > others
                     let mut has_sstore = false;
                     let mut has_add_or_sub = false;
                     for (_pc, op, _push) in &block.ops {
-                        let opname = super::opcode(*op).name.to_ascii_lowercase();
+                        let opname = block.op_name(*op).to_ascii_lowercase();
                         if opname == "sstore" {
                             has_sstore = true;
                             break;
@@ -374,7 +744,9 @@ impl<'main> CFGRunner<'main> {
                             has_add_or_sub = true;
                         }
                     }
-                    let fillcolor = if has_sstore {
+                    let fillcolor = if self.gas_heatmap {
+                        heat_color(self.block_gas((*start_pc, *end_pc)), max_gas)
+                    } else if has_sstore {
                         "#f7768e" // Pink for SSTORE
                     } else if has_add_or_sub {
                         "#ff9e64" // Orange for ADD/SUB
@@ -382,10 +754,10 @@ impl<'main> CFGRunner<'main> {
                         "#9ece6a" // Green for others
                     };
                     let mut attrs = vec![
-                        format!("label = \"{}\"", label.replace("\"", "\\\"")),
+                        format!("label = \"{}\"", label),
                         format!("fillcolor = \"{}\" fontcolor = \"#1a1b26\"", fillcolor)
                     ];
-                    if *start_pc == 0 {
+                    if *start_pc == self.entry_pc() {
                         attrs.push("shape = invhouse".to_string());
                     }
                     dot_str.push(format!(
@@ -396,15 +768,56 @@ impl<'main> CFGRunner<'main> {
                 }
             }
 
-            // Only output highlighted edges (from and to both highlighted)
-            for (from, to, _edge_type) in self.cfg_dag.all_edges() {
-                if pcs.contains(&from.0) && pcs.contains(&to.0) {
+            // Group the executed blocks of each EOF code section or function
+            for (cluster_label, nodes) in self.node_clusters() {
+                let node_ids = nodes
+                    .iter()
+                    .filter(|node| self.block_ran(**node, pcs))
+                    .map(|(start_pc, end_pc)| format!("\"{start_pc}_{end_pc}\";"))
+                    .join(" ");
+                if !node_ids.is_empty() {
                     dot_str.push(format!(
-                        "\"{}_{}\" -> \"{}_{}\";",
-                        from.0, from.1, to.0, to.1
+                        "subgraph \"cluster_{cluster_label}\" {{ label = \"{cluster_label}\"; fontcolor = \"#c0caf5\"; color = \"#565f89\"; style = \"dashed, rounded\"; {node_ids} }}"
                     ));
                 }
             }
+
+            // Only output edges taken during execution
+            for TakenEdge { from, to, edge_type, traversal } in self.taken_edges(&self.edge_traversals, pcs) {
+                let mut labels = Vec::new();
+                let mut attrs = Vec::new();
+                match edge_type {
+                    Some(Edges::ConditionTrue) => labels.push("True".to_string()),
+                    Some(Edges::ConditionFalse) => {
+                        labels.push("False".to_string());
+                        attrs.push("color = \"#f7768e\"".to_string());
+                    }
+                    Some(edge_type @ (Edges::CallF | Edges::RetF | Edges::JumpF)) => {
+                        labels.push(format!("{:?}", edge_type));
+                        attrs.push("style = \"dashed\"".to_string());
+                    }
+                    // Taken, but the static analysis did not resolve the jump
+                    None => {
+                        labels.push("traced".to_string());
+                        attrs.push("color = \"#e0af68\" style = \"dotted\"".to_string());
+                    }
+                    _ => {}
+                }
+                if let Some(traversal) = traversal {
+                    labels.push(traversal.label());
+                }
+                if !labels.is_empty() {
+                    attrs.insert(0, format!("label = \"{}\"", labels.join(" ")));
+                }
+                let attrs = match attrs.is_empty() {
+                    true => String::new(),
+                    false => format!(" [{}]", attrs.join(" ")),
+                };
+                dot_str.push(format!(
+                    "\"{}_{}\" -> \"{}_{}\"{};",
+                    from.0, from.1, to.0, to.1, attrs
+                ));
+            }
         }
 
         dot_str.push("}".to_string());
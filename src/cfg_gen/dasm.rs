@@ -1,272 +1,13 @@
 use crate::cfg_gen::*;
 use fnv::FnvBuildHasher;
 use itertools::Itertools;
+use revm::primitives::SpecId;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     fmt::Display,
     hash::Hash,
 };
 
-// TODO: Implement enum
-pub const OPCODE_JUMPMAP: [Option<&'static str>; 256] = [
-    /* 0x00 */ Some("STOP"),
-    /* 0x01 */ Some("ADD"),
-    /* 0x02 */ Some("MUL"),
-    /* 0x03 */ Some("SUB"),
-    /* 0x04 */ Some("DIV"),
-    /* 0x05 */ Some("SDIV"),
-    /* 0x06 */ Some("MOD"),
-    /* 0x07 */ Some("SMOD"),
-    /* 0x08 */ Some("ADDMOD"),
-    /* 0x09 */ Some("MULMOD"),
-    /* 0x0a */ Some("EXP"),
-    /* 0x0b */ Some("SIGNEXTEND"),
-    /* 0x0c */ None,
-    /* 0x0d */ None,
-    /* 0x0e */ None,
-    /* 0x0f */ None,
-    /* 0x10 */ Some("LT"),
-    /* 0x11 */ Some("GT"),
-    /* 0x12 */ Some("SLT"),
-    /* 0x13 */ Some("SGT"),
-    /* 0x14 */ Some("EQ"),
-    /* 0x15 */ Some("ISZERO"),
-    /* 0x16 */ Some("AND"),
-    /* 0x17 */ Some("OR"),
-    /* 0x18 */ Some("XOR"),
-    /* 0x19 */ Some("NOT"),
-    /* 0x1a */ Some("BYTE"),
-    /* 0x1b */ Some("SHL"),
-    /* 0x1c */ Some("SHR"),
-    /* 0x1d */ Some("SAR"),
-    /* 0x1e */ None,
-    /* 0x1f */ None,
-    /* 0x20 */ Some("SHA3"),
-    /* 0x21 */ None,
-    /* 0x22 */ None,
-    /* 0x23 */ None,
-    /* 0x24 */ None,
-    /* 0x25 */ None,
-    /* 0x26 */ None,
-    /* 0x27 */ None,
-    /* 0x28 */ None,
-    /* 0x29 */ None,
-    /* 0x2a */ None,
-    /* 0x2b */ None,
-    /* 0x2c */ None,
-    /* 0x2d */ None,
-    /* 0x2e */ None,
-    /* 0x2f */ None,
-    /* 0x30 */ Some("ADDRESS"),
-    /* 0x31 */ Some("BALANCE"),
-    /* 0x32 */ Some("ORIGIN"),
-    /* 0x33 */ Some("CALLER"),
-    /* 0x34 */ Some("CALLVALUE"),
-    /* 0x35 */ Some("CALLDATALOAD"),
-    /* 0x36 */ Some("CALLDATASIZE"),
-    /* 0x37 */ Some("CALLDATACOPY"),
-    /* 0x38 */ Some("CODESIZE"),
-    /* 0x39 */ Some("CODECOPY"),
-    /* 0x3a */ Some("GASPRICE"),
-    /* 0x3b */ Some("EXTCODESIZE"),
-    /* 0x3c */ Some("EXTCODECOPY"),
-    /* 0x3d */ Some("RETURNDATASIZE"),
-    /* 0x3e */ Some("RETURNDATACOPY"),
-    /* 0x3f */ Some("EXTCODEHASH"),
-    /* 0x40 */ Some("BLOCKHASH"),
-    /* 0x41 */ Some("COINBASE"),
-    /* 0x42 */ Some("TIMESTAMP"),
-    /* 0x43 */ Some("NUMBER"),
-    /* 0x44 */ Some("DIFFICULTY"),
-    /* 0x45 */ Some("GASLIMIT"),
-    /* 0x46 */ Some("CHAINID"),
-    /* 0x47 */ Some("SELFBALANCE"),
-    /* 0x48 */ Some("BASEFEE"),
-    /* 0x49 */ None,
-    /* 0x4a */ None,
-    /* 0x4b */ None,
-    /* 0x4c */ None,
-    /* 0x4d */ None,
-    /* 0x4e */ None,
-    /* 0x4f */ None,
-    /* 0x50 */ Some("POP"),
-    /* 0x51 */ Some("MLOAD"),
-    /* 0x52 */ Some("MSTORE"),
-    /* 0x53 */ Some("MSTORE8"),
-    /* 0x54 */ Some("SLOAD"),
-    /* 0x55 */ Some("SSTORE"),
-    /* 0x56 */ Some("JUMP"),
-    /* 0x57 */ Some("JUMPI"),
-    /* 0x58 */ Some("PC"),
-    /* 0x59 */ Some("MSIZE"),
-    /* 0x5a */ Some("GAS"),
-    /* 0x5b */ Some("JUMPDEST"),
-    /* 0x5c */ Some("TLOAD"),
-    /* 0x55 */ Some("TSTORE"),
-    /* 0x5e */ Some("MCOPY"),
-    /* 0x5f */ Some("PUSH0"),
-    /* 0x60 */ Some("PUSH1"),
-    /* 0x61 */ Some("PUSH2"),
-    /* 0x62 */ Some("PUSH3"),
-    /* 0x63 */ Some("PUSH4"),
-    /* 0x64 */ Some("PUSH5"),
-    /* 0x65 */ Some("PUSH6"),
-    /* 0x66 */ Some("PUSH7"),
-    /* 0x67 */ Some("PUSH8"),
-    /* 0x68 */ Some("PUSH9"),
-    /* 0x69 */ Some("PUSH10"),
-    /* 0x6a */ Some("PUSH11"),
-    /* 0x6b */ Some("PUSH12"),
-    /* 0x6c */ Some("PUSH13"),
-    /* 0x6d */ Some("PUSH14"),
-    /* 0x6e */ Some("PUSH15"),
-    /* 0x6f */ Some("PUSH16"),
-    /* 0x70 */ Some("PUSH17"),
-    /* 0x71 */ Some("PUSH18"),
-    /* 0x72 */ Some("PUSH19"),
-    /* 0x73 */ Some("PUSH20"),
-    /* 0x74 */ Some("PUSH21"),
-    /* 0x75 */ Some("PUSH22"),
-    /* 0x76 */ Some("PUSH23"),
-    /* 0x77 */ Some("PUSH24"),
-    /* 0x78 */ Some("PUSH25"),
-    /* 0x79 */ Some("PUSH26"),
-    /* 0x7a */ Some("PUSH27"),
-    /* 0x7b */ Some("PUSH28"),
-    /* 0x7c */ Some("PUSH29"),
-    /* 0x7d */ Some("PUSH30"),
-    /* 0x7e */ Some("PUSH31"),
-    /* 0x7f */ Some("PUSH32"),
-    /* 0x80 */ Some("DUP1"),
-    /* 0x81 */ Some("DUP2"),
-    /* 0x82 */ Some("DUP3"),
-    /* 0x83 */ Some("DUP4"),
-    /* 0x84 */ Some("DUP5"),
-    /* 0x85 */ Some("DUP6"),
-    /* 0x86 */ Some("DUP7"),
-    /* 0x87 */ Some("DUP8"),
-    /* 0x88 */ Some("DUP9"),
-    /* 0x89 */ Some("DUP10"),
-    /* 0x8a */ Some("DUP11"),
-    /* 0x8b */ Some("DUP12"),
-    /* 0x8c */ Some("DUP13"),
-    /* 0x8d */ Some("DUP14"),
-    /* 0x8e */ Some("DUP15"),
-    /* 0x8f */ Some("DUP16"),
-    /* 0x90 */ Some("SWAP1"),
-    /* 0x91 */ Some("SWAP2"),
-    /* 0x92 */ Some("SWAP3"),
-    /* 0x93 */ Some("SWAP4"),
-    /* 0x94 */ Some("SWAP5"),
-    /* 0x95 */ Some("SWAP6"),
-    /* 0x96 */ Some("SWAP7"),
-    /* 0x97 */ Some("SWAP8"),
-    /* 0x98 */ Some("SWAP9"),
-    /* 0x99 */ Some("SWAP10"),
-    /* 0x9a */ Some("SWAP11"),
-    /* 0x9b */ Some("SWAP12"),
-    /* 0x9c */ Some("SWAP13"),
-    /* 0x9d */ Some("SWAP14"),
-    /* 0x9e */ Some("SWAP15"),
-    /* 0x9f */ Some("SWAP16"),
-    /* 0xa0 */ Some("LOG0"),
-    /* 0xa1 */ Some("LOG1"),
-    /* 0xa2 */ Some("LOG2"),
-    /* 0xa3 */ Some("LOG3"),
-    /* 0xa4 */ Some("LOG4"),
-    /* 0xa5 */ None,
-    /* 0xa6 */ None,
-    /* 0xa7 */ None,
-    /* 0xa8 */ None,
-    /* 0xa9 */ None,
-    /* 0xaa */ None,
-    /* 0xab */ None,
-    /* 0xac */ None,
-    /* 0xad */ None,
-    /* 0xae */ None,
-    /* 0xaf */ None,
-    /* 0xb0 */ None,
-    /* 0xb1 */ None,
-    /* 0xb2 */ None,
-    /* 0xb3 */ None,
-    /* 0xb4 */ None,
-    /* 0xb5 */ None,
-    /* 0xb6 */ None,
-    /* 0xb7 */ None,
-    /* 0xb8 */ None,
-    /* 0xb9 */ None,
-    /* 0xba */ None,
-    /* 0xbb */ None,
-    /* 0xbc */ None,
-    /* 0xbd */ None,
-    /* 0xbe */ None,
-    /* 0xbf */ None,
-    /* 0xc0 */ None,
-    /* 0xc1 */ None,
-    /* 0xc2 */ None,
-    /* 0xc3 */ None,
-    /* 0xc4 */ None,
-    /* 0xc5 */ None,
-    /* 0xc6 */ None,
-    /* 0xc7 */ None,
-    /* 0xc8 */ None,
-    /* 0xc9 */ None,
-    /* 0xca */ None,
-    /* 0xcb */ None,
-    /* 0xcc */ None,
-    /* 0xcd */ None,
-    /* 0xce */ None,
-    /* 0xcf */ None,
-    /* 0xd0 */ None,
-    /* 0xd1 */ None,
-    /* 0xd2 */ None,
-    /* 0xd3 */ None,
-    /* 0xd4 */ None,
-    /* 0xd5 */ None,
-    /* 0xd6 */ None,
-    /* 0xd7 */ None,
-    /* 0xd8 */ None,
-    /* 0xd9 */ None,
-    /* 0xda */ None,
-    /* 0xdb */ None,
-    /* 0xdc */ None,
-    /* 0xdd */ None,
-    /* 0xde */ None,
-    /* 0xdf */ None,
-    /* 0xe0 */ None,
-    /* 0xe1 */ None,
-    /* 0xe2 */ None,
-    /* 0xe3 */ None,
-    /* 0xe4 */ None,
-    /* 0xe5 */ None,
-    /* 0xe6 */ None,
-    /* 0xe7 */ None,
-    /* 0xe8 */ None,
-    /* 0xe9 */ None,
-    /* 0xea */ None,
-    /* 0xeb */ None,
-    /* 0xec */ None,
-    /* 0xed */ None,
-    /* 0xee */ None,
-    /* 0xef */ None,
-    /* 0xf0 */ Some("CREATE"),
-    /* 0xf1 */ Some("CALL"),
-    /* 0xf2 */ Some("CALLCODE"),
-    /* 0xf3 */ Some("RETURN"),
-    /* 0xf4 */ Some("DELEGATECALL"),
-    /* 0xf5 */ Some("CREATE2"),
-    /* 0xf6 */ None,
-    /* 0xf7 */ None,
-    /* 0xf8 */ None,
-    /* 0xf9 */ None,
-    /* 0xfa */ Some("STATICCALL"),
-    /* 0xfb */ None,
-    /* 0xfc */ None,
-    /* 0xfd */ Some("REVERT"),
-    /* 0xfe */ Some("INVALID"),
-    /* 0xff */ Some("SELFDESTRUCT"),
-];
-
 #[derive(Clone, Default)]
 pub struct InstructionBlock {
     pub start_pc: u16,
@@ -276,6 +17,8 @@ pub struct InstructionBlock {
     pub indirect_jump: Option<u16>,
     pub push_vals: Vec<(Vec<u8>, Option<BTreeSet<u16>>)>,
     pub stack_info: StackInfo,
+    pub spec_id: SpecId, // hardfork the code was disassembled for
+    pub eof_section: Option<u16>, // code section of an EOF container, None for legacy code
 }
 
 #[derive(Clone, Default, Eq, PartialEq)]
@@ -309,7 +52,7 @@ impl Display for StackInfo {
                         "\t{}->{}:{}:{}\n",
                         entry_pos,
                         pc,
-                        OPCODE_JUMPMAP[*op as usize].unwrap_or("INVALID"),
+                        opcode_name(*op),
                         pos
                     ));
                 }
@@ -386,7 +129,7 @@ impl Display for StackElement {
                 f,
                 "{}:{}:{}",
                 pc,
-                OPCODE_JUMPMAP[*op as usize].unwrap_or("INVALID"),
+                opcode_name(*op),
                 pos
             ),
         }
@@ -401,7 +144,7 @@ impl Display for InstructionBlock {
 
         // start the string by doing a typical "pc14: PUSH1 0x16"
         for (pc, op, push_val) in &self.ops {
-            let op_str = OPCODE_JUMPMAP[*op as usize].unwrap_or("INVALID");
+            let op_str = self.op_name(*op);
             let formatted_pc = format_pc(*pc);
             match push_val {
                 Some(val) => {
@@ -427,7 +170,7 @@ impl Display for InstructionBlock {
 }
 
 impl InstructionBlock {
-    pub fn new(start_pc: u16) -> Self {
+    pub fn new(start_pc: u16, spec_id: SpecId) -> Self {
         Self {
             start_pc,
             end_pc: 0,
@@ -435,9 +178,32 @@ impl InstructionBlock {
             indirect_jump: None,
             push_vals: Vec::new(),
             stack_info: StackInfo::default(),
+            spec_id,
+            eof_section: None,
+        }
+    }
+
+    /// Metadata of an opcode of this block, `None` when it is not a valid instruction here
+    pub fn op_info(&self, op: u8) -> Option<Opcode> {
+        match self.eof_section {
+            Some(_) => eof_opcode(op),
+            None => opcode_at(op, self.spec_id),
         }
     }
 
+    /// Name of an opcode of this block, `INVALID` when the hardfork does not define it
+    pub fn op_name(&self, op: u8) -> &'static str {
+        self.op_info(op).map_or("INVALID", |info| info.name)
+    }
+
+    /// Whether control continues with the instruction after the block's last one
+    pub fn falls_through(&self) -> bool {
+        self.ops
+            .last()
+            .and_then(|(_pc, op, _push_val)| self.op_info(*op))
+            .is_some_and(|info| !info.ends_block)
+    }
+
     pub fn add_instruction(&mut self, pc: u16, op: u8, push_val: Option<Vec<u8>>) {
         self.ops.push((pc, op, push_val.clone()));
         if let Some(push_val) = push_val {
@@ -473,12 +239,12 @@ impl InstructionBlock {
     ) -> InstructionBlock {
         self.end_pc = end_pc;
         blocks.push(self.clone());
-        InstructionBlock::new(end_pc + 1)
+        InstructionBlock::new(end_pc + 1, self.spec_id)
     }
 
     pub fn node_color(&self) -> Option<String> {
         for (_pc, op, _push_val) in &self.ops {
-            let op_str = OPCODE_JUMPMAP[*op as usize].unwrap_or("INVALID");
+            let op_str = self.op_name(*op);
             if ["REVERT", "INVALID"].contains(&op_str) {
                 return Some("red".to_string());
             } else if ["RETURN", "STOP"].contains(&op_str) {
@@ -526,7 +292,10 @@ impl InstructionBlock {
         for (pc, op, _push_val) in &self.ops {
             let pc = *pc;
             let op = *op;
-            let opcode_info = opcode(op);
+            // Opcodes the hardfork does not define halt, nothing after them runs
+            let Some(opcode_info) = self.op_info(op) else {
+                continue;
+            };
             let op_inputs = opcode_info.inputs as u8;
             let op_outputs = opcode_info.outputs as u8;
 
@@ -1472,7 +1241,7 @@ impl InstructionBlock {
 
         let vec_popped = stack.drain(0..n as usize).collect::<Vec<_>>();
         // for each element used in the op, update map_stack_entry_pos_to_stack_usage_pos
-        for (_i, elem) in vec_popped.iter().enumerate() {
+        for elem in vec_popped.iter() {
             // check if element is a StackElement::Entry enum
             if let StackElement::Entry(_entry) = &elem {
                 // if so, update the map of {entry: (pc (op, pos_in_op))}
@@ -1546,48 +1315,52 @@ impl InstructionBlock {
     }
 }
 
-pub fn disassemble(bytecode: &[u8]) -> Vec<InstructionBlock> {
+/// Split legacy bytecode into basic blocks, decoding it as the given hardfork does
+///
+/// Opcodes introduced after `spec_id` (PUSH0 before Shanghai, TLOAD before Cancun, ...)
+/// are invalid instructions that end their block.
+pub fn disassemble(bytecode: &[u8], spec_id: SpecId) -> Vec<InstructionBlock> {
     let mut pc: u16 = 0;
     let mut blocks: Vec<InstructionBlock> = Vec::new();
     // Iterate over the bytecode, disassembling each instruction.
-    let mut block = InstructionBlock::new(0);
+    let mut block = InstructionBlock::new(0, spec_id);
     let mut push_flag: i32 = 0;
     // TODO: Implement iterator
     while (pc as usize) < bytecode.len() {
         let op = bytecode[pc as usize];
-        let op_str = OPCODE_JUMPMAP[op as usize];
-        match op_str {
-            Some(name) => {
-                if name.contains("PUSH") {
-                    let byte_count_to_push: u16 = name.replace("PUSH", "").parse().unwrap();
-                    let pushed_bytes = bytecode
-                        .get(pc as usize + 1..pc as usize + 1 + byte_count_to_push as usize)
-                        .unwrap_or(&[0x45])
-                        .to_vec();
-                    // OoB, this can actually happen in the metadata often but is useless
-                    // let pushed_bytes = 0x45; // what actually happens in the evm is the remaining OoB bytes are treated as zeros and appended
-                    block.add_instruction(pc, op, Some(pushed_bytes));
-                    pc += byte_count_to_push;
-                    push_flag = 2;
-                } else if name.contains("JUMPDEST") {
-                    if !block.ops.is_empty() {
-                        // this is only used if the metadata doesnt end with a block ender
-                        block.end_block(pc - 1, &mut blocks); // we are starting a new block, so end the old one with the previous pc
-                    }
-                    block = InstructionBlock::new(pc);
-                    block.add_instruction(pc, op, None);
-                } else if name.contains("JUMP") {
-                    block.add_instruction(pc, op, None);
-                    if push_flag != 1 {
-                        block.add_indirect_jump(pc);
-                    }
-                    block = block.end_block(pc, &mut blocks);
-                } else if BLOCK_ENDERS_U8.contains(&op) {
-                    block.add_instruction(pc, op, None);
-                    block = block.end_block(pc, &mut blocks);
-                } else {
-                    block.add_instruction(pc, op, None);
+        match opcode_at(op, spec_id) {
+            Some(_) if (PUSH0..=PUSH32).contains(&op) => {
+                let byte_count_to_push = (op - PUSH0) as u16;
+                let immediate_start = (pc as usize + 1).min(bytecode.len());
+                let immediate_end = (pc as usize + 1 + byte_count_to_push as usize).min(bytecode.len());
+                // A push truncated by the end of the code reads the missing bytes as zeros, as the EVM does
+                let mut pushed_bytes = bytecode[immediate_start..immediate_end].to_vec();
+                pushed_bytes.resize(byte_count_to_push as usize, 0);
+                block.add_instruction(pc, op, Some(pushed_bytes));
+                pc += byte_count_to_push;
+                push_flag = 2;
+            }
+            Some(_) if op == JUMPDEST => {
+                if !block.ops.is_empty() {
+                    // this is only used if the metadata doesnt end with a block ender
+                    block.end_block(pc - 1, &mut blocks); // we are starting a new block, so end the old one with the previous pc
+                }
+                block = InstructionBlock::new(pc, spec_id);
+                block.add_instruction(pc, op, None);
+            }
+            Some(_) if op == JUMP || op == JUMPI => {
+                block.add_instruction(pc, op, None);
+                if push_flag != 1 {
+                    block.add_indirect_jump(pc);
                 }
+                block = block.end_block(pc, &mut blocks);
+            }
+            Some(info) if info.ends_block => {
+                block.add_instruction(pc, op, None);
+                block = block.end_block(pc, &mut blocks);
+            }
+            Some(_) => {
+                block.add_instruction(pc, op, None);
             }
             None => {
                 //invalid
@@ -0,0 +1,330 @@
+use crate::cfg_gen::cfg_graph::{CFGRunner, Edges};
+use petgraph::Direction;
+use revm::interpreter::opcode::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+
+type Node = (u16, u16);
+
+/// External function a block belongs to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FunctionId {
+    Dispatcher,       // selector matching that runs before any function
+    Selector([u8; 4]),
+    Fallback,         // fallback or receive, entered when no selector matches
+}
+
+impl Display for FunctionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FunctionId::Dispatcher => write!(f, "dispatcher"),
+            FunctionId::Selector(selector) => write!(f, "0x{}", hex::encode(selector)),
+            FunctionId::Fallback => write!(f, "fallback"),
+        }
+    }
+}
+
+/// Function dispatcher recovered from a contract's CFG
+#[derive(Clone, Debug, Default)]
+pub struct Dispatcher {
+    pub entries: BTreeMap<FunctionId, Node>, // entry block of every external function
+    pub blocks: HashSet<Node>,               // blocks of the dispatcher itself
+}
+
+/// Comparison of the selector against a constant, the last instructions of a dispatcher block
+struct SelectorCompare {
+    constant: [u8; 4],
+    op: u8, // EQ, XOR (Vyper, jumps on mismatch), GT or LT (binary search pivot)
+}
+
+impl Dispatcher {
+    /// Recover the selector dispatcher by walking the CFG from its entry
+    ///
+    /// Recognizes `PUSH4 selector EQ PUSH2 dest JUMPI` chains (with the `DUP` before or
+    /// after the push, as emitted by different solc versions), Vyper's `XOR` variant that
+    /// jumps on a mismatch, and the `GT`/`LT` pivots of binary-search dispatchers that
+    /// solc emits for contracts with many functions. Only blocks reached from the entry
+    /// through the preamble (CALLVALUE and CALLDATASIZE checks, selector extraction) and
+    /// other comparisons are considered, so constants compared inside functions are ignored.
+    ///
+    /// Returns `None` when no selector is matched, e.g. for initcode or contracts without
+    /// external functions.
+    pub fn recover(cfg_runner: &CFGRunner) -> Option<Self> {
+        let mut dispatcher = Dispatcher::default();
+        let entry = cfg_runner.cfg_dag.nodes().find(|node| node.0 == cfg_runner.entry_pc())?;
+
+        let mut queue = VecDeque::from([entry]);
+        let mut visited = HashSet::from([entry]);
+        while let Some(node) = queue.pop_front() {
+            let successors = |edge_filter: &dyn Fn(&Edges) -> bool| -> Vec<Node> {
+                cfg_runner
+                    .cfg_dag
+                    .edges_directed(node, Direction::Outgoing)
+                    .filter(|(_, _, edge)| edge_filter(edge))
+                    .map(|(_, to, _)| to)
+                    .collect()
+            };
+
+            let to_visit = match selector_compare(cfg_runner, node) {
+                Some(compare) if compare.op == EQ || compare.op == XOR => {
+                    // EQ jumps to the function when the selector matches, Vyper's XOR when it does not
+                    let (function_edge, next_edge) = if compare.op == EQ {
+                        (Edges::ConditionTrue, Edges::ConditionFalse)
+                    } else {
+                        (Edges::ConditionFalse, Edges::ConditionTrue)
+                    };
+                    if let Some(function_entry) = successors(&|edge| *edge == function_edge).first() {
+                        dispatcher.entries.insert(FunctionId::Selector(compare.constant), *function_entry);
+                    }
+                    successors(&|edge| *edge == next_edge)
+                }
+                Some(_) => successors(&|_| true),
+                None if node == entry || is_preamble(cfg_runner, node) || is_trampoline(cfg_runner, node) => {
+                    successors(&|_| true)
+                }
+                None => {
+                    // Left the dispatcher, anything but a bare revert is the fallback
+                    if !dispatcher.entries.contains_key(&FunctionId::Fallback) && !only_reverts(cfg_runner, node) {
+                        dispatcher.entries.insert(FunctionId::Fallback, node);
+                    }
+                    continue;
+                }
+            };
+            dispatcher.blocks.insert(node);
+            for next in to_visit {
+                if visited.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        // Without any selector the contract has no recognizable dispatcher
+        (dispatcher.selector_count() > 0).then_some(dispatcher)
+    }
+
+    /// Number of external functions matched by selector, the fallback is not counted
+    pub fn selector_count(&self) -> usize {
+        self.entries.keys().filter(|function| matches!(function, FunctionId::Selector(_))).count()
+    }
+
+    /// Entry block of the function with the given selector
+    pub fn entry_of(&self, selector: [u8; 4]) -> Option<Node> {
+        self.entries.get(&FunctionId::Selector(selector)).copied()
+    }
+
+    /// Functions each block can be reached from, walking forward from every function entry
+    ///
+    /// When `executed_pcs` is given only executed blocks are walked, so helpers shared by
+    /// several functions belong to the ones that actually ran. Dispatcher blocks map to
+    /// [`FunctionId::Dispatcher`] alone.
+    pub fn block_functions(&self, cfg_runner: &CFGRunner, executed_pcs: Option<&HashSet<u16>>) -> HashMap<Node, BTreeSet<FunctionId>> {
+        let is_executed = |node: &Node| executed_pcs.is_none_or(|pcs| cfg_runner.block_ran(*node, pcs));
+        let entry_nodes: HashSet<Node> = self.entries.values().copied().collect();
+        let mut functions: HashMap<Node, BTreeSet<FunctionId>> = HashMap::new();
+        for node in self.blocks.iter().filter(|node| is_executed(node)) {
+            functions.entry(*node).or_default().insert(FunctionId::Dispatcher);
+        }
+
+        for (function, entry) in &self.entries {
+            if !is_executed(entry) {
+                continue;
+            }
+            let mut queue = VecDeque::from([*entry]);
+            let mut visited = HashSet::from([*entry]);
+            while let Some(node) = queue.pop_front() {
+                functions.entry(node).or_default().insert(*function);
+                for next in cfg_runner.cfg_dag.neighbors_directed(node, Direction::Outgoing) {
+                    let stays_inside = is_executed(&next) && !self.blocks.contains(&next) && !entry_nodes.contains(&next);
+                    if stays_inside && visited.insert(next) {
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        functions
+    }
+}
+
+/// Match `[PUSH constant] [DUPn] CMP [PUSH dest] JUMPI` at the end of a block
+///
+/// The DUP may come before the push instead, and the jump destination push is absent for RJUMPI.
+fn selector_compare(cfg_runner: &CFGRunner, node: Node) -> Option<SelectorCompare> {
+    let block = cfg_runner.map_to_instructionblock.get(&node)?;
+    let ops = &block.ops;
+    let (_, last_op, _) = ops.last()?;
+    let compare_at = match *last_op {
+        JUMPI => ops.len().checked_sub(3).filter(|at| is_push(ops[at + 1].1))?,
+        RJUMPI => ops.len().checked_sub(2)?,
+        _ => return None,
+    };
+    let op = ops[compare_at].1;
+    if ![EQ, XOR, GT, LT].contains(&op) {
+        return None;
+    }
+
+    // The selector stays on the stack for the next comparison, so it is always duplicated
+    let is_dup = |at: usize| ops.get(at).is_some_and(|(_, op, _)| (DUP1..=DUP16).contains(op));
+    let operand_at = if is_dup(compare_at.checked_sub(1)?) {
+        compare_at.checked_sub(2)?
+    } else if is_dup(compare_at.checked_sub(2)?) {
+        compare_at - 1
+    } else {
+        return None;
+    };
+    let (_, push, value) = &ops[operand_at];
+    // Selectors with leading zero bytes are pushed with fewer bytes
+    if !(PUSH1..=PUSH4).contains(push) {
+        return None;
+    }
+    let value = value.as_deref()?;
+    let mut constant = [0u8; 4];
+    constant[4 - value.len()..].copy_from_slice(value);
+    Some(SelectorCompare { constant, op })
+}
+
+fn is_push(op: u8) -> bool {
+    (PUSH1..=PUSH32).contains(&op)
+}
+
+/// Checks run before the selector is matched: CALLDATASIZE bounds and selector extraction
+fn is_preamble(cfg_runner: &CFGRunner, node: Node) -> bool {
+    let Some(block) = cfg_runner.map_to_instructionblock.get(&node) else {
+        return false;
+    };
+    let has = |wanted: u8| block.ops.iter().any(|(_, op, _)| *op == wanted);
+    has(CALLDATASIZE) || (has(CALLDATALOAD) && (has(SHR) || has(DIV)))
+}
+
+/// Block that only jumps somewhere else, e.g. `JUMPDEST PUSH2 dest JUMP`
+fn is_trampoline(cfg_runner: &CFGRunner, node: Node) -> bool {
+    cfg_runner.map_to_instructionblock.get(&node).is_some_and(|block| {
+        block.ops.last().is_some_and(|(_, op, _)| *op == JUMP)
+            && block.ops.iter().all(|(_, op, _)| *op == JUMPDEST || *op == JUMP || is_push(*op))
+    })
+}
+
+/// Block that does nothing but revert, e.g. `PUSH0 DUP1 REVERT`
+fn only_reverts(cfg_runner: &CFGRunner, node: Node) -> bool {
+    cfg_runner.map_to_instructionblock.get(&node).is_some_and(|block| {
+        block.ops.last().is_some_and(|(_, op, _)| *op == REVERT || *op == INVALID)
+            && block.ops.iter().all(|(_, op, _)| {
+                [JUMPDEST, REVERT, INVALID, POP].contains(op) || is_push(*op) || *op == PUSH0 || (DUP1..=DUP16).contains(op)
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg_gen::dasm::{self, InstructionBlock};
+    use revm::primitives::SpecId;
+    use std::collections::BTreeMap;
+
+    fn cfg(hex_code: &str) -> CFGRunner<'static> {
+        let code = hex::decode(hex_code).unwrap();
+        let blocks: BTreeMap<(u16, u16), InstructionBlock> = dasm::disassemble(&code, SpecId::CANCUN)
+            .into_iter()
+            .map(|mut block| {
+                block.analyze_stack_info();
+                ((block.start_pc, block.end_pc), block)
+            })
+            .collect();
+        let mut cfg_runner = CFGRunner::new(code, Box::leak(Box::new(blocks)));
+        cfg_runner.form_basic_connections();
+        cfg_runner
+    }
+
+    fn entry_pc(dispatcher: &Dispatcher, selector: u32) -> Option<u16> {
+        dispatcher.entry_of(selector.to_be_bytes()).map(|node| node.0)
+    }
+
+    #[test]
+    fn recovers_linear_solc_dispatcher() {
+        // DUP1 PUSH4 selector EQ PUSH2 dest JUMPI, twice, then a bare revert
+        let dispatcher = Dispatcher::recover(&cfg(concat!(
+            "608060405260043610610028575f3560e01c",
+            "8063a9059cbb1461002c57",
+            "806370a082311461002e57",
+            "5b5f80fd", // 40 nomatch: revert
+            "5b00",     // 44 transfer
+            "5b00",     // 46 balanceOf
+        )))
+        .unwrap();
+        assert_eq!(entry_pc(&dispatcher, 0xa9059cbb), Some(44));
+        assert_eq!(entry_pc(&dispatcher, 0x70a08231), Some(46));
+        assert_eq!(dispatcher.selector_count(), 2);
+        assert!(!dispatcher.entries.contains_key(&FunctionId::Fallback));
+        // Preamble and both comparisons, not the revert
+        assert_eq!(dispatcher.blocks, HashSet::from([(0, 12), (13, 28), (29, 39)]));
+    }
+
+    #[test]
+    fn recovers_dup_after_push_order_and_fallback() {
+        // PUSH4 selector DUP2 EQ PUSH2 dest JUMPI, the second selector has a leading zero
+        // byte and is pushed with PUSH3, no match ends in a fallback that does not revert
+        let dispatcher = Dispatcher::recover(&cfg(concat!(
+            "608060405260043610610027575f3560e01c",
+            "63a9059cbb811461002a57",
+            "620d15cd811461002c57",
+            "5b3400", // 39 nomatch: fallback
+            "5b00",   // 42
+            "5b00",   // 44
+        )))
+        .unwrap();
+        assert_eq!(entry_pc(&dispatcher, 0xa9059cbb), Some(42));
+        assert_eq!(entry_pc(&dispatcher, 0x000d15cd), Some(44));
+        assert_eq!(dispatcher.entries.get(&FunctionId::Fallback).map(|node| node.0), Some(39));
+        // The fallback is an entry but not a selector
+        assert_eq!(dispatcher.entries.len(), 3);
+        assert_eq!(dispatcher.selector_count(), 2);
+    }
+
+    #[test]
+    fn recovers_binary_search_dispatcher() {
+        // DUP1 PUSH4 pivot GT PUSH2 upper JUMPI splits the selectors in two linear chains
+        let dispatcher = Dispatcher::recover(&cfg(concat!(
+            "60806040526004361061004e575f3560e01c",
+            "806370a082311161003757",   // 18 pivot, same block as the selector extraction
+            "8063095ea7b31461005257",   // 29
+            "806318160ddd1461005457",   // 40
+            "61004e56",                 // 51 to nomatch
+            "5b806370a082311461005657", // 55 upper
+            "8063a9059cbb1461005857",   // 67
+            "5b5f80fd",                 // 78 nomatch: revert
+            "5b00", "5b00", "5b00", "5b00",
+        )))
+        .unwrap();
+        assert_eq!(entry_pc(&dispatcher, 0x095ea7b3), Some(82));
+        assert_eq!(entry_pc(&dispatcher, 0x18160ddd), Some(84));
+        assert_eq!(entry_pc(&dispatcher, 0x70a08231), Some(86));
+        assert_eq!(entry_pc(&dispatcher, 0xa9059cbb), Some(88));
+        // The pivot is compared with GT, it is not a function of its own
+        assert_eq!(dispatcher.selector_count(), 4);
+        assert!(dispatcher.blocks.contains(&(55, 66)) && dispatcher.blocks.contains(&(13, 28)));
+    }
+
+    #[test]
+    fn recovers_vyper_xor_dispatcher() {
+        // PUSH4 selector DUP2 XOR PUSH2 next JUMPI, the function follows when the XOR is zero
+        let dispatcher = Dispatcher::recover(&cfg(concat!(
+            "60806040526004361061002d575f3560e01c",
+            "63a9059cbb811861001e57",
+            "00",                       // 29 transfer
+            "5b6370a08231811861002d57", // 30 next
+            "600100",                   // 42 balanceOf
+            "5b5f80fd",                 // 45 nomatch: revert
+        )))
+        .unwrap();
+        assert_eq!(entry_pc(&dispatcher, 0xa9059cbb), Some(29));
+        assert_eq!(entry_pc(&dispatcher, 0x70a08231), Some(42));
+        assert_eq!(dispatcher.selector_count(), 2);
+    }
+
+    #[test]
+    fn finds_no_dispatcher_without_selectors() {
+        // Constructor-like code: copy something and return it
+        assert!(Dispatcher::recover(&cfg("600a600c5f39600a5ff3")).is_none());
+        // A constant compared against another constant, not against a kept selector
+        assert!(Dispatcher::recover(&cfg("60016002146008575b00")).is_none());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::cfg_gen::{dasm::InstructionBlock, eof_opcode};
+use eyre::{eyre, Result};
+use revm::interpreter::{opcode::*, OPCODE_INFO_JUMPTABLE};
+use revm::primitives::{Bytes, Eof, SpecId};
+use std::collections::BTreeSet;
+
+/// Prefix of EOF containers (EIP-3540), legacy code can never start with 0xEF (EIP-3541)
+pub const EOF_MAGIC: [u8; 2] = [0xEF, 0x00];
+
+/// `outputs` of a code section that never returns to its caller (EIP-6206)
+pub const NON_RETURNING: u8 = 0x80;
+
+/// Whether the code is an EOF container rather than legacy bytecode
+pub fn is_eof(code: &[u8]) -> bool {
+    code.starts_with(&EOF_MAGIC)
+}
+
+/// A code section of an EOF container, i.e. one function (EIP-4750)
+#[derive(Clone, Debug)]
+pub struct CodeSection {
+    pub index: u16,
+    pub offset: u16, // position of the section's first byte in the container
+    pub inputs: u8,
+    pub outputs: u8,
+    pub max_stack_height: u16,
+    pub code: Bytes,
+}
+
+impl CodeSection {
+    /// Offset just past the section's last byte
+    pub fn end(&self) -> u16 {
+        self.offset + self.code.len() as u16
+    }
+
+    pub fn is_returning(&self) -> bool {
+        self.outputs != NON_RETURNING
+    }
+
+    /// Short description of the section, e.g. `section 1 (2 → 1)`
+    pub fn label(&self) -> String {
+        if self.is_returning() {
+            format!("section {} ({} → {})", self.index, self.inputs, self.outputs)
+        } else {
+            format!("section {} ({} → non-returning)", self.index, self.inputs)
+        }
+    }
+}
+
+/// Decoded EOF container
+///
+/// Instructions are addressed by their offset in the whole container, so the pcs of
+/// different code sections never overlap and section 0 starts after the header.
+#[derive(Clone, Debug)]
+pub struct EofContainer {
+    pub sections: Vec<CodeSection>,
+    pub subcontainers: Vec<Bytes>, // containers deployed by EOFCREATE and RETURNCONTRACT
+    pub data: Bytes,
+}
+
+impl EofContainer {
+    /// Offset of the first instruction executed when the container is called
+    pub fn entry_pc(&self) -> u16 {
+        self.sections[0].offset
+    }
+
+    /// Code section holding the given container offset
+    pub fn section_at(&self, pc: u16) -> Option<&CodeSection> {
+        self.sections
+            .iter()
+            .find(|section| section.offset <= pc && pc < section.end())
+    }
+}
+
+/// Parse the header and sections of an EOF container
+pub fn parse_container(code: &[u8]) -> Result<EofContainer> {
+    let eof = Eof::decode(Bytes::copy_from_slice(code)).map_err(|e| eyre!("Invalid EOF container: {:?}", e))?;
+
+    let mut offset = eof.header.size() + eof.header.types_size as usize;
+    let mut sections = Vec::with_capacity(eof.body.code_section.len());
+    for (index, (types, code)) in eof.body.types_section.iter().zip(&eof.body.code_section).enumerate() {
+        sections.push(CodeSection {
+            index: index as u16,
+            offset: offset as u16,
+            inputs: types.inputs,
+            outputs: types.outputs,
+            max_stack_height: types.max_stack_size,
+            code: code.clone(),
+        });
+        offset += code.len();
+    }
+
+    Ok(EofContainer {
+        sections,
+        subcontainers: eof.body.container_section.clone(),
+        data: eof.body.data_section.clone(),
+    })
+}
+
+/// Immediate bytes following the opcode at `pos` of a code section
+fn immediate_size(code: &[u8], pos: usize) -> usize {
+    let op = code[pos];
+    if op == RJUMPV {
+        // max_index, then one relative offset per case
+        return code.get(pos + 1).map_or(1, |max_index| 1 + 2 * (*max_index as usize + 1));
+    }
+    OPCODE_INFO_JUMPTABLE[op as usize].map_or(0, |info| info.immediate_size() as usize)
+}
+
+/// Targets of a relative jump instruction (RJUMP, RJUMPI, RJUMPV), as container offsets
+///
+/// Offsets are relative to the end of the instruction, which the immediates are part of.
+pub fn relative_jump_targets(pc: u16, op: u8, immediates: &[u8]) -> Vec<u16> {
+    let read_offset = |at: usize| -> Option<i16> {
+        Some(i16::from_be_bytes([*immediates.get(at)?, *immediates.get(at + 1)?]))
+    };
+    let next_pc = pc as i32 + 1 + immediates.len() as i32;
+    let offsets: Vec<i16> = match op {
+        RJUMP | RJUMPI => read_offset(0).into_iter().collect(),
+        RJUMPV => (0..immediates.first().map_or(0, |max_index| *max_index as usize + 1))
+            .filter_map(|case| read_offset(1 + 2 * case))
+            .collect(),
+        _ => Vec::new(),
+    };
+    offsets
+        .into_iter()
+        .filter_map(|offset| u16::try_from(next_pc + offset as i32).ok())
+        .collect()
+}
+
+/// Code section targeted by CALLF or JUMPF
+pub fn function_target(immediates: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes([*immediates.first()?, *immediates.get(1)?]))
+}
+
+/// Split every code section of an EOF container into basic blocks
+///
+/// Blocks start at the beginning of a section, at relative jump targets and after
+/// any instruction that ends a block. Immediates are kept as the instruction's push value.
+pub fn disassemble_container(container: &EofContainer, spec_id: SpecId) -> Vec<InstructionBlock> {
+    let mut blocks = Vec::new();
+    for section in &container.sections {
+        let code = &section.code[..];
+
+        // Decode the section once to find where blocks start
+        let mut instructions = Vec::new();
+        let mut leaders = BTreeSet::from([section.offset]);
+        let mut pos = 0;
+        while pos < code.len() {
+            let op = code[pos];
+            let pc = section.offset + pos as u16;
+            let size = immediate_size(code, pos);
+            let immediates = code.get(pos + 1..(pos + 1 + size).min(code.len())).unwrap_or_default().to_vec();
+            let next_pc = pc + 1 + immediates.len() as u16;
+            if eof_opcode(op).is_none_or(|info| info.ends_block) {
+                leaders.insert(next_pc);
+            }
+            leaders.extend(relative_jump_targets(pc, op, &immediates));
+            instructions.push((pc, op, (size > 0).then_some(immediates)));
+            pos += 1 + size;
+        }
+
+        let mut block: Option<InstructionBlock> = None;
+        for (pc, op, immediates) in instructions {
+            if leaders.contains(&pc) {
+                if let Some(mut finished) = block.take() {
+                    finished.end_pc = pc - 1;
+                    blocks.push(finished);
+                }
+            }
+            let current = block.get_or_insert_with(|| {
+                let mut new_block = InstructionBlock::new(pc, spec_id);
+                new_block.eof_section = Some(section.index);
+                new_block
+            });
+            current.add_instruction(pc, op, immediates);
+        }
+        if let Some(mut finished) = block {
+            finished.end_pc = section.end() - 1;
+            blocks.push(finished);
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two code sections: section 0 switches with RJUMPV and calls section 1 with CALLF
+    fn container() -> Vec<u8> {
+        let hex_code = concat!(
+            "ef0001",       // magic and version
+            "010008",       // types section, 2 entries of 4 bytes
+            "020002000b",   // 2 code sections: 11 bytes,
+            "0001",         // and 1 byte
+            "040002",       // data section of 2 bytes
+            "00",           // header terminator
+            "00800001",     // section 0: no inputs, non-returning, max stack height 1
+            "00000000",     // section 1: no inputs, no outputs
+            "5f",           // 0 PUSH0
+            "e20100000003", // 1 RJUMPV [+0, +3]
+            "e30001",       // 7 CALLF 1
+            "00",           // 10 STOP
+            "e4",           // section 1, 0 RETF
+            "aabb",         // data
+        );
+        hex::decode(hex_code).unwrap()
+    }
+
+    #[test]
+    fn parses_header_and_sections() {
+        let code = container();
+        assert!(is_eof(&code));
+        let container = parse_container(&code).unwrap();
+
+        assert_eq!(container.sections.len(), 2);
+        let (main, function) = (&container.sections[0], &container.sections[1]);
+        assert_eq!((main.offset, main.end()), (25, 36));
+        assert!(!main.is_returning());
+        assert_eq!(main.max_stack_height, 1);
+        assert_eq!((function.offset, function.end()), (36, 37));
+        assert!(function.is_returning());
+        assert_eq!(function.label(), "section 1 (0 → 0)");
+
+        assert_eq!(container.entry_pc(), 25);
+        assert_eq!(container.section_at(35).map(|section| section.index), Some(0));
+        assert_eq!(container.section_at(36).map(|section| section.index), Some(1));
+        assert!(container.section_at(37).is_none());
+        assert_eq!(&container.data[..], &[0xaa, 0xbb]);
+        assert!(container.subcontainers.is_empty());
+    }
+
+    #[test]
+    fn resolves_rjumpv_and_callf_targets() {
+        // RJUMPV at container offset 26 ends at 32, its cases go to the CALLF and the STOP
+        assert_eq!(relative_jump_targets(26, RJUMPV, &[0x01, 0x00, 0x00, 0x00, 0x03]), vec![32, 35]);
+        assert_eq!(relative_jump_targets(26, RJUMP, &[0xff, 0xf0]), vec![13]);
+        assert_eq!(function_target(&[0x00, 0x01]), Some(1));
+        assert_eq!(function_target(&[0x00]), None);
+    }
+
+    #[test]
+    fn splits_sections_into_blocks() {
+        let container = parse_container(&container()).unwrap();
+        let blocks = disassemble_container(&container, SpecId::PRAGUE);
+        let bounds: Vec<(u16, u16, Option<u16>)> = blocks
+            .iter()
+            .map(|block| (block.start_pc, block.end_pc, block.eof_section))
+            .collect();
+        assert_eq!(
+            bounds,
+            vec![
+                (25, 31, Some(0)), // PUSH0, RJUMPV
+                (32, 34, Some(0)), // CALLF
+                (35, 35, Some(0)), // STOP
+                (36, 36, Some(1)), // RETF
+            ]
+        );
+        assert_eq!(blocks[0].ops[1], (26, RJUMPV, Some(vec![0x01, 0x00, 0x00, 0x00, 0x03])));
+        assert_eq!(blocks[1].ops[0], (32, CALLF, Some(vec![0x00, 0x01])));
+    }
+
+    #[test]
+    fn rejects_truncated_container() {
+        let code = container();
+        assert!(parse_container(&code[..20]).is_err()); // inside the header
+        assert!(parse_container(&code[..30]).is_err()); // inside section 0
+        // The data section may be short, deployment appends the rest
+        assert!(parse_container(&code[..code.len() - 1]).is_ok());
+    }
+}
@@ -0,0 +1,397 @@
+use crate::cfg_gen::{eof, opcode_at};
+use revm::interpreter::opcode::*;
+use revm::primitives::SpecId;
+use std::fmt::Display;
+use std::ops::Range;
+
+/// Split of a contract's bytecode into executable code, data and the compiler's metadata trailer
+///
+/// Only `code` is worth disassembling, decoding the rest yields bogus instruction blocks.
+#[derive(Clone, Debug, Default)]
+pub struct BytecodeLayout {
+    pub code: Range<usize>,
+    pub data: Vec<Range<usize>>, // unreachable regions: constants, constructor arguments, EOF data section
+    pub metadata: Option<CompilerMetadata>,
+}
+
+/// Compiler metadata trailer, a CBOR item followed by its 2 byte big-endian length
+#[derive(Clone, Debug)]
+pub struct CompilerMetadata {
+    pub range: Range<usize>, // including the length suffix
+    pub compiler: Compiler,
+    pub version: Option<String>,
+    pub ipfs: Option<String>,  // base58 CIDv0 of the metadata JSON
+    pub swarm: Option<String>, // bzzr0/bzzr1 hash of the metadata JSON, hex
+    pub experimental: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compiler {
+    Solidity,
+    Vyper,
+}
+
+impl Display for CompilerMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.compiler {
+            Compiler::Solidity => "solc",
+            Compiler::Vyper => "vyper",
+        };
+        write!(f, "{} {}", name, self.version.as_deref().unwrap_or("(unknown version)"))?;
+        if self.experimental {
+            write!(f, " (experimental)")?;
+        }
+        Ok(())
+    }
+}
+
+impl BytecodeLayout {
+    /// Locate the code, data and metadata of legacy bytecode or an EOF container
+    pub fn analyze(bytecode: &[u8], spec_id: SpecId) -> Self {
+        if eof::is_eof(bytecode) {
+            return Self::analyze_eof(bytecode);
+        }
+
+        // Creation code carries constructor arguments after its metadata, so the
+        // trailer is not always at the very end
+        let metadata = find_metadata(bytecode);
+        let code_end = metadata.as_ref().map_or(bytecode.len(), |metadata| metadata.range.start);
+        let data_start = data_start(&bytecode[..code_end], spec_id);
+
+        let mut data = Vec::new();
+        if data_start < code_end {
+            data.push(data_start..code_end);
+        }
+        if let Some(metadata) = &metadata {
+            if metadata.range.end < bytecode.len() {
+                data.push(metadata.range.end..bytecode.len());
+            }
+        }
+        Self { code: 0..data_start, data, metadata }
+    }
+
+    fn analyze_eof(bytecode: &[u8]) -> Self {
+        let Ok(container) = eof::parse_container(bytecode) else {
+            return Self { code: 0..bytecode.len(), ..Default::default() };
+        };
+        let code_start = container.entry_pc() as usize;
+        let code_end = container.sections.last().map_or(code_start, |section| section.end() as usize);
+        // Subcontainers sit between the code sections and the data section
+        let data_start = bytecode.len() - container.data.len();
+        let data = (data_start < bytecode.len()).then_some(data_start..bytecode.len()).into_iter().collect();
+        Self { code: code_start..code_end, data, metadata: None }
+    }
+
+    /// Whether the byte at `pc` is neither code nor metadata
+    pub fn is_data(&self, pc: usize) -> bool {
+        self.data.iter().any(|range| range.contains(&pc))
+    }
+}
+
+/// Start of the unreachable bytes following the last instruction that ends execution
+///
+/// Without a JUMPDEST after it nothing can jump past that instruction, and it does
+/// not fall through, so whatever follows is data (e.g. constants read with CODECOPY).
+fn data_start(code: &[u8], spec_id: SpecId) -> usize {
+    let mut data_start = code.len();
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        let next_pc = pc + 1 + if (PUSH1..=PUSH32).contains(&op) { (op - PUSH0) as usize } else { 0 };
+        match opcode_at(op, spec_id) {
+            Some(_) if op == JUMPDEST => data_start = code.len(),
+            Some(info) if info.ends_block && op != JUMPI && data_start == code.len() => {
+                data_start = next_pc.min(code.len())
+            }
+            None if data_start == code.len() => data_start = next_pc.min(code.len()),
+            _ => {}
+        }
+        pc = next_pc;
+    }
+    data_start
+}
+
+/// Find the Solidity or Vyper metadata trailer, preferring the one closest to the end
+fn find_metadata(bytecode: &[u8]) -> Option<CompilerMetadata> {
+    (2..=bytecode.len()).rev().find_map(|end| parse_metadata(bytecode, end))
+}
+
+/// Try to read a metadata trailer whose length suffix ends at `end`
+fn parse_metadata(bytecode: &[u8], end: usize) -> Option<CompilerMetadata> {
+    let length = u16::from_be_bytes([bytecode[end - 2], bytecode[end - 1]]) as usize;
+    // Solidity and Vyper before 0.3.10 exclude the suffix from the length, later Vyper includes it
+    [length, length.checked_sub(2)?].into_iter().find_map(|cbor_len| {
+        let start = (end - 2).checked_sub(cbor_len)?;
+        let cbor = &bytecode[start..end - 2];
+        // Cheap check before decoding, the trailer is a map (Solidity, old Vyper) or an array (Vyper)
+        if !matches!(cbor.first()? >> 5, 4 | 5) {
+            return None;
+        }
+        let (item, used) = Cbor::decode(cbor)?;
+        if used != cbor.len() {
+            return None;
+        }
+        let mut metadata = metadata_from_cbor(&item)?;
+        metadata.range = start..end;
+        Some(metadata)
+    })
+}
+
+fn metadata_from_cbor(item: &Cbor) -> Option<CompilerMetadata> {
+    // Vyper 0.3.10+ wraps its map in an array with the code and data sizes
+    let entries = match item {
+        Cbor::Map(entries) => entries,
+        Cbor::Array(items) => items.iter().find_map(|item| match item {
+            Cbor::Map(entries) => Some(entries),
+            _ => None,
+        })?,
+        _ => return None,
+    };
+
+    let mut metadata = CompilerMetadata {
+        range: 0..0,
+        compiler: Compiler::Solidity,
+        version: None,
+        ipfs: None,
+        swarm: None,
+        experimental: false,
+    };
+    let mut known_key = false;
+    for (key, value) in entries {
+        let Cbor::Text(key) = key else { return None };
+        match (key.as_str(), value) {
+            ("solc", Cbor::Bytes(version)) if version.len() == 3 => {
+                metadata.version = Some(format!("{}.{}.{}", version[0], version[1], version[2]));
+            }
+            ("solc", Cbor::Text(version)) => metadata.version = Some(version.clone()), // pre-release builds
+            ("vyper", Cbor::Array(version)) => {
+                metadata.compiler = Compiler::Vyper;
+                let parts: Option<Vec<String>> = version
+                    .iter()
+                    .map(|part| match part {
+                        Cbor::Uint(part) => Some(part.to_string()),
+                        _ => None,
+                    })
+                    .collect();
+                metadata.version = Some(parts?.join("."));
+            }
+            ("ipfs", Cbor::Bytes(hash)) => metadata.ipfs = Some(base58(hash)),
+            ("bzzr0" | "bzzr1", Cbor::Bytes(hash)) => metadata.swarm = Some(hex::encode(hash)),
+            ("experimental", Cbor::Bool(experimental)) => metadata.experimental = *experimental,
+            _ => continue,
+        }
+        known_key = true;
+    }
+    known_key.then_some(metadata)
+}
+
+/// The subset of CBOR (RFC 8949) compilers emit in their metadata
+enum Cbor {
+    Uint(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Bool(bool),
+    Null,
+}
+
+impl Cbor {
+    /// Decode one item, returning it with the number of bytes it took
+    fn decode(data: &[u8]) -> Option<(Self, usize)> {
+        Self::decode_nested(data, 0)
+    }
+
+    fn decode_nested(data: &[u8], depth: usize) -> Option<(Self, usize)> {
+        if depth > 8 {
+            return None;
+        }
+        let head = *data.first()?;
+        let (major, info) = (head >> 5, head & 0x1f);
+        let (argument, mut pos): (u64, usize) = match info {
+            0..=23 => (info as u64, 1),
+            24 => (*data.get(1)? as u64, 2),
+            25 => (u16::from_be_bytes(data.get(1..3)?.try_into().ok()?) as u64, 3),
+            26 => (u32::from_be_bytes(data.get(1..5)?.try_into().ok()?) as u64, 5),
+            27 => (u64::from_be_bytes(data.get(1..9)?.try_into().ok()?), 9),
+            _ => return None, // indefinite lengths are never emitted
+        };
+        let item = match major {
+            0 => Cbor::Uint(argument),
+            2 | 3 => {
+                let bytes = data.get(pos..pos.checked_add(argument as usize)?)?.to_vec();
+                pos += bytes.len();
+                if major == 2 {
+                    Cbor::Bytes(bytes)
+                } else {
+                    Cbor::Text(String::from_utf8(bytes).ok()?)
+                }
+            }
+            4 => {
+                let mut items = Vec::new();
+                for _ in 0..argument {
+                    let (item, used) = Self::decode_nested(&data[pos..], depth + 1)?;
+                    items.push(item);
+                    pos += used;
+                }
+                Cbor::Array(items)
+            }
+            5 => {
+                let mut entries = Vec::new();
+                for _ in 0..argument {
+                    let (key, used) = Self::decode_nested(&data[pos..], depth + 1)?;
+                    pos += used;
+                    let (value, used) = Self::decode_nested(&data[pos..], depth + 1)?;
+                    pos += used;
+                    entries.push((key, value));
+                }
+                Cbor::Map(entries)
+            }
+            7 => match info {
+                20 => Cbor::Bool(false),
+                21 => Cbor::Bool(true),
+                22 => Cbor::Null,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some((item, pos))
+    }
+}
+
+/// Base58 (bitcoin alphabet) encoding, used for IPFS hashes
+fn base58(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let mut digits: Vec<u8> = Vec::new(); // little-endian base 58
+    for byte in bytes {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let leading_zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    std::iter::repeat_n(b'1', leading_zeros)
+        .chain(digits.iter().rev().map(|digit| ALPHABET[*digit as usize]))
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// solc 0.8.24 trailer: {"ipfs": <multihash>, "solc": 0x000818} and its length
+    const SOLC_TRAILER: &str = concat!(
+        "a264697066735822",
+        "122045447b7afbd5e544f7d0f1df0fccd26014d9850130abd3f020b89ff96b82079f",
+        "64736f6c6343000818",
+        "0033",
+    );
+
+    /// Vyper 0.3.10 trailer: [runtime size, data sizes, immutables size, {"vyper": [0, 3, 10]}],
+    /// its length counts the 2 length bytes too
+    const VYPER_TRAILER: &str = "84190123800ba16576797065728300030a0013";
+
+    fn bytes(hex_code: &str) -> Vec<u8> {
+        hex::decode(hex_code).unwrap()
+    }
+
+    #[test]
+    fn finds_solc_trailer() {
+        // PUSH1 0x80 PUSH1 0x40 MSTORE PUSH0 DUP1 REVERT INVALID
+        let code = bytes(&format!("60806040525f80fdfe{}", SOLC_TRAILER));
+        let layout = BytecodeLayout::analyze(&code, SpecId::CANCUN);
+
+        let metadata = layout.metadata.as_ref().unwrap();
+        assert_eq!(metadata.compiler, Compiler::Solidity);
+        assert_eq!(metadata.version.as_deref(), Some("0.8.24"));
+        assert_eq!(metadata.ipfs.as_deref(), Some("QmT13QYnUQxx28uA7BqnPdPE1dQpV3zp9Jy9z21dv3DqWn"));
+        assert_eq!(metadata.range, 9..code.len());
+        assert_eq!(metadata.to_string(), "solc 0.8.24");
+
+        // The INVALID solc puts before the trailer is never reached
+        assert_eq!(layout.code, 0..8);
+        assert_eq!(layout.data, vec![8..9]);
+    }
+
+    #[test]
+    fn finds_vyper_trailer_with_inclusive_length() {
+        // PUSH0 PUSH0 REVERT
+        let code = bytes(&format!("5f5ffd{}", VYPER_TRAILER));
+        let layout = BytecodeLayout::analyze(&code, SpecId::CANCUN);
+
+        let metadata = layout.metadata.as_ref().unwrap();
+        assert_eq!(metadata.compiler, Compiler::Vyper);
+        assert_eq!(metadata.version.as_deref(), Some("0.3.10"));
+        assert!(metadata.ipfs.is_none());
+        assert_eq!(metadata.range, 3..code.len());
+        assert_eq!(layout.code, 0..3);
+        assert!(layout.data.is_empty());
+    }
+
+    #[test]
+    fn separates_creation_code_runtime_and_constructor_arguments() {
+        // Copy the 62 byte runtime at offset 9 to memory and return it
+        let init = "603e8060095f395ff3";
+        let runtime = format!("60806040525f80fdfe{}", SOLC_TRAILER);
+        let arguments = format!("{:064x}", 100);
+        let code = bytes(&format!("{}{}{}", init, runtime, arguments));
+        let layout = BytecodeLayout::analyze(&code, SpecId::CANCUN);
+
+        let metadata = layout.metadata.as_ref().unwrap();
+        assert_eq!(metadata.version.as_deref(), Some("0.8.24"));
+        assert_eq!(metadata.range, 18..71);
+        assert_eq!(layout.code, 0..9);
+        assert_eq!(layout.data, vec![9..18, 71..103]);
+        assert!(layout.is_data(80) && !layout.is_data(20) && !layout.is_data(5));
+    }
+
+    #[test]
+    fn ignores_malformed_trailers() {
+        // Hash cut short, the length no longer matches
+        let truncated = SOLC_TRAILER.replacen("45447b7a", "", 1);
+        let code = bytes(&format!("5f5ffd{}", truncated));
+        assert!(find_metadata(&code).is_none());
+        let layout = BytecodeLayout::analyze(&code, SpecId::CANCUN);
+        assert!(layout.metadata.is_none());
+        assert_eq!(layout.code, 0..3);
+
+        // A map without any key compilers use
+        assert!(find_metadata(&bytes("a1636b6579f50006")).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_or_unsupported_cbor() {
+        let trailer = bytes(SOLC_TRAILER);
+        let cbor = &trailer[..trailer.len() - 2];
+        assert!(matches!(Cbor::decode(cbor), Some((Cbor::Map(entries), 51)) if entries.len() == 2));
+        assert!(Cbor::decode(&cbor[..40]).is_none()); // inside the hash
+        assert!(Cbor::decode(&cbor[..cbor.len() - 1]).is_none()); // inside the version
+
+        assert!(Cbor::decode(&bytes("bf")).is_none()); // indefinite length map
+        assert!(Cbor::decode(&bytes("62fffe")).is_none()); // text that is not UTF-8
+        assert!(Cbor::decode(&bytes("19ff")).is_none()); // argument cut short
+        assert!(Cbor::decode(&bytes("f7")).is_none()); // undefined
+        assert!(matches!(Cbor::decode(&bytes("818181818181818100")), Some((_, 9)))); // nested 8 deep
+        assert!(Cbor::decode(&bytes("81818181818181818100")).is_none()); // nested too deep
+    }
+
+    #[test]
+    fn data_starts_after_last_terminating_instruction() {
+        // ADD of two pushes, STOP, then 4 bytes of data
+        assert_eq!(data_start(&bytes("600160020100deadbeef"), SpecId::CANCUN), 6);
+        // A JUMPDEST after the STOP can be jumped to, so the code goes on
+        assert_eq!(data_start(&bytes("005b00"), SpecId::CANCUN), 3);
+        // A PUSH running past the end is data
+        assert_eq!(data_start(&bytes("0061ff"), SpecId::CANCUN), 1);
+        // PUSH0 is not an opcode before Shanghai, so it starts the data there
+        assert_eq!(data_start(&bytes("5f5ffd"), SpecId::MERGE), 1);
+        assert_eq!(data_start(&bytes("5f5ffd"), SpecId::SHANGHAI), 3);
+    }
+}
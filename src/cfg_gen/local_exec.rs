@@ -0,0 +1,449 @@
+use crate::cfg_gen::{eof, trace::TraceStep};
+use ethers::types::{Bytes, H160, H256, U256};
+use eyre::{eyre, Result};
+use revm::{
+    db::{CacheDB, EmptyDB},
+    inspector_handle_register,
+    inspectors::GasInspector,
+    interpreter::{opcode, CallInputs, CallOutcome, CreateInputs, CreateOutcome, Interpreter, OpCode},
+    primitives::{
+        AccountInfo, Address, Bytecode, Eof, ExecutionResult, SpecId, TransactTo, B256, U256 as RevmU256,
+    },
+    Database, Evm, EvmContext, Inspector,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Everything needed to re-execute a transaction without a node: the transaction,
+/// the block it was included in and the state of every account it touched.
+///
+/// Field names follow the JSON-RPC results (`eth_getTransactionByHash`,
+/// `eth_getBlockByNumber` and the `prestateTracer`), so those can be dumped as is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionSnapshot {
+    pub transaction: SnapshotTransaction,
+    pub block: SnapshotBlock,
+    pub prestate: HashMap<H160, PrestateAccount>,
+    #[serde(default, rename = "blockHashes")]
+    pub block_hashes: HashMap<u64, H256>, // earlier blocks the transaction reads with BLOCKHASH
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotTransaction {
+    #[serde(default)]
+    pub hash: Option<H256>,
+    pub from: H160,
+    #[serde(default)]
+    pub to: Option<H160>, // None for contract creation
+    #[serde(default)]
+    pub value: U256,
+    #[serde(default)]
+    pub input: Bytes,
+    pub gas: U256,
+    #[serde(default)]
+    pub gas_price: Option<U256>,
+    #[serde(default)]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(default)]
+    pub max_priority_fee_per_gas: Option<U256>,
+    #[serde(default)]
+    pub nonce: Option<U256>,
+    #[serde(default)]
+    pub chain_id: Option<U256>,
+    #[serde(default)]
+    pub access_list: Option<Vec<SnapshotAccessListItem>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotAccessListItem {
+    pub address: H160,
+    #[serde(default)]
+    pub storage_keys: Vec<H256>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotBlock {
+    pub number: U256,
+    pub timestamp: U256,
+    #[serde(default)]
+    pub miner: H160,
+    #[serde(default)]
+    pub gas_limit: U256,
+    #[serde(default)]
+    pub base_fee_per_gas: Option<U256>,
+    #[serde(default)]
+    pub difficulty: U256,
+    #[serde(default)]
+    pub mix_hash: Option<H256>, // prevrandao after the merge
+    #[serde(default)]
+    pub excess_blob_gas: Option<U256>,
+}
+
+/// One account of a `prestateTracer` result
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrestateAccount {
+    #[serde(default)]
+    pub balance: Option<U256>,
+    #[serde(default)]
+    pub nonce: Option<u64>,
+    #[serde(default)]
+    pub code: Option<Bytes>,
+    #[serde(default)]
+    pub storage: Option<HashMap<H256, H256>>,
+}
+
+/// Result of re-executing a snapshot
+#[derive(Debug, Clone)]
+pub struct ExecutionOutcome {
+    pub success: bool,
+    pub gas_used: u64,
+    pub output: Bytes,
+    pub step_count: usize,
+    pub missing_accounts: Vec<H160>, // read during execution but absent from the prestate, executed as empty
+}
+
+/// Parse an execution snapshot file
+pub fn parse_snapshot_file(path: &str) -> Result<ExecutionSnapshot> {
+    let file = std::fs::File::open(path)?;
+    Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+}
+
+/// Re-execute the snapshot's transaction in revm under `spec_id`, handing every executed step to `on_step`
+///
+/// Steps carry the same fields as `debug_traceTransaction` struct logs (plus the
+/// storage context address), so they can be folded into call frames like a fetched trace.
+/// Memory is only recorded for calls and logs (to recover calldata and event data) unless `with_memory`
+/// is set, it dominates the cost of tracing.
+pub fn execute(
+    snapshot: &ExecutionSnapshot,
+    spec_id: SpecId,
+    with_memory: bool,
+    on_step: impl FnMut(TraceStep),
+) -> Result<ExecutionOutcome> {
+    let mut db = CacheDB::new(EmptyDB::default());
+    for (number, hash) in &snapshot.block_hashes {
+        db.block_hashes.insert(RevmU256::from(*number), B256::from(hash.0));
+    }
+    for (address, account) in &snapshot.prestate {
+        let code = account.code.clone().unwrap_or_default();
+        let bytecode = if eof::is_eof(&code) {
+            let container = Eof::decode(code.0.into())
+                .map_err(|e| eyre!("Invalid EOF code at {:?}: {:?}", address, e))?;
+            Bytecode::Eof(container)
+        } else {
+            Bytecode::new_raw(code.0.into())
+        };
+        let info = AccountInfo::new(
+            to_revm_u256(account.balance.unwrap_or_default()),
+            account.nonce.unwrap_or_default(),
+            bytecode.hash_slow(),
+            bytecode,
+        );
+        db.insert_account_info(to_address(*address), info);
+        for (slot, value) in account.storage.iter().flatten() {
+            db.insert_account_storage(
+                to_address(*address),
+                RevmU256::from_be_bytes(slot.0),
+                RevmU256::from_be_bytes(value.0),
+            )
+            .map_err(|e| eyre!("Could not seed storage of {:?}: {:?}", address, e))?;
+        }
+    }
+
+    let tx = &snapshot.transaction;
+    let block = &snapshot.block;
+    let recorder = StepRecorder {
+        gas_inspector: GasInspector::default(),
+        on_step,
+        with_memory,
+        pending: None,
+        step_count: 0,
+    };
+
+    let mut evm = Evm::builder()
+        .with_db(SnapshotDb { cache: db, missing_accounts: BTreeSet::new() })
+        .with_external_context(recorder)
+        .with_spec_id(spec_id)
+        .modify_cfg_env(|cfg| {
+            cfg.chain_id = tx.chain_id.map(|id| id.low_u64()).unwrap_or(1);
+        })
+        .modify_block_env(|env| {
+            env.number = to_revm_u256(block.number);
+            env.timestamp = to_revm_u256(block.timestamp);
+            env.coinbase = to_address(block.miner);
+            env.gas_limit = to_revm_u256(block.gas_limit);
+            env.basefee = to_revm_u256(block.base_fee_per_gas.unwrap_or_default());
+            env.difficulty = to_revm_u256(block.difficulty);
+            env.prevrandao = Some(B256::from(block.mix_hash.unwrap_or_default().0));
+            if let Some(excess_blob_gas) = block.excess_blob_gas {
+                env.set_blob_excess_gas_and_price(excess_blob_gas.low_u64());
+            }
+        })
+        .modify_tx_env(|env| {
+            env.caller = to_address(tx.from);
+            env.transact_to = match tx.to {
+                Some(to) => TransactTo::Call(to_address(to)),
+                None => TransactTo::Create,
+            };
+            env.value = to_revm_u256(tx.value);
+            env.data = tx.input.0.clone().into();
+            env.gas_limit = tx.gas.low_u64();
+            // EIP-1559 transactions pay min(maxFee, baseFee + tip), legacy ones their gas price
+            env.gas_price = to_revm_u256(tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default());
+            env.gas_priority_fee = tx.max_priority_fee_per_gas.map(to_revm_u256);
+            env.nonce = tx.nonce.map(|nonce| nonce.low_u64());
+            env.chain_id = tx.chain_id.map(|id| id.low_u64());
+            env.access_list = tx
+                .access_list
+                .iter()
+                .flatten()
+                .map(|item| {
+                    let keys = item.storage_keys.iter().map(|key| RevmU256::from_be_bytes(key.0)).collect();
+                    (to_address(item.address), keys)
+                })
+                .collect();
+        })
+        .append_handler_register(inspector_handle_register)
+        .build();
+
+    let result = evm
+        .transact()
+        .map_err(|e| eyre!("Local execution failed: {:?}", e))?
+        .result;
+    let step_count = evm.context.external.step_count;
+    let missing_accounts = evm.db().missing_accounts.iter().map(|address| H160::from(address.0 .0)).collect();
+
+    let (success, output) = match &result {
+        ExecutionResult::Success { output, .. } => (true, output.data().clone()),
+        ExecutionResult::Revert { output, .. } => (false, output.clone()),
+        ExecutionResult::Halt { .. } => (false, Default::default()),
+    };
+    Ok(ExecutionOutcome {
+        success,
+        gas_used: result.gas_used(),
+        output: Bytes::from(output.to_vec()),
+        step_count,
+        missing_accounts,
+    })
+}
+
+/// State of the snapshot, noting what execution needs beyond it
+///
+/// An account missing from the prestate is executed as empty and reported, which is
+/// right for accounts that did not exist yet. A block hash cannot be made up, reading
+/// one the snapshot does not have fails the execution.
+struct SnapshotDb {
+    cache: CacheDB<EmptyDB>,
+    missing_accounts: BTreeSet<Address>,
+}
+
+impl Database for SnapshotDb {
+    type Error = String;
+
+    fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        if !self.cache.accounts.contains_key(&address) {
+            self.missing_accounts.insert(address);
+        }
+        Ok(self.cache.basic(address).unwrap_or_default())
+    }
+
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        Ok(self.cache.code_by_hash(code_hash).unwrap_or_default())
+    }
+
+    fn storage(&mut self, address: Address, index: RevmU256) -> Result<RevmU256, Self::Error> {
+        Ok(self.cache.storage(address, index).unwrap_or_default())
+    }
+
+    fn block_hash(&mut self, number: RevmU256) -> Result<B256, Self::Error> {
+        self.cache
+            .block_hashes
+            .get(&number)
+            .copied()
+            .ok_or_else(|| format!("BLOCKHASH of block {} is not in the snapshot's blockHashes", number))
+    }
+}
+
+/// Inspector turning every interpreter step into a [`TraceStep`]
+struct StepRecorder<F> {
+    gas_inspector: GasInspector,
+    on_step: F,
+    with_memory: bool,
+    pending: Option<TraceStep>, // step whose gas cost is known once it finished
+    step_count: usize,
+}
+
+impl<DB: Database, F: FnMut(TraceStep)> Inspector<DB> for StepRecorder<F> {
+    fn initialize_interp(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.gas_inspector.initialize_interp(interp, context);
+    }
+
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.gas_inspector.step(interp, context);
+
+        let opcode = interp.current_opcode();
+        // Calls, logs and hashes always get their memory, it holds the calldata, event data
+        // and the keys mapping slots are derived from
+        let reads_memory = matches!(
+            opcode,
+            opcode::CALL | opcode::CALLCODE | opcode::DELEGATECALL | opcode::STATICCALL | opcode::LOG0..=opcode::LOG4 | opcode::KECCAK256
+        );
+        let memory = (self.with_memory || reads_memory).then(|| {
+            interp
+                .shared_memory
+                .context_memory()
+                .chunks(32)
+                .map(hex::encode)
+                .collect()
+        });
+        self.pending = Some(TraceStep {
+            pc: Some(container_pc(interp)),
+            op: Some(
+                OpCode::new(opcode)
+                    .map(|op| op.as_str().to_string())
+                    .unwrap_or_else(|| format!("opcode 0x{:02x} not defined", opcode)),
+            ),
+            gas: Some(interp.gas.remaining()),
+            gas_cost: None,
+            depth: Some(context.journaled_state.depth()),
+            error: None,
+            stack: Some(interp.stack.data().iter().map(|value| format!("{:#x}", from_revm_u256(*value))).collect()),
+            memory,
+            storage: None,
+            address: Some(H160::from_slice(interp.contract.target_address.as_slice())),
+        });
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.gas_inspector.step_end(interp, context);
+
+        if let Some(mut step) = self.pending.take() {
+            step.gas_cost = Some(self.gas_inspector.last_gas_cost());
+            if interp.instruction_result.is_error() {
+                step.error = Some(format!("{:?}", interp.instruction_result));
+            }
+            (self.on_step)(step);
+            self.step_count += 1;
+        }
+    }
+
+    fn call_end(&mut self, context: &mut EvmContext<DB>, inputs: &CallInputs, outcome: CallOutcome) -> CallOutcome {
+        self.gas_inspector.call_end(context, inputs, outcome)
+    }
+
+    fn create_end(&mut self, context: &mut EvmContext<DB>, inputs: &CreateInputs, outcome: CreateOutcome) -> CreateOutcome {
+        self.gas_inspector.create_end(context, inputs, outcome)
+    }
+}
+
+/// Pc of the current instruction, as an offset into the whole container for EOF code
+/// so that it matches the blocks of [`eof::disassemble_container`]
+fn container_pc(interp: &Interpreter) -> u16 {
+    let pc = interp.program_counter();
+    let Some(container) = interp.contract.bytecode.eof() else {
+        return pc as u16;
+    };
+    let section = interp.function_stack.current_code_idx;
+    let section_offset = container.header.size()
+        + container.header.types_size as usize
+        + container.header.code_sizes[..section].iter().map(|size| *size as usize).sum::<usize>();
+    (section_offset + pc) as u16
+}
+
+fn to_address(address: H160) -> Address {
+    Address::from(address.0)
+}
+
+fn to_revm_u256(value: U256) -> RevmU256 {
+    let mut word = [0u8; 32];
+    value.to_big_endian(&mut word);
+    RevmU256::from_be_bytes(word)
+}
+
+fn from_revm_u256(value: RevmU256) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CONTRACT: &str = "0x000000000000000000000000000000000000c0de";
+    const MISSING: &str = "0x000000000000000000000000000000000000dead";
+
+    /// A call to a contract returning the hash of the previous block and storage slot 1,
+    /// after reading the balance of an account the prestate does not have
+    fn snapshot() -> ExecutionSnapshot {
+        let code = concat!(
+            "0x",
+            "6001430340", // PUSH1 1 NUMBER SUB BLOCKHASH
+            "5f52",       // PUSH0 MSTORE
+            "600154",     // PUSH1 1 SLOAD
+            "602052",     // PUSH1 0x20 MSTORE
+            "61dead3150", // PUSH2 0xdead BALANCE POP
+            "60405ff3",   // PUSH1 0x40 PUSH0 RETURN
+        );
+        serde_json::from_value(json!({
+            "transaction": {
+                "from": "0x00000000000000000000000000000000000000aa",
+                "to": CONTRACT,
+                "gas": "0x186a0",
+                "gasPrice": "0x0",
+                "chainId": "0x1",
+            },
+            "block": {
+                "number": "0x64",
+                "timestamp": "0x6553f100",
+                "miner": "0x00000000000000000000000000000000000000cb",
+                "gasLimit": "0x1c9c380",
+                "baseFeePerGas": "0x0",
+            },
+            "prestate": {
+                "0x00000000000000000000000000000000000000aa": { "balance": "0xde0b6b3a7640000", "nonce": 0 },
+                "0x00000000000000000000000000000000000000cb": { "balance": "0x0" },
+                CONTRACT: {
+                    "balance": "0x0",
+                    "code": code,
+                    "storage": {
+                        "0x0000000000000000000000000000000000000000000000000000000000000001":
+                            "0x000000000000000000000000000000000000000000000000000000000000002a",
+                    },
+                },
+            },
+            "blockHashes": {
+                "99": "0x1111111111111111111111111111111111111111111111111111111111111111",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn executes_snapshot_with_prestate_and_block_hashes() {
+        let mut steps = Vec::new();
+        let outcome = execute(&snapshot(), SpecId::CANCUN, false, |step| steps.push(step)).unwrap();
+
+        assert!(outcome.success);
+        let expected = [[0x11; 32], H256::from_low_u64_be(0x2a).0].concat();
+        assert_eq!(outcome.output.as_ref(), expected.as_slice());
+        assert_eq!(outcome.missing_accounts, vec![MISSING.parse::<H160>().unwrap()]);
+
+        assert_eq!(outcome.step_count, 16);
+        assert_eq!(steps.len(), 16);
+        let ops: Vec<&str> = steps.iter().filter_map(|step| step.op.as_deref()).collect();
+        assert_eq!(&ops[..4], ["PUSH1", "NUMBER", "SUB", "BLOCKHASH"]);
+        assert_eq!(steps[4].stack.as_deref(), Some(&["0x1111111111111111111111111111111111111111111111111111111111111111".to_string()][..]));
+        assert!(steps.iter().all(|step| step.address == Some(CONTRACT.parse().unwrap()) && step.depth == Some(1)));
+    }
+
+    #[test]
+    fn fails_on_block_hash_missing_from_snapshot() {
+        let mut snapshot = snapshot();
+        snapshot.block_hashes.clear();
+        let error = execute(&snapshot, SpecId::CANCUN, false, |_| {}).unwrap_err();
+        assert!(error.to_string().contains("BLOCKHASH of block 99"), "{}", error);
+    }
+}
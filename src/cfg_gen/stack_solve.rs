@@ -160,7 +160,7 @@ impl EdgeStack {
         }
 
         // now sort the exit_pos_vec by the stack_exit_pos
-        exit_pos_vec.sort_by(|a, b| a.1.cmp(&b.1));
+        exit_pos_vec.sort_by_key(|a| a.1);
         // convert the exit_pos_vec to a stack_pos and stack_items
         let mut new_stack_pos = 0u128;
         let mut new_stack_items = [0; TRACKED_ITEM_COUNT];
@@ -0,0 +1,85 @@
+use crate::cfg_gen::trace::{CallFrame, StorageOp};
+use ethers::types::{H160, H256};
+use std::collections::{BTreeMap, HashMap};
+
+/// A storage access placed in the transaction's timeline of its account
+#[derive(Debug, Clone)]
+pub struct StorageEffect {
+    pub frame_id: usize,
+    pub pc: Option<u16>,
+    pub op: StorageOp,
+    pub slot: H256,
+    pub old_value: Option<H256>, // value before a write, None when it is unknown or for reads
+    pub new_value: Option<H256>, // value read or written
+    pub reverted: bool,          // the frame or one of its callers failed, so a write was undone
+}
+
+/// Slot of a context address, storage and transient storage are separate
+type SlotKey = (H160, bool, H256);
+
+/// Order the storage accesses of all frames by account, with the value each write replaced
+///
+/// A write's old value is the last value the transaction saw in the slot, from an earlier
+/// load or store, or the pre-state when it was never touched before. Values set inside a
+/// frame that later failed are rolled back once that frame is left. Transient storage
+/// always starts out zero.
+pub fn storage_timeline(frames: &[CallFrame], prestate: &HashMap<H160, HashMap<H256, H256>>) -> BTreeMap<H160, Vec<StorageEffect>> {
+    let mut accesses: Vec<_> = frames
+        .iter()
+        .flat_map(|frame| frame.storage_accesses.iter().map(move |access| (frame, access)))
+        .collect();
+    accesses.sort_by_key(|(_, access)| access.step);
+
+    let mut seen: HashMap<SlotKey, Vec<(usize, H256)>> = HashMap::new(); // frame and value, oldest first
+    let mut timeline: BTreeMap<H160, Vec<StorageEffect>> = BTreeMap::new();
+    for (frame, access) in accesses {
+        let key = (frame.context_address, access.op.is_transient(), access.slot);
+        let old_value = if access.op.is_write() {
+            let current = seen.get(&key).and_then(|values| {
+                values
+                    .iter()
+                    .rev()
+                    .find(|(seen_by, _)| !rolled_back(frames, *seen_by, frame.id))
+                    .map(|(_, value)| *value)
+            });
+            current.or_else(|| {
+                if access.op.is_transient() {
+                    Some(H256::zero())
+                } else {
+                    prestate.get(&frame.context_address).and_then(|slots| slots.get(&access.slot).copied())
+                }
+            })
+        } else {
+            None
+        };
+        if let Some(value) = access.value {
+            seen.entry(key).or_default().push((frame.id, value));
+        }
+
+        timeline.entry(frame.context_address).or_default().push(StorageEffect {
+            frame_id: frame.id,
+            pc: access.pc,
+            op: access.op,
+            slot: access.slot,
+            old_value,
+            new_value: access.value,
+            reverted: !frames_to_root(frames, frame.id).all(|id| frames[id].succeeded()),
+        });
+    }
+    timeline
+}
+
+/// Whether a value seen by one frame was undone by the time another frame accesses the slot
+///
+/// That is the case when a frame around the first one failed and the second one runs
+/// outside of it, since frames nest the failed frame has already ended.
+fn rolled_back(frames: &[CallFrame], seen_by: usize, accessed_by: usize) -> bool {
+    frames_to_root(frames, seen_by)
+        .filter(|id| !frames[*id].succeeded())
+        .any(|failed| !frames_to_root(frames, accessed_by).any(|id| id == failed))
+}
+
+/// A frame followed by its callers up to the transaction's root frame
+fn frames_to_root(frames: &[CallFrame], frame_id: usize) -> impl Iterator<Item = usize> + '_ {
+    std::iter::successors(Some(frame_id), |id| frames[*id].parent)
+}
@@ -1,6 +1,10 @@
-use ethers::types::H160;
+use ethers::types::{BigEndianHash, Bytes, H160, H256, U256};
+use ethers::utils::keccak256;
+use serde::de::{DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::ops::Range;
 use std::str::FromStr;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -15,7 +19,9 @@ pub struct TraceStep {
     pub stack: Option<Vec<String>>,
     pub memory: Option<Vec<String>>,
     pub storage: Option<HashMap<String, String>>,
-    pub address: Option<HashMap<String, u8>>,
+    /// Storage context of the step, the tracer emits `0x` hex but older traces use a `{"0":182,...}` byte map
+    #[serde(default, deserialize_with = "deserialize_step_address")]
+    pub address: Option<H160>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,24 +35,14 @@ pub struct TraceTransaction {
 }
 
 impl TraceStep {
-    /// Convert address field (HashMap) to a hex string starting with 0x
+    /// Get the address as a hex string starting with 0x
     pub fn address_hex(&self) -> Option<String> {
-        self.address.as_ref().map(|map| {
-            let mut bytes: Vec<u8> = vec![];
-            for i in 0..map.len() {
-                if let Some(b) = map.get(&i.to_string()) {
-                    bytes.push(*b);
-                }
-            }
-            format!("0x{}", hex::encode(bytes))
-        })
+        self.address.map(|address| format!("{:?}", address))
     }
 
     /// Get address in H160 format
     pub fn get_h160_address(&self) -> Option<H160> {
-        self.address_hex().and_then(|addr_hex| {
-            H160::from_str(&addr_hex).ok()
-        })
+        self.address
     }
     
     /// Determine if this step is a contract call
@@ -74,44 +70,376 @@ impl TraceStep {
             _ => return None,
         };
         
-        self.stack.as_ref().and_then(|stack| {
-            if stack.len() > stack_pos {
-                let addr_hex = &stack[stack.len() - 1 - stack_pos]; // Stack is read from right to left
-                if addr_hex.starts_with("0x") {
-                    H160::from_str(addr_hex).ok()
-                } else {
-                    H160::from_str(&format!("0x{}", addr_hex)).ok()
-                }
-            } else {
-                None
-            }
+        // Stack values drop leading zeros, so go through U256 to keep addresses like 0x0000...0001 intact
+        self.stack_item(stack_pos).map(|value| {
+            let mut word = [0u8; 32];
+            value.to_big_endian(&mut word);
+            H160::from_slice(&word[12..])
         })
     }
     
+    /// Determine if this step creates a contract
+    pub fn is_create(&self) -> bool {
+        matches!(self.op.as_deref(), Some("CREATE") | Some("CREATE2"))
+    }
+    
     /// Get call type
     pub fn get_call_type(&self) -> Option<String> {
         self.op.clone()
     }
+    
+    /// Get the `n`th stack item counting from the top of the stack (0 is the top)
+    pub fn stack_item(&self, n: usize) -> Option<U256> {
+        let stack = self.stack.as_ref()?;
+        if stack.len() <= n {
+            return None;
+        }
+        let item = &stack[stack.len() - 1 - n]; // Stack is read from right to left
+        U256::from_str_radix(item.trim_start_matches("0x"), 16).ok()
+    }
+    
+    /// Read `size` bytes of memory starting at `offset`
+    ///
+    /// Bytes past the end of the recorded memory read as zero, matching EVM semantics.
+    /// Returns `None` when the trace was captured without memory.
+    pub fn read_memory(&self, offset: usize, size: usize) -> Option<Vec<u8>> {
+        let memory = self.memory.as_ref()?;
+        let mut result = vec![0u8; size];
+        if size == 0 {
+            return Some(result);
+        }
+        
+        // Only decode the 32-byte words overlapping the window
+        let end = offset + size;
+        let words = memory.iter().enumerate().take((end - 1) / 32 + 1).skip(offset / 32);
+        for (index, word) in words {
+            let word = hex::decode(word.trim_start_matches("0x")).ok()?;
+            let word_start = index * 32;
+            let (from, to) = (offset.max(word_start), end.min(word_start + word.len()));
+            if from < to {
+                result[from - offset..to - offset].copy_from_slice(&word[from - word_start..to - word_start]);
+            }
+        }
+        Some(result)
+    }
+    
+    /// Read the memory region described by the offset/size pair at stack positions `offset_pos` and `offset_pos + 1`
+    pub fn read_memory_from_stack(&self, offset_pos: usize) -> Option<Vec<u8>> {
+        let offset = self.stack_item(offset_pos)?;
+        let size = self.stack_item(offset_pos + 1)?;
+        if size.is_zero() {
+            return Some(Vec::new());
+        }
+        // Anything this large would have run out of gas, the trace must be inconsistent
+        if offset > U256::from(u32::MAX) || size > U256::from(MAX_MEMORY_READ) {
+            return None;
+        }
+        self.read_memory(offset.as_usize(), size.as_usize())
+    }
+    
+    /// Get the initcode passed to CREATE/CREATE2, if memory was traced
+    pub fn get_create_initcode(&self) -> Option<Bytes> {
+        if !self.is_create() {
+            return None;
+        }
+        // CREATE: [value, offset, size], CREATE2: [value, offset, size, salt]
+        self.read_memory_from_stack(1).map(Bytes::from)
+    }
+    
+    /// Get the calldata passed to CALL/CALLCODE/DELEGATECALL/STATICCALL, if memory was traced
+    pub fn get_call_input(&self) -> Option<Bytes> {
+        // CALL/CALLCODE: [gas, address, value, argsOffset, argsLength, ...]
+        // DELEGATECALL/STATICCALL: [gas, address, argsOffset, argsLength, ...]
+        let offset_pos = match self.op.as_deref() {
+            Some("CALL") | Some("CALLCODE") => 3,
+            Some("DELEGATECALL") | Some("STATICCALL") => 2,
+            _ => return None,
+        };
+        self.read_memory_from_stack(offset_pos).map(Bytes::from)
+    }
+    
+    /// Get the ETH value sent by a call or creation
+    pub fn get_call_value(&self) -> Option<U256> {
+        match self.op.as_deref() {
+            Some("CALL") | Some("CALLCODE") => self.stack_item(2),
+            Some("CREATE") | Some("CREATE2") => self.stack_item(0),
+            Some("STATICCALL") => Some(U256::zero()),
+            _ => None, // DELEGATECALL keeps the caller's value, nothing is transferred
+        }
+    }
+    
+    /// Get the topics of a LOG0..LOG4 and its data (when memory was traced)
+    pub fn get_log(&self) -> Option<EmittedLog> {
+        let topic_count = match self.op.as_deref()? {
+            "LOG0" => 0,
+            "LOG1" => 1,
+            "LOG2" => 2,
+            "LOG3" => 3,
+            "LOG4" => 4,
+            _ => return None,
+        };
+        // LOGn: [offset, size, topic0, ..., topicn-1]
+        let topics = (0..topic_count)
+            .map(|i| self.stack_item(2 + i).map(|topic| H256::from_uint(&topic)))
+            .collect::<Option<Vec<_>>>()?;
+        Some(EmittedLog {
+            pc: self.pc,
+            topics,
+            data: self.read_memory_from_stack(0).map(Bytes::from),
+        })
+    }
+    
+    /// Get the slot accessed by SLOAD/SSTORE/TLOAD/TSTORE and the value written
+    ///
+    /// The value of a load is taken from the `storage` map geth records at the step, when
+    /// there is none it is only known once the next step shows it on the stack.
+    pub fn get_storage_access(&self, step: usize) -> Option<StorageAccess> {
+        let op = match self.op.as_deref()? {
+            "SLOAD" => StorageOp::Sload,
+            "SSTORE" => StorageOp::Sstore,
+            "TLOAD" => StorageOp::Tload,
+            "TSTORE" => StorageOp::Tstore,
+            _ => return None,
+        };
+        // Failed accesses (out of gas, SSTORE in a static call) had no effect
+        if self.error.is_some() {
+            return None;
+        }
+        // SLOAD/TLOAD: [key], SSTORE/TSTORE: [key, value]
+        let slot = H256::from_uint(&self.stack_item(0)?);
+        let value = if op.is_write() {
+            Some(H256::from_uint(&self.stack_item(1)?))
+        } else if op == StorageOp::Sload {
+            self.storage_value(&slot)
+        } else {
+            None
+        };
+        Some(StorageAccess {
+            step,
+            pc: self.pc,
+            op,
+            slot,
+            value,
+        })
+    }
+    
+    /// Value of a slot in the `storage` map geth records for SLOAD and SSTORE steps
+    fn storage_value(&self, slot: &H256) -> Option<H256> {
+        self.storage.as_ref()?.iter().find_map(|(key, value)| {
+            let key = H256::from_str(key.trim_start_matches("0x")).ok()?;
+            if &key != slot {
+                return None;
+            }
+            H256::from_str(value.trim_start_matches("0x")).ok()
+        })
+    }
+    
+    /// Get the input hashed by KECCAK256 together with its hash, if memory was traced
+    ///
+    /// Only inputs up to [`MAX_PREIMAGE_LEN`] bytes are read, storage locations hash a
+    /// mapping key and a slot, anything longer is data being hashed.
+    pub fn get_keccak_preimage(&self) -> Option<(H256, Bytes)> {
+        if !matches!(self.op.as_deref(), Some("KECCAK256" | "SHA3")) || self.error.is_some() {
+            return None;
+        }
+        // KECCAK256: [offset, size]
+        if self.stack_item(1)? > U256::from(MAX_PREIMAGE_LEN) {
+            return None;
+        }
+        let input = self.read_memory_from_stack(0)?;
+        Some((H256(keccak256(&input)), Bytes::from(input)))
+    }
+    
+    /// Get the data returned by RETURN/REVERT, if memory was traced
+    pub fn get_return_data(&self) -> Option<Bytes> {
+        match self.op.as_deref() {
+            // RETURN/REVERT: [offset, size]
+            Some("RETURN") | Some("REVERT") => self.read_memory_from_stack(0).map(Bytes::from),
+            _ => None,
+        }
+    }
+    
+    /// Whether the instruction transfers control within the frame, so the next step starts a new block
+    pub fn ends_block_by_jump(&self) -> bool {
+        matches!(
+            self.op.as_deref(),
+            Some("JUMP" | "JUMPI" | "RJUMP" | "RJUMPI" | "RJUMPV" | "CALLF" | "RETF" | "JUMPF")
+        )
+    }
+    
+    /// Size of the data handed back to the caller when this step ends a frame
+    ///
+    /// Only RETURN and REVERT return data, anything else that ends a frame (STOP,
+    /// SELFDESTRUCT, INVALID, an exceptional halt) leaves the return data empty.
+    pub fn get_return_data_size(&self) -> Option<usize> {
+        match self.op.as_deref() {
+            Some("RETURN") | Some("REVERT") if self.error.is_none() => {
+                self.stack_item(1).filter(|size| *size <= U256::from(usize::MAX)).map(|size| size.as_usize())
+            }
+            _ => Some(0),
+        }
+    }
+}
+
+/// Parse a step address from either `0x` hex or the legacy byte map keyed by index
+fn deserialize_step_address<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<H160>, D::Error> {
+    deserializer.deserialize_any(StepAddressVisitor)
+}
+
+struct StepAddressVisitor;
+
+impl<'de> Visitor<'de> for StepAddressVisitor {
+    type Value = Option<H160>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a hex address, a byte map keyed by index or null")
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<Option<H160>, E> {
+        Ok(None)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Option<H160>, E> {
+        H160::from_str(value).map(Some).map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Option<H160>, A::Error> {
+        let mut bytes = [0u8; 20];
+        let mut len = 0;
+        while let Some((key, byte)) = map.next_entry::<String, u8>()? {
+            let index: usize = key.parse().map_err(serde::de::Error::custom)?;
+            if index >= bytes.len() {
+                return Err(serde::de::Error::custom(format!("address byte index {} out of range", index)));
+            }
+            bytes[index] = byte;
+            len += 1;
+        }
+        if len == 0 {
+            return Ok(None);
+        }
+        Ok(Some(H160::from(bytes)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Option<H160>, A::Error> {
+        let mut bytes = Vec::with_capacity(20);
+        while let Some(byte) = seq.next_element::<u8>()? {
+            bytes.push(byte);
+        }
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        if bytes.len() != 20 {
+            return Err(serde::de::Error::invalid_length(bytes.len(), &self));
+        }
+        Ok(Some(H160::from_slice(&bytes)))
+    }
 }
 
+/// Largest memory region we will reconstruct from a trace (initcode, return data, calldata)
+pub const MAX_MEMORY_READ: usize = 0x100000;
+
+/// Gas a CALL or CALLCODE transferring value hands the callee on top of what the caller pays
+pub const CALL_STIPEND: u64 = 2300;
+
+/// Longest KECCAK256 input kept as a possible storage location preimage (a string mapping key and a slot)
+pub const MAX_PREIMAGE_LEN: usize = 256;
+
 /// Parse transaction trace file
+///
+/// Collects every step in memory, prefer [`stream_trace_file`] for large traces.
 pub fn parse_trace_file(path: &str) -> eyre::Result<Vec<TraceStep>> {
-    let data = std::fs::read_to_string(path)?;
-    
-    // Try to parse directly as an array of steps
-    let steps_result: Result<Vec<TraceStep>, _> = serde_json::from_str(&data);
-    
-    match steps_result {
-        Ok(steps) => Ok(steps),
-        Err(_) => {
-            // Try to parse as TraceTransaction format
-            let trace: TraceTransaction = serde_json::from_str(&data)?;
-            Ok(trace.struct_logs)
+    let mut steps = Vec::new();
+    stream_trace_file(path, |step| steps.push(step))?;
+    Ok(steps)
+}
+
+/// Stream the steps of a struct log trace file to `on_step` without loading the whole file
+///
+/// Accepts a bare array of steps, a `debug_traceTransaction` result with `structLogs`,
+/// or the full JSON-RPC response wrapping it. Returns the number of steps read.
+pub fn stream_trace_file(path: &str, on_step: impl FnMut(TraceStep)) -> eyre::Result<usize> {
+    let file = std::fs::File::open(path)?;
+    stream_trace(std::io::BufReader::new(file), on_step)
+}
+
+/// Stream the steps of a struct log trace from any reader, see [`stream_trace_file`]
+pub fn stream_trace(reader: impl std::io::Read, on_step: impl FnMut(TraceStep)) -> eyre::Result<usize> {
+    stream_trace_as(reader, on_step)
+}
+
+/// Stream the steps of a struct log trace as any type, e.g. [`serde_json::value::RawValue`]
+/// to keep each step exactly as the node sent it
+///
+/// A JSON-RPC error response is returned as an error.
+pub fn stream_trace_as<T: DeserializeOwned>(reader: impl std::io::Read, mut on_step: impl FnMut(T)) -> eyre::Result<usize> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let mut count = 0;
+    let found = StepSeed { on_step: &mut on_step, count: &mut count, step: PhantomData }.deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    if !found {
+        return Err(eyre::eyre!("No struct log steps found in trace"));
+    }
+    Ok(count)
+}
+
+/// Walks the JSON document and hands every step to the callback as soon as it is parsed.
+/// Deserializes to whether a step array was found.
+struct StepSeed<'a, T, F> {
+    on_step: &'a mut F,
+    count: &'a mut usize,
+    step: PhantomData<fn(T)>,
+}
+
+impl<'de, T: DeserializeOwned, F: FnMut(T)> DeserializeSeed<'de> for StepSeed<'_, T, F> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, T: DeserializeOwned, F: FnMut(T)> Visitor<'de> for StepSeed<'_, T, F> {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an array of struct log steps or an object containing one")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<bool, A::Error> {
+        while let Some(step) = seq.next_element::<T>()? {
+            (self.on_step)(step);
+            *self.count += 1;
         }
+        Ok(true)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<bool, A::Error> {
+        let mut found = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "structLogs" | "result" if !found => {
+                    found = map.next_value_seed(StepSeed { on_step: &mut *self.on_step, count: &mut *self.count, step: PhantomData })?;
+                }
+                // Only a JSON-RPC response has an error object here, a step's own `error` is a string
+                "error" => {
+                    let error: serde_json::Value = map.next_value()?;
+                    if let Some(message) = error.get("message").and_then(|message| message.as_str()) {
+                        return Err(serde::de::Error::custom(format!("RPC error: {}", message)));
+                    }
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(found)
     }
 }
 
 /// Extract all contract addresses involved in the trace
+///
+/// These are the addresses reported by the tracer, i.e. storage contexts. Use
+/// [`extract_code_addresses`] to get the addresses whose bytecode actually ran.
 pub fn extract_contract_addresses(steps: &[TraceStep]) -> HashSet<H160> {
     let mut addresses = HashSet::new();
     
@@ -124,56 +452,487 @@ pub fn extract_contract_addresses(steps: &[TraceStep]) -> HashSet<H160> {
     addresses
 }
 
-/// Extract call relationships from the trace
-pub struct CallEdge {
-    pub from_addr: H160,
-    pub from_pc: u16,
-    pub to_addr: H160,
-    pub call_type: String,
+/// Which of an account's two pieces of code a frame executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CodeKind {
+    Runtime, // deployed code
+    Init,    // constructor code run by CREATE/CREATE2
 }
 
-pub fn extract_call_edges(steps: &[TraceStep]) -> Vec<CallEdge> {
-    let mut edges = Vec::new();
-    let mut i = 0;
-    
-    while i < steps.len() - 1 {
-        let current_step = &steps[i];
-        let next_step = &steps[i + 1];
+/// Identifies a piece of bytecode a CFG is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CodeId {
+    pub address: H160,
+    pub kind: CodeKind,
+}
+
+impl CodeId {
+    pub fn runtime(address: H160) -> Self {
+        Self { address, kind: CodeKind::Runtime }
+    }
+
+    pub fn init(address: H160) -> Self {
+        Self { address, kind: CodeKind::Init }
+    }
+
+    /// File name (without extension) used when saving this code's CFG
+    pub fn file_stem(&self) -> String {
+        match self.kind {
+            CodeKind::Runtime => format!("{:x}", self.address),
+            CodeKind::Init => format!("{:x}_initcode", self.address),
+        }
+    }
+}
+
+/// An event emitted by a frame
+#[derive(Debug, Clone)]
+pub struct EmittedLog {
+    pub pc: Option<u16>, // pc of the LOG instruction, unknown for callTracer logs
+    pub topics: Vec<H256>,
+    pub data: Option<Bytes>, // None when the trace has no memory
+}
+
+/// Kind of storage access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageOp {
+    Sload,
+    Sstore,
+    Tload,  // transient storage (EIP-1153), cleared after the transaction
+    Tstore,
+}
+
+impl StorageOp {
+    pub fn is_write(&self) -> bool {
+        matches!(self, StorageOp::Sstore | StorageOp::Tstore)
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, StorageOp::Tload | StorageOp::Tstore)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageOp::Sload => "SLOAD",
+            StorageOp::Sstore => "SSTORE",
+            StorageOp::Tload => "TLOAD",
+            StorageOp::Tstore => "TSTORE",
+        }
+    }
+}
+
+/// A storage or transient storage access made by a frame, on its context address
+#[derive(Debug, Clone)]
+pub struct StorageAccess {
+    pub step: usize, // position in the trace, orders the accesses of different frames
+    pub pc: Option<u16>,
+    pub op: StorageOp,
+    pub slot: H256,
+    pub value: Option<H256>, // value loaded or stored, None when the trace does not show it
+}
+
+/// A single execution of contract code within the transaction.
+///
+/// Frames are delimited by `depth` transitions in the trace, so a contract that is
+/// entered several times (e.g. re-entered during a flash loan callback) gets one
+/// frame per entry instead of having all executions merged together.
+///
+/// For DELEGATECALL and CALLCODE the code that runs and the account whose storage
+/// it touches differ, so both are tracked separately.
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub id: usize,
+    pub parent: Option<usize>,
+    pub call_path: Vec<usize>, // child index at every level, root frame is [0]
+    pub depth: u64,
+    pub code_address: H160,    // account whose bytecode is executing
+    pub context_address: H160, // account whose storage and balance are used
+    pub code_kind: CodeKind,
+    pub call_type: String,     // opcode that created this frame, root frame is "CALL"
+    pub call_pc: Option<u16>,  // pc of the call instruction in the parent frame
+    pub children: Vec<usize>,
+    pub step_ranges: Vec<Range<usize>>, // trace step indices executed by this frame
+    pub executed_pcs: HashSet<u16>,
+    pub block_entries: Vec<u16>,       // pc at which every basic block visit started, in execution order
+    pub gas_by_pc: HashMap<u16, u64>,  // gas spent at each pc over all its executions, forwarded gas excluded
+    pub gas_used: u64,                 // gas spent by the frame's own instructions, children excluded
+    pub step_count: usize,
+    pub initcode: Option<Bytes>,       // code run by a CREATE/CREATE2 frame, recovered from the creator's memory
+    pub deployed_code: Option<Bytes>,  // runtime code RETURNed by a CREATE/CREATE2 frame
+    pub error: Option<String>,         // why the frame failed (revert, out of gas, ...)
+    pub input: Option<Bytes>,          // calldata, recovered from the caller's memory for struct log traces
+    pub value: Option<U256>,           // ETH sent along, None for DELEGATECALL
+    pub gas: Option<U256>,             // gas made available to the frame
+    pub logs: Vec<EmittedLog>,         // events emitted by the frame itself, in order
+    pub storage_accesses: Vec<StorageAccess>, // SLOAD/SSTORE/TLOAD/TSTORE of the frame itself, in order
+    pub hash_preimages: HashMap<H256, Bytes>, // short KECCAK256 inputs by hash, where mapping slots come from
+    pub exit_pc: Option<u16>,          // pc of the instruction that ended the frame
+    pub return_pc: Option<u16>,        // pc the parent frame resumed at once this frame ended
+    pub return_data_size: Option<usize>, // bytes handed back to the caller
+}
+
+impl CallFrame {
+    /// Call path in dotted form, e.g. `0.2.1` is the second call made by the third call of the root frame
+    pub fn call_path_str(&self) -> String {
+        self.call_path.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(".")
+    }
+
+    /// Whether the code runs on another account's storage (DELEGATECALL, CALLCODE)
+    pub fn is_delegated(&self) -> bool {
+        self.code_address != self.context_address
+    }
+
+    /// Gas spent by the frame and everything it called
+    pub fn inclusive_gas(&self, frames: &[CallFrame]) -> u64 {
+        self.gas_used + self.children.iter().map(|child| frames[*child].inclusive_gas(frames)).sum::<u64>()
+    }
+
+    /// Free gas the frame started with on top of what its caller paid for, see [`CALL_STIPEND`]
+    pub fn stipend(&self) -> u64 {
+        let transfers_value = self.value.is_some_and(|value| !value.is_zero());
+        match self.call_type.as_str() {
+            "CALL" | "CALLCODE" if transfers_value => CALL_STIPEND,
+            _ => 0,
+        }
+    }
+
+    /// Whether the frame returned normally, i.e. its state changes were kept
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Take the gas a call or create handed to its child frame out of the step's cost
+    ///
+    /// `forwarded` excludes the stipend, which the caller never paid for. Only done when
+    /// the reported cost covers the forwarded gas: geth includes it for calls but not for
+    /// creations, revm for both.
+    fn exclude_forwarded_gas(&mut self, call_step: &TraceStep, forwarded: u64) {
+        if let Some(gas_cost) = call_step.gas_cost.filter(|gas_cost| *gas_cost >= forwarded) {
+            self.replace_step_gas(call_step, gas_cost - forwarded);
+        }
+    }
+
+    /// Correct the cost already recorded for a step
+    fn replace_step_gas(&mut self, step: &TraceStep, gas: u64) {
+        if let (Some(pc), Some(gas_cost)) = (step.pc, step.gas_cost) {
+            let pc_gas = self.gas_by_pc.entry(pc).or_default();
+            *pc_gas = pc_gas.saturating_sub(gas_cost) + gas;
+            self.gas_used = self.gas_used.saturating_sub(gas_cost) + gas;
+        }
+    }
+
+    /// The bytecode this frame executed
+    pub fn code_id(&self) -> CodeId {
+        CodeId {
+            address: self.code_address,
+            kind: self.code_kind,
+        }
+    }
+}
+
+/// Segment the trace into call frames using `depth` transitions
+pub fn extract_call_frames(steps: &[TraceStep]) -> Vec<CallFrame> {
+    let mut builder = CallFrameBuilder::new();
+    for step in steps {
+        builder.push(step.clone());
+    }
+    builder.finish()
+}
+
+/// Folds trace steps into call frames one step at a time.
+///
+/// Only the currently open frames and the previous step (the call that opens a new
+/// frame) are kept around, so the trace itself never has to be held in memory.
+#[derive(Debug, Default)]
+pub struct CallFrameBuilder {
+    frames: Vec<CallFrame>,
+    active: Vec<usize>, // stack of currently open frame ids
+    root_count: usize,
+    step_index: usize,
+    prev_step: Option<TraceStep>,
+    prev_frame: Option<usize>, // frame that executed `prev_step`
+}
+
+impl CallFrameBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of steps folded so far
+    pub fn step_count(&self) -> usize {
+        self.step_index
+    }
+
+    /// Fold the next step of the trace
+    pub fn push(&mut self, step: TraceStep) {
+        let i = self.step_index;
+        let depth = step.depth.unwrap_or(1);
+        let frames = &mut self.frames;
+
+        // Returning from calls: close every frame deeper than this step
+        let mut returned = None;
+        while let Some(&top) = self.active.last() {
+            if frames[top].depth > depth {
+                self.active.pop();
+                returned = Some(top);
+            } else {
+                break;
+            }
+        }
+        // The frame returned to directly resumes after its call instruction
+        if let Some(returned) = returned {
+            frames[returned].return_pc = step.pc;
+        }
+
+        let current = match self.active.last() {
+            Some(&top) if frames[top].depth == depth => top,
+            parent => {
+                // Entering a new frame, the previous step is the call that created it
+                let parent = parent.copied();
+                let call_step = if parent.is_some() { self.prev_step.as_ref() } else { None };
+
+                let call_path = match parent {
+                    Some(parent_id) => {
+                        let mut path = frames[parent_id].call_path.clone();
+                        path.push(frames[parent_id].children.len());
+                        path
+                    }
+                    None => {
+                        self.root_count += 1;
+                        vec![self.root_count - 1]
+                    }
+                };
+
+                // The tracer reports the storage context, the call target on the
+                // caller's stack is the code address
+                let call_target = call_step.and_then(|s| s.get_call_target());
+                let delegated = matches!(
+                    call_step.and_then(|s| s.op.as_deref()),
+                    Some("DELEGATECALL") | Some("CALLCODE")
+                );
+                let context_address = step
+                    .get_h160_address()
+                    .or_else(|| match parent {
+                        Some(parent_id) if delegated => Some(frames[parent_id].context_address),
+                        _ => call_target,
+                    })
+                    .unwrap_or_default();
+                let code_address = call_target.unwrap_or(context_address);
+                let is_create = call_step.map(|s| s.is_create()).unwrap_or(false);
+
+                let id = frames.len();
+                frames.push(CallFrame {
+                    id,
+                    parent,
+                    call_path,
+                    depth,
+                    code_address,
+                    context_address,
+                    code_kind: if is_create { CodeKind::Init } else { CodeKind::Runtime },
+                    call_type: call_step
+                        .and_then(|s| s.get_call_type())
+                        .unwrap_or_else(|| "CALL".to_string()),
+                    call_pc: call_step.and_then(|s| s.pc),
+                    children: Vec::new(),
+                    step_ranges: Vec::new(),
+                    executed_pcs: HashSet::new(),
+                    block_entries: Vec::new(),
+                    gas_by_pc: HashMap::new(),
+                    gas_used: 0,
+                    step_count: 0,
+                    initcode: call_step.and_then(|s| s.get_create_initcode()),
+                    deployed_code: None,
+                    error: None,
+                    input: call_step.and_then(|s| s.get_call_input()),
+                    value: call_step.and_then(|s| s.get_call_value()),
+                    // What the child starts out with, not the requested amount on the caller's stack
+                    gas: step.gas.map(U256::from),
+                    logs: Vec::new(),
+                    storage_accesses: Vec::new(),
+                    hash_preimages: HashMap::new(),
+                    exit_pc: None,
+                    return_pc: None,
+                    return_data_size: None,
+                });
+                if let Some(parent_id) = parent {
+                    frames[parent_id].children.push(id);
+                    // The call's cost includes the gas it forwarded, which the child accounts for itself
+                    if let (Some(call_step), Some(gas)) = (call_step, step.gas) {
+                        let forwarded = gas.saturating_sub(frames[id].stipend());
+                        frames[parent_id].exclude_forwarded_gas(call_step, forwarded);
+                    }
+                }
+                self.active.push(id);
+                id
+            }
+        };
+
+        let frame = &mut frames[current];
+        match frame.step_ranges.last_mut() {
+            Some(range) if range.end == i => range.end = i + 1,
+            _ => frame.step_ranges.push(i..i + 1),
+        }
+        if let Some(pc) = step.pc {
+            frame.executed_pcs.insert(pc);
+            // A block starts at the frame's first step, at a JUMPDEST and after a jump, the
+            // instruction after a call belongs to the block the call is in
+            let after_jump = self.prev_frame == Some(current)
+                && self.prev_step.as_ref().is_some_and(|prev| prev.ends_block_by_jump());
+            if frame.step_count == 0 || after_jump || step.op.as_deref() == Some("JUMPDEST") {
+                frame.block_entries.push(pc);
+            }
+        }
+        frame.step_count += 1;
         
-        if current_step.is_contract_call() {
-            if let (Some(from_addr), Some(from_pc), Some(call_type)) = (
-                current_step.get_h160_address(),
-                current_step.pc,
-                current_step.get_call_type()
-            ) {
-                if let Some(to_addr) = next_step.get_h160_address() {
-                    edges.push(CallEdge {
-                        from_addr,
-                        from_pc,
-                        to_addr,
-                        call_type,
-                    });
+        // A call that entered no frame (precompile, account without code) cost what the
+        // remaining gas dropped by, its reported cost may include gas it never used
+        if let (Some(prev), true) = (self.prev_step.as_ref(), self.prev_frame == Some(current)) {
+            if prev.is_contract_call() || prev.is_create() {
+                if let (Some(before), Some(after)) = (prev.gas, step.gas) {
+                    frame.replace_step_gas(prev, before.saturating_sub(after));
                 }
             }
         }
+        if let (Some(pc), Some(gas_cost)) = (step.pc, step.gas_cost) {
+            *frame.gas_by_pc.entry(pc).or_default() += gas_cost;
+            frame.gas_used += gas_cost;
+        }
+        
+        // Every step may be the frame's last, the one before a return to the caller
+        frame.exit_pc = step.pc;
+        frame.return_data_size = step.get_return_data_size();
+        
+        if step.error.is_some() {
+            frame.error = step.error.clone();
+        } else if step.op.as_deref() == Some("REVERT") {
+            frame.error = Some("execution reverted".to_string());
+        }
+        
+        if let Some(log) = step.get_log() {
+            frame.logs.push(log);
+        }
         
-        i += 1;
+        // A load the trace showed no value for left it on top of the stack
+        if self.prev_frame == Some(current) {
+            if let Some(access) = frame.storage_accesses.last_mut().filter(|access| access.step + 1 == i) {
+                if access.value.is_none() && !access.op.is_write() {
+                    access.value = step.stack_item(0).map(|value| H256::from_uint(&value));
+                }
+            }
+        }
+        if let Some(access) = step.get_storage_access(i) {
+            frame.storage_accesses.push(access);
+        }
+        if let Some((hash, input)) = step.get_keccak_preimage() {
+            frame.hash_preimages.insert(hash, input);
+        }
+        
+        // The RETURN that ends a constructor hands back the runtime code
+        if frame.code_kind == CodeKind::Init && step.op.as_deref() == Some("RETURN") {
+            frame.deployed_code = step.get_return_data();
+        }
+
+        self.step_index += 1;
+        self.prev_step = Some(step);
+        self.prev_frame = Some(current);
+    }
+
+    /// Finish folding and return the frames in the order they were entered
+    pub fn finish(self) -> Vec<CallFrame> {
+        self.frames
     }
-    
-    edges
 }
 
-/// Filter trace steps by address
-pub fn filter_steps_by_address(steps: &[TraceStep], address: &H160) -> Vec<TraceStep> {
-    steps
+/// Collect the addresses whose runtime bytecode was executed, which is what CFGs are built from
+pub fn extract_code_addresses(frames: &[CallFrame]) -> HashSet<H160> {
+    frames
         .iter()
-        .filter(|step| {
-            if let Some(addr) = step.get_h160_address() {
-                &addr == address
-            } else {
-                false
-            }
+        .filter(|frame| frame.code_kind == CodeKind::Runtime)
+        .map(|frame| frame.code_address)
+        .collect()
+}
+
+/// Collect bytecode recovered from the trace itself: initcode of every CREATE/CREATE2
+/// frame and the runtime code it deployed. Only available when memory was traced.
+pub fn extract_created_code(frames: &[CallFrame]) -> HashMap<CodeId, Bytes> {
+    let mut codes = HashMap::new();
+    for frame in frames.iter().filter(|frame| frame.code_kind == CodeKind::Init) {
+        if let Some(initcode) = &frame.initcode {
+            codes.insert(CodeId::init(frame.code_address), initcode.clone());
+        }
+        if let Some(deployed_code) = &frame.deployed_code {
+            codes.insert(CodeId::runtime(frame.code_address), deployed_code.clone());
+        }
+    }
+    codes
+}
+
+/// Extract call relationships from the trace
+pub struct CallEdge {
+    pub from_addr: H160,    // code address of the caller
+    pub from_pc: Option<u16>, // None when the trace has no opcode-level data (e.g. callTracer)
+    pub from_frame: usize,
+    pub to_addr: H160,      // code address of the callee
+    pub to_context: H160,   // storage context of the callee
+    pub to_frame: usize,
+    pub call_type: String,
+    pub input: Option<Bytes>, // calldata, None when the trace has no memory
+    pub value: Option<U256>,
+    pub gas: Option<U256>,    // gas forwarded to the callee
+    pub exit_pc: Option<u16>,   // instruction that ended the callee
+    pub return_pc: Option<u16>, // where the caller continued after the call
+    pub error: Option<String>,  // why the callee failed
+    pub return_data_size: Option<usize>,
+}
+
+impl CallEdge {
+    /// 4-byte function selector of the calldata, if known
+    pub fn selector(&self) -> Option<[u8; 4]> {
+        if self.call_type.starts_with("CREATE") {
+            return None;
+        }
+        self.input.as_ref()?.get(..4)?.try_into().ok()
+    }
+}
+
+/// Build one call edge for every frame entered from a parent frame
+pub fn extract_call_edges(frames: &[CallFrame]) -> Vec<CallEdge> {
+    frames
+        .iter()
+        .filter_map(|frame| {
+            let parent = &frames[frame.parent?];
+            Some(CallEdge {
+                from_addr: parent.code_address,
+                from_pc: frame.call_pc,
+                from_frame: parent.id,
+                to_addr: frame.code_address,
+                to_context: frame.context_address,
+                to_frame: frame.id,
+                call_type: frame.call_type.clone(),
+                input: frame.input.clone(),
+                value: frame.value,
+                gas: frame.gas,
+                exit_pc: frame.exit_pc,
+                return_pc: frame.return_pc,
+                error: frame.error.clone(),
+                return_data_size: frame.return_data_size,
+            })
         })
+        .collect()
+}
+
+/// Filter trace steps by the address of the code that executed them
+pub fn filter_steps_by_address(steps: &[TraceStep], frames: &[CallFrame], address: &H160) -> Vec<TraceStep> {
+    let mut ranges: Vec<&Range<usize>> = frames
+        .iter()
+        .filter(|frame| &frame.code_address == address)
+        .flat_map(|frame| frame.step_ranges.iter())
+        .collect();
+    // Keep trace order when a contract calls back into itself
+    ranges.sort_by_key(|range| range.start);
+
+    ranges
+        .into_iter()
+        .flat_map(|range| steps[range.clone()].iter())
         .cloned()
         .collect()
 }
@@ -184,4 +943,4 @@ pub fn get_executed_pcs(steps: &[TraceStep]) -> HashSet<u16> {
         .iter()
         .filter_map(|step| step.pc)
         .collect()
-}
\ No newline at end of file
+}
\ No newline at end of file
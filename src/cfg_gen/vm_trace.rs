@@ -0,0 +1,297 @@
+use crate::cfg_gen::opcode;
+use crate::cfg_gen::trace::{CallFrame, CodeId, CodeKind, StorageAccess, StorageOp};
+use ethers::types::{BigEndianHash, Bytes, H160, H256, U256};
+use revm::interpreter::opcode::{
+    CALL, CALLCODE, CREATE, CREATE2, DELEGATECALL, JUMP, JUMPDEST, JUMPI, SELFDESTRUCT, STATICCALL,
+};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/// Result of `trace_replayTransaction` with the `trace` and `vmTrace` trace types
+/// (Erigon, Nethermind, Reth, OpenEthereum)
+#[derive(Debug, Deserialize, Clone)]
+pub struct TraceReplay {
+    pub output: Option<Bytes>,
+    #[serde(default)]
+    pub trace: Vec<ParityTrace>,
+    #[serde(rename = "vmTrace")]
+    pub vm_trace: VmTrace,
+}
+
+/// One entry of the flat `trace` list, located in the call tree by `traceAddress`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ParityTrace {
+    pub action: ParityAction,
+    pub result: Option<ParityResult>,
+    pub error: Option<String>,
+    #[serde(rename = "traceAddress")]
+    pub trace_address: Vec<usize>,
+    #[serde(rename = "type")]
+    pub trace_type: String, // call, create, suicide
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ParityAction {
+    #[serde(rename = "callType")]
+    pub call_type: Option<String>, // call, delegatecall, callcode, staticcall
+    pub from: Option<H160>,
+    pub to: Option<H160>,
+    pub value: Option<U256>,
+    pub gas: Option<U256>,
+    pub input: Option<Bytes>,
+    pub init: Option<Bytes>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ParityResult {
+    #[serde(rename = "gasUsed")]
+    pub gas_used: Option<U256>,
+    pub output: Option<Bytes>,
+    pub address: Option<H160>, // created contract
+    pub code: Option<Bytes>,   // deployed code of the created contract
+}
+
+/// Code executed by one call together with the operations it ran
+#[derive(Debug, Deserialize, Clone)]
+pub struct VmTrace {
+    pub code: Bytes,
+    #[serde(default)]
+    pub ops: Vec<VmOperation>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VmOperation {
+    pub pc: usize,
+    pub cost: u64,
+    pub ex: Option<VmExecutedOperation>,
+    pub sub: Option<VmTrace>, // nested trace of the CALL/CREATE this operation made
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VmExecutedOperation {
+    pub used: u64, // gas remaining after the operation
+    #[serde(default)]
+    pub push: Vec<U256>,
+    pub store: Option<VmStorageDiff>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct VmStorageDiff {
+    pub key: U256,
+    pub val: U256,
+}
+
+/// JSON-RPC response wrapper, for traces saved straight from `trace_replayTransaction`
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: TraceReplay,
+}
+
+/// Frames and the code they ran, both taken from a `vmTrace`
+pub struct VmTraceFrames {
+    pub frames: Vec<CallFrame>,
+    pub code: HashMap<CodeId, Bytes>, // embedded code of every frame, no RPC fetch needed
+    pub step_count: usize,            // operations of all frames, in execution order
+}
+
+/// Parse a `trace_replayTransaction` result, either bare or wrapped in a JSON-RPC response
+pub fn parse_trace_replay(data: &str) -> eyre::Result<TraceReplay> {
+    match serde_json::from_str::<TraceReplay>(data) {
+        Ok(replay) => Ok(replay),
+        Err(_) => {
+            let response: RpcResponse = serde_json::from_str(data)?;
+            Ok(response.result)
+        }
+    }
+}
+
+/// Parse a `trace_replayTransaction` trace file
+pub fn parse_trace_replay_file(path: &str) -> eyre::Result<TraceReplay> {
+    let data = std::fs::read_to_string(path)?;
+    parse_trace_replay(&data)
+}
+
+/// Turn a `vmTrace` into call frames
+///
+/// `vmTrace` nests the operations of every call under the CALL/CREATE that made it but
+/// carries no addresses, so each nested trace is matched with the `trace` entry at the
+/// same position in the call tree.
+pub fn extract_call_frames(replay: &TraceReplay) -> VmTraceFrames {
+    let entries: HashMap<&[usize], &ParityTrace> = replay
+        .trace
+        .iter()
+        .map(|entry| (entry.trace_address.as_slice(), entry))
+        .collect();
+
+    let mut result = VmTraceFrames {
+        frames: Vec::new(),
+        code: HashMap::new(),
+        step_count: 0,
+    };
+    let root_call_type = match entries.get([].as_slice()) {
+        Some(entry) if entry.trace_type == "create" => "CREATE",
+        _ => "CALL",
+    };
+    push_frame(
+        &replay.vm_trace,
+        &entries,
+        FrameOrigin {
+            parent: None,
+            trace_address: Vec::new(),
+            call_type: root_call_type.to_string(),
+            call_pc: None,
+            return_pc: None,
+        },
+        &mut result,
+    );
+    result
+}
+
+/// Where a nested `vmTrace` was entered from
+struct FrameOrigin {
+    parent: Option<usize>,
+    trace_address: Vec<usize>,
+    call_type: String,
+    call_pc: Option<u16>,
+    return_pc: Option<u16>, // next operation of the parent after the call
+}
+
+fn push_frame(
+    vm_trace: &VmTrace,
+    entries: &HashMap<&[usize], &ParityTrace>,
+    origin: FrameOrigin,
+    result: &mut VmTraceFrames,
+) -> usize {
+    let entry = entries.get(origin.trace_address.as_slice()).copied();
+    let is_create = origin.call_type.starts_with("CREATE");
+
+    let code_address = entry
+        .and_then(|entry| {
+            if is_create {
+                entry.result.as_ref().and_then(|result| result.address)
+            } else {
+                entry.action.to
+            }
+        })
+        .unwrap_or_default();
+    let delegated = matches!(origin.call_type.as_str(), "DELEGATECALL" | "CALLCODE");
+    let context_address = match origin.parent {
+        Some(parent_id) if delegated => result.frames[parent_id].context_address,
+        None if delegated => entry.and_then(|entry| entry.action.from).unwrap_or(code_address),
+        _ => code_address,
+    };
+
+    let mut call_path = vec![0];
+    call_path.extend(origin.trace_address.iter().copied());
+
+    let id = result.frames.len();
+    result.frames.push(CallFrame {
+        id,
+        parent: origin.parent,
+        call_path,
+        depth: origin.trace_address.len() as u64 + 1,
+        code_address,
+        context_address,
+        code_kind: if is_create { CodeKind::Init } else { CodeKind::Runtime },
+        call_type: origin.call_type.clone(),
+        call_pc: origin.call_pc,
+        children: Vec::new(),
+        step_ranges: Vec::new(),
+        executed_pcs: HashSet::new(),
+        block_entries: Vec::new(),
+        gas_by_pc: HashMap::new(),
+        gas_used: 0,
+        step_count: vm_trace.ops.len(),
+        initcode: if is_create { Some(vm_trace.code.clone()) } else { None },
+        deployed_code: if is_create {
+            entry.and_then(|entry| entry.result.as_ref()).and_then(|result| result.code.clone())
+        } else {
+            None
+        },
+        error: entry.and_then(|entry| entry.error.clone()),
+        input: entry.filter(|_| !is_create).and_then(|entry| entry.action.input.clone()),
+        value: entry.and_then(|entry| entry.action.value),
+        gas: entry.and_then(|entry| entry.action.gas),
+        logs: Vec::new(), // vmTrace does not keep the stack operands of LOG
+        storage_accesses: Vec::new(),
+        hash_preimages: HashMap::new(), // vmTrace does not keep memory
+        exit_pc: vm_trace.ops.last().map(|op| op.pc as u16),
+        return_pc: origin.return_pc,
+        // Reverted calls have no result, their revert data is not part of the trace
+        return_data_size: entry.and_then(|entry| entry.result.as_ref()).map(|result| {
+            let returned = if is_create { &result.code } else { &result.output };
+            returned.as_ref().map_or(0, |data| data.len())
+        }),
+    });
+    let code_id = CodeId {
+        address: code_address,
+        kind: if is_create { CodeKind::Init } else { CodeKind::Runtime },
+    };
+    result.code.insert(code_id, vm_trace.code.clone());
+
+    // Every CALL/CREATE/SELFDESTRUCT gets a `trace` entry, in execution order
+    let mut next_child = 0;
+    let mut prev_op_code = None;
+    for (i, op) in vm_trace.ops.iter().enumerate() {
+        let step = result.step_count;
+        result.step_count += 1;
+        result.frames[id].executed_pcs.insert(op.pc as u16);
+
+        let op_code = match vm_trace.code.get(op.pc) {
+            Some(op_code) => *op_code,
+            None => continue,
+        };
+        // Same block boundaries as struct log frames, see `CallFrameBuilder::push`
+        if i == 0 || op_code == JUMPDEST || prev_op_code.is_some_and(|prev| prev == JUMP || prev == JUMPI) {
+            result.frames[id].block_entries.push(op.pc as u16);
+        }
+        prev_op_code = Some(op_code);
+        let frame = &mut result.frames[id];
+        *frame.gas_by_pc.entry(op.pc as u16).or_default() += op.cost;
+        frame.gas_used += op.cost;
+        // Only writes are recorded, the slot an SLOAD read is not part of the trace
+        if let Some(store) = op.ex.as_ref().and_then(|ex| ex.store.as_ref()) {
+            frame.storage_accesses.push(StorageAccess {
+                step,
+                pc: Some(op.pc as u16),
+                op: StorageOp::Sstore,
+                slot: H256::from_uint(&store.key),
+                value: Some(H256::from_uint(&store.val)),
+            });
+        }
+        if ![CALL, CALLCODE, DELEGATECALL, STATICCALL, CREATE, CREATE2, SELFDESTRUCT].contains(&op_code) {
+            continue;
+        }
+        let mut child_address = origin.trace_address.clone();
+        child_address.push(next_child);
+        next_child += 1;
+
+        // Calls to accounts without code have no operations, same as with struct logs
+        if let Some(sub) = op.sub.as_ref().filter(|sub| !sub.ops.is_empty()) {
+            let child_id = push_frame(
+                sub,
+                entries,
+                FrameOrigin {
+                    parent: Some(id),
+                    trace_address: child_address,
+                    call_type: opcode(op_code).name.to_string(),
+                    call_pc: Some(op.pc as u16),
+                    return_pc: vm_trace.ops.get(i + 1).map(|next| next.pc as u16),
+                },
+                result,
+            );
+            result.frames[id].children.push(child_id);
+            // Like struct logs, the call's cost includes the gas forwarded to the child, but
+            // not the stipend the child got on top of it
+            let child = &result.frames[child_id];
+            let forwarded = child.gas.map_or(0, |gas| gas.low_u64()).saturating_sub(child.stipend());
+            if op.cost >= forwarded {
+                let frame = &mut result.frames[id];
+                *frame.gas_by_pc.entry(op.pc as u16).or_default() -= forwarded;
+                frame.gas_used -= forwarded;
+            }
+        }
+    }
+
+    id
+}
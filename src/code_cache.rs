@@ -0,0 +1,407 @@
+use ethers::{
+    types::{Bytes, H160, H256},
+    utils::keccak256,
+};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which state a piece of cached code was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeVersion {
+    Block(u64), // state at the end of a block
+    Latest,     // latest block at fetch time, expires after the cache's TTL
+}
+
+/// Code seen at one address on one chain, stored as `index/<chain_id>/<address>.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AddressIndex {
+    ranges: Vec<CodeRange>, // ordered by block, never overlapping
+    latest: Option<LatestEntry>,
+}
+
+/// Blocks over which an address is known to hold the same code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct CodeRange {
+    code_hash: H256,
+    first_block: u64,
+    last_block: u64, // inclusive
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LatestEntry {
+    code_hash: H256,
+    fetched_at: u64, // unix seconds
+}
+
+impl AddressIndex {
+    fn code_hash_at(&self, block: u64) -> Option<H256> {
+        self.ranges
+            .iter()
+            .find(|range| (range.first_block..=range.last_block).contains(&block))
+            .map(|range| range.code_hash)
+    }
+
+    /// Record that the address held `code_hash` at `block`
+    ///
+    /// Code only changes by being destroyed and deployed again, so the range grows to
+    /// the closest block the same code was seen at unless other code was seen in between.
+    /// Seeing other code inside a range splits it.
+    fn observe(&mut self, code_hash: H256, block: u64) {
+        if let Some(at) = self.ranges.iter().position(|range| (range.first_block..=range.last_block).contains(&block)) {
+            let range = self.ranges[at];
+            if range.code_hash == code_hash {
+                return;
+            }
+            self.ranges.remove(at);
+            if range.first_block < block {
+                self.ranges.push(CodeRange { last_block: block - 1, ..range });
+            }
+            if block < range.last_block {
+                self.ranges.push(CodeRange { first_block: block + 1, ..range });
+            }
+        }
+
+        let mut observed = CodeRange { code_hash, first_block: block, last_block: block };
+        let below = self.ranges.iter().filter(|range| range.last_block < block).max_by_key(|range| range.last_block).copied();
+        let above = self.ranges.iter().filter(|range| range.first_block > block).min_by_key(|range| range.first_block).copied();
+        for neighbour in [below, above].into_iter().flatten().filter(|range| range.code_hash == code_hash) {
+            observed.first_block = observed.first_block.min(neighbour.first_block);
+            observed.last_block = observed.last_block.max(neighbour.last_block);
+            self.ranges.retain(|range| *range != neighbour);
+        }
+        self.ranges.push(observed);
+        self.ranges.sort_by_key(|range| range.first_block);
+    }
+}
+
+/// Bytecode cache persisted on disk and shared across runs
+///
+/// Code is stored once per code hash under `blobs/`, so the same contract seen at many
+/// blocks (or a proxy implementation shared by many proxies) takes space only once.
+/// An index per chain and address keeps the block ranges over which the address is
+/// known to hold each code, so lookups at any block inside a range need no RPC call.
+/// Outside them, [`Self::get_by_hash`] reuses a blob once the code hash at that block
+/// is known. When the blobs grow past `max_bytes` the least recently used ones are
+/// evicted, index entries pointing at evicted blobs count as misses.
+pub struct DiskCodeCache {
+    dir: PathBuf,
+    chain_id: u64,
+    max_bytes: u64,
+    latest_ttl: Duration,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// Default size limit of the cached code
+pub const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+/// How long code fetched at the latest block stays valid
+pub const DEFAULT_LATEST_TTL: Duration = Duration::from_secs(60 * 60);
+
+impl DiskCodeCache {
+    /// Open (creating it if needed) the cache for `chain_id` under `dir`
+    pub fn open(dir: impl Into<PathBuf>, chain_id: u64) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(dir.join("blobs"))?;
+        std::fs::create_dir_all(dir.join("index").join(chain_id.to_string()))?;
+        Ok(Self {
+            dir,
+            chain_id,
+            max_bytes: DEFAULT_MAX_BYTES,
+            latest_ttl: DEFAULT_LATEST_TTL,
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn with_latest_ttl(mut self, latest_ttl: Duration) -> Self {
+        self.latest_ttl = latest_ttl;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn index_path(&self, address: H160) -> PathBuf {
+        self.dir
+            .join("index")
+            .join(self.chain_id.to_string())
+            .join(format!("{:x}.json", address))
+    }
+
+    fn blob_path(&self, code_hash: H256) -> PathBuf {
+        self.dir.join("blobs").join(format!("{:x}.hex", code_hash))
+    }
+
+    fn read_index(&self, address: H160) -> AddressIndex {
+        // A corrupt index is treated like a missing one, it gets rewritten on the next insert
+        std::fs::read(self.index_path(address))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn write_index(&self, address: H160, index: &AddressIndex) -> Result<()> {
+        std::fs::write(self.index_path(address), serde_json::to_vec(index)?)?;
+        Ok(())
+    }
+
+    /// Look up the code of `address` at `version`
+    pub fn get(&mut self, address: H160, version: CodeVersion) -> Option<Bytes> {
+        let code = self.lookup(address, version);
+        match code {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        code
+    }
+
+    fn lookup(&self, address: H160, version: CodeVersion) -> Option<Bytes> {
+        let index = self.read_index(address);
+        let code_hash = match version {
+            CodeVersion::Block(block) => index.code_hash_at(block)?,
+            CodeVersion::Latest => {
+                let entry = index.latest?;
+                if unix_now().saturating_sub(entry.fetched_at) > self.latest_ttl.as_secs() {
+                    return None;
+                }
+                entry.code_hash
+            }
+        };
+        self.read_blob(code_hash)
+    }
+
+    /// Whether any code of `address` is indexed, so that a miss at a block outside its
+    /// ranges is worth checking against the code hash at that block
+    pub fn is_known(&self, address: H160) -> bool {
+        !self.read_index(address).ranges.is_empty()
+    }
+
+    /// Look up code by the hash `address` is known to hold at `block`, e.g. from `eth_getProof`
+    ///
+    /// On a hit the index learns that the address holds this code at `block`.
+    pub fn get_by_hash(&mut self, address: H160, block: u64, code_hash: H256) -> Option<Bytes> {
+        let code = self.read_blob(code_hash);
+        match &code {
+            Some(_) => {
+                self.hits += 1;
+                let mut index = self.read_index(address);
+                index.observe(code_hash, block);
+                // Losing the observation only costs another check later
+                let _ = self.write_index(address, &index);
+            }
+            None => self.misses += 1,
+        }
+        code
+    }
+
+    fn read_blob(&self, code_hash: H256) -> Option<Bytes> {
+        let path = self.blob_path(code_hash);
+        let hex_code = std::fs::read_to_string(&path).ok()?;
+        let code: Bytes = hex_code.trim().parse().ok()?;
+        // Never hand out a blob that does not match its hash, e.g. after a partial write
+        if H256(keccak256(&code)) != code_hash {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        // Mark the blob as recently used for eviction
+        if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(code)
+    }
+
+    /// Store the code of `address` at `version`
+    pub fn insert(&mut self, address: H160, version: CodeVersion, code: &Bytes) -> Result<()> {
+        let code_hash = H256(keccak256(code));
+        let blob_path = self.blob_path(code_hash);
+        if !blob_path.exists() {
+            // Write to a temporary file first so concurrent runs never see half a blob
+            let tmp_path = blob_path.with_extension(format!("tmp{}", std::process::id()));
+            std::fs::write(&tmp_path, format!("{}", code))?;
+            std::fs::rename(&tmp_path, &blob_path)?;
+        }
+
+        let mut index = self.read_index(address);
+        match version {
+            CodeVersion::Block(block) => index.observe(code_hash, block),
+            CodeVersion::Latest => index.latest = Some(LatestEntry { code_hash, fetched_at: unix_now() }),
+        }
+        self.write_index(address, &index)
+    }
+
+    /// Forget every cached version of `address` on this chain
+    pub fn invalidate(&mut self, address: H160) -> Result<()> {
+        match std::fs::remove_file(self.index_path(address)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove everything from the cache, for every chain
+    pub fn clear(&mut self) -> Result<()> {
+        for sub_dir in ["blobs", "index"] {
+            let path = self.dir.join(sub_dir);
+            if path.exists() {
+                std::fs::remove_dir_all(&path)?;
+            }
+        }
+        std::fs::create_dir_all(self.dir.join("blobs"))?;
+        std::fs::create_dir_all(self.dir.join("index").join(self.chain_id.to_string()))?;
+        Ok(())
+    }
+
+    /// Evict the least recently used blobs until the cache fits its size limit
+    ///
+    /// Returns the number of evicted blobs.
+    pub fn enforce_size_limit(&mut self) -> Result<usize> {
+        let mut blobs = Vec::new();
+        let mut total_bytes = 0;
+        for entry in std::fs::read_dir(self.dir.join("blobs"))? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            total_bytes += metadata.len();
+            blobs.push((metadata.modified().unwrap_or(UNIX_EPOCH), metadata.len(), entry.path()));
+        }
+        if total_bytes <= self.max_bytes {
+            return Ok(0);
+        }
+
+        blobs.sort();
+        let mut evicted = 0;
+        for (_, size, path) in blobs {
+            if total_bytes <= self.max_bytes {
+                break;
+            }
+            std::fs::remove_file(&path).map_err(|e| eyre!("Could not evict {}: {}", path.display(), e))?;
+            total_bytes -= size;
+            evicted += 1;
+        }
+        Ok(evicted)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: H160 = H160([0xaa; 20]);
+
+    /// A fresh cache in its own temporary directory, removed when dropped
+    struct TestCache {
+        cache: DiskCodeCache,
+    }
+
+    impl TestCache {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("evm-cfg-code-cache-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_dir_all(&dir);
+            Self { cache: DiskCodeCache::open(dir, 1).unwrap() }
+        }
+    }
+
+    impl Drop for TestCache {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.cache.dir());
+        }
+    }
+
+    fn code(hex_code: &str) -> Bytes {
+        hex::decode(hex_code).unwrap().into()
+    }
+
+    fn at(block: u64) -> CodeVersion {
+        CodeVersion::Block(block)
+    }
+
+    #[test]
+    fn hits_anywhere_between_blocks_the_code_was_seen_at() {
+        let mut test = TestCache::new("hit");
+        let cache = &mut test.cache;
+        let token = code("6080604052");
+        cache.insert(ADDRESS, at(100), &token).unwrap();
+        cache.insert(ADDRESS, at(200), &token).unwrap();
+
+        assert_eq!(cache.get(ADDRESS, at(100)), Some(token.clone()));
+        assert_eq!(cache.get(ADDRESS, at(150)), Some(token.clone()));
+        assert_eq!(cache.get(ADDRESS, at(200)), Some(token));
+        assert_eq!((cache.hits, cache.misses), (3, 0));
+
+        // Another address with the same code shares the blob
+        cache.insert(H160([0xbb; 20]), at(100), &code("6080604052")).unwrap();
+        assert_eq!(std::fs::read_dir(cache.dir().join("blobs")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn misses_outside_known_ranges() {
+        let mut test = TestCache::new("miss");
+        let cache = &mut test.cache;
+        assert!(!cache.is_known(ADDRESS));
+        assert_eq!(cache.get(ADDRESS, at(100)), None);
+
+        cache.insert(ADDRESS, at(100), &code("6001")).unwrap();
+        assert!(cache.is_known(ADDRESS));
+        assert_eq!(cache.get(ADDRESS, at(99)), None);
+        assert_eq!(cache.get(ADDRESS, at(101)), None);
+        assert_eq!(cache.get(ADDRESS, CodeVersion::Latest), None);
+        assert_eq!((cache.hits, cache.misses), (0, 4));
+
+        // The code hash at another block, e.g. from eth_getProof, finds the blob and extends the range
+        let code_hash = H256(keccak256(code("6001")));
+        assert_eq!(cache.get_by_hash(ADDRESS, 120, code_hash), Some(code("6001")));
+        assert_eq!(cache.get(ADDRESS, at(110)), Some(code("6001")));
+        assert_eq!(cache.get_by_hash(ADDRESS, 130, H256([0x11; 32])), None);
+    }
+
+    #[test]
+    fn keeps_code_apart_across_a_redeploy() {
+        let mut test = TestCache::new("redeploy");
+        let cache = &mut test.cache;
+        let (old, new) = (code("6001"), code("6002"));
+        cache.insert(ADDRESS, at(100), &old).unwrap();
+        cache.insert(ADDRESS, at(300), &old).unwrap();
+        // Redeployed with other code in between: the range is split around it
+        cache.insert(ADDRESS, at(200), &new).unwrap();
+
+        assert_eq!(cache.get(ADDRESS, at(150)), Some(old.clone()));
+        assert_eq!(cache.get(ADDRESS, at(200)), Some(new.clone()));
+        assert_eq!(cache.get(ADDRESS, at(250)), Some(old.clone()));
+
+        // Seeing the new code later does not reach across the old code in between
+        cache.insert(ADDRESS, at(400), &new).unwrap();
+        assert_eq!(cache.get(ADDRESS, at(300)), Some(old));
+        assert_eq!(cache.get(ADDRESS, at(350)), None);
+        assert_eq!(cache.get(ADDRESS, at(400)), Some(new));
+    }
+
+    #[test]
+    fn drops_blobs_that_do_not_match_their_hash() {
+        let mut test = TestCache::new("corrupt");
+        let cache = &mut test.cache;
+        cache.insert(ADDRESS, at(100), &code("6001")).unwrap();
+        let blob_path = cache.blob_path(H256(keccak256(code("6001"))));
+        std::fs::write(&blob_path, "0x6002").unwrap();
+
+        assert_eq!(cache.get(ADDRESS, at(100)), None);
+        assert!(!blob_path.exists());
+    }
+}
@@ -1,20 +1,242 @@
 use dotenv::dotenv;
 use eyre::{eyre, Result};
+use revm::primitives::SpecId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::path::{Path, PathBuf};
 
 pub struct Config {
-    pub rpc_url: String,
+    pub networks: BTreeMap<String, NetworkConfig>, // built-in networks, overridden by the networks file
+    pub code_cache_dir: PathBuf,                   // persistent bytecode cache shared across runs
+    pub code_cache_max_bytes: Option<u64>,
+    pub signature_paths: Vec<PathBuf>,             // signature files and artifact directories, loaded over the bundled list
 }
 
+/// A chain the tool can analyze transactions on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    pub chain_id: u64,
+    #[serde(default)]
+    pub rpc_url: Option<String>,
+    #[serde(default)]
+    pub rpc_url_env: Option<String>, // environment variable holding the RPC URL, used when `rpc_url` is unset
+    #[serde(default)]
+    pub explorer_url: Option<String>, // e.g. https://etherscan.io
+    #[serde(default)]
+    pub hardforks: Vec<HardforkActivation>, // in activation order
+}
+
+/// Activation of a hardfork, by block number or (for post-merge forks) by timestamp
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardforkActivation {
+    pub fork: String,
+    #[serde(default)]
+    pub block: Option<u64>,
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+/// Layout of the networks file, `[networks.<name>]` tables
+#[derive(Debug, Deserialize)]
+struct NetworksFile {
+    #[serde(default)]
+    networks: BTreeMap<String, NetworkConfig>,
+}
+
+/// Network used when `--chain` is not given
+pub const DEFAULT_NETWORK: &str = "mainnet";
+
 impl Config {
     pub fn new() -> Result<Self> {
         // Load .env file
         dotenv().ok();
 
-        // Read RPC URL
-        let rpc_url = env::var("GETH_API")
-            .map_err(|_| eyre!("GETH_API environment variable not found. Please configure GETH_API=<Your RPC Node URL> in the .env file"))?;
+        let mut networks = builtin_networks();
+        let custom_path = env::var("NETWORKS_CONFIG").ok();
+        let networks_path = custom_path.as_deref().unwrap_or("networks.toml");
+        if Path::new(networks_path).exists() {
+            let data = std::fs::read_to_string(networks_path)?;
+            let file: NetworksFile = toml::from_str(&data).map_err(|e| eyre!("Invalid {}: {}", networks_path, e))?;
+            networks.extend(file.networks);
+        } else if custom_path.is_some() {
+            return Err(eyre!("Networks file {} does not exist", networks_path));
+        }
+        for (name, network) in &networks {
+            network.validate().map_err(|e| eyre!("Network {}: {}", name, e))?;
+        }
+
+        let code_cache_dir = env::var("BYTECODE_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(".cache/bytecode"));
+        let code_cache_max_bytes = match env::var("BYTECODE_CACHE_MAX_MB") {
+            Ok(max_mb) => Some(
+                max_mb
+                    .parse::<u64>()
+                    .map_err(|_| eyre!("BYTECODE_CACHE_MAX_MB must be a whole number of megabytes, got {}", max_mb))?
+                    * 1024
+                    * 1024,
+            ),
+            Err(_) => None,
+        };
+
+        let signature_paths = match env::var("SIGNATURES") {
+            Ok(paths) => paths
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(|path| match Path::new(path).exists() {
+                    true => Ok(PathBuf::from(path)),
+                    false => Err(eyre!("Signatures path {} does not exist", path)),
+                })
+                .collect::<Result<_>>()?,
+            Err(_) => ["signatures.txt", "signatures.json"]
+                .into_iter()
+                .map(PathBuf::from)
+                .filter(|path| path.exists())
+                .collect(),
+        };
+
+        Ok(Config { networks, code_cache_dir, code_cache_max_bytes, signature_paths })
+    }
+
+    /// Look up a network by name
+    pub fn network(&self, name: &str) -> Result<&NetworkConfig> {
+        self.networks.get(name).ok_or_else(|| {
+            let known: Vec<&str> = self.networks.keys().map(|name| name.as_str()).collect();
+            eyre!("Unknown network {}, configured networks: {}", name, known.join(", "))
+        })
+    }
+}
+
+impl NetworkConfig {
+    /// RPC URL of the network, unset when only saved data is available
+    pub fn rpc_url(&self) -> Option<String> {
+        self.rpc_url
+            .clone()
+            .or_else(|| self.rpc_url_env.as_ref().and_then(|var| env::var(var).ok()))
+            .filter(|url| !url.is_empty())
+    }
+
+    /// Hardfork a block with the given number and timestamp executed under
+    pub fn spec_at(&self, block_number: u64, timestamp: u64) -> SpecId {
+        self.hardforks
+            .iter()
+            .rev()
+            .filter(|activation| {
+                activation.block.is_none_or(|block| block_number >= block)
+                    && activation.timestamp.is_none_or(|time| timestamp >= time)
+            })
+            .find_map(|activation| parse_fork(&activation.fork))
+            .unwrap_or(SpecId::LATEST)
+    }
+
+    /// Link to a transaction on the network's block explorer
+    pub fn explorer_tx_url(&self, tx_hash: &str) -> Option<String> {
+        self.explorer_url
+            .as_ref()
+            .map(|url| format!("{}/tx/{}", url.trim_end_matches('/'), tx_hash))
+    }
 
-        Ok(Config { rpc_url })
+    fn validate(&self) -> Result<()> {
+        for activation in &self.hardforks {
+            if parse_fork(&activation.fork).is_none() {
+                return Err(eyre!("unknown hardfork {}", activation.fork));
+            }
+        }
+        Ok(())
     }
 }
+
+/// Parse a hardfork name such as `london` or `Cancun`
+pub fn parse_fork(name: &str) -> Option<SpecId> {
+    let spec_id = match name.to_lowercase().replace(['_', '-', ' '], "").as_str() {
+        "frontier" => SpecId::FRONTIER,
+        "homestead" => SpecId::HOMESTEAD,
+        "tangerine" | "tangerinewhistle" => SpecId::TANGERINE,
+        "spuriousdragon" | "spurious" => SpecId::SPURIOUS_DRAGON,
+        "byzantium" => SpecId::BYZANTIUM,
+        "constantinople" => SpecId::CONSTANTINOPLE,
+        "petersburg" => SpecId::PETERSBURG,
+        "istanbul" => SpecId::ISTANBUL,
+        "muirglacier" => SpecId::MUIR_GLACIER,
+        "berlin" => SpecId::BERLIN,
+        "london" => SpecId::LONDON,
+        "arrowglacier" => SpecId::ARROW_GLACIER,
+        "grayglacier" => SpecId::GRAY_GLACIER,
+        "merge" | "paris" => SpecId::MERGE,
+        "shanghai" => SpecId::SHANGHAI,
+        "cancun" => SpecId::CANCUN,
+        "prague" => SpecId::PRAGUE,
+        "latest" => SpecId::LATEST,
+        _ => return None,
+    };
+    Some(spec_id)
+}
+
+fn fork_at_block(fork: &str, block: u64) -> HardforkActivation {
+    HardforkActivation { fork: fork.to_string(), block: Some(block), timestamp: None }
+}
+
+fn fork_at_time(fork: &str, timestamp: u64) -> HardforkActivation {
+    HardforkActivation { fork: fork.to_string(), block: None, timestamp: Some(timestamp) }
+}
+
+/// Networks known without a networks file
+///
+/// L2 and BSC schedules map their upgrades onto the closest Ethereum hardfork,
+/// override them in `networks.toml` when exact semantics matter.
+fn builtin_networks() -> BTreeMap<String, NetworkConfig> {
+    let network = |chain_id, rpc_url_env: &str, explorer_url: &str, hardforks| NetworkConfig {
+        chain_id,
+        rpc_url: None,
+        rpc_url_env: Some(rpc_url_env.to_string()),
+        explorer_url: Some(explorer_url.to_string()),
+        hardforks,
+    };
+
+    let mainnet = vec![
+        fork_at_block("frontier", 0),
+        fork_at_block("homestead", 1150000),
+        fork_at_block("tangerine", 2463000),
+        fork_at_block("spuriousdragon", 2675000),
+        fork_at_block("byzantium", 4370000),
+        fork_at_block("petersburg", 7280000),
+        fork_at_block("istanbul", 9069000),
+        fork_at_block("muirglacier", 9200000),
+        fork_at_block("berlin", 12244000),
+        fork_at_block("london", 12965000),
+        fork_at_block("arrowglacier", 13773000),
+        fork_at_block("grayglacier", 15050000),
+        fork_at_block("merge", 15537394),
+        fork_at_time("shanghai", 1681338455),
+        fork_at_time("cancun", 1710338135),
+        fork_at_time("prague", 1746612311),
+    ];
+    // Bedrock, Canyon, Ecotone and Isthmus of the OP stack
+    let op_stack = vec![
+        fork_at_block("london", 0),
+        fork_at_time("shanghai", 1704992401),
+        fork_at_time("cancun", 1710374401),
+        fork_at_time("prague", 1746806401),
+    ];
+    let arbitrum = vec![
+        fork_at_block("london", 0),
+        fork_at_time("cancun", 1710424089), // ArbOS 20
+        fork_at_time("prague", 1750176000), // ArbOS 40
+    ];
+    let bsc = vec![
+        fork_at_block("london", 0),
+        fork_at_time("shanghai", 1705996800), // Kepler
+        fork_at_time("cancun", 1718863500),   // Tycho
+        fork_at_time("prague", 1742436600),   // Pascal
+    ];
+
+    BTreeMap::from([
+        ("mainnet".to_string(), network(1, "GETH_API", "https://etherscan.io", mainnet)),
+        ("optimism".to_string(), network(10, "OPTIMISM_RPC_URL", "https://optimistic.etherscan.io", op_stack.clone())),
+        ("base".to_string(), network(8453, "BASE_RPC_URL", "https://basescan.org", op_stack)),
+        ("arbitrum".to_string(), network(42161, "ARBITRUM_RPC_URL", "https://arbiscan.io", arbitrum)),
+        ("bsc".to_string(), network(56, "BSC_RPC_URL", "https://bscscan.com", bsc)),
+    ])
+}
@@ -25,5 +25,8 @@ impl OutputHandler {
 // Export all new modules
 pub mod analyzer;
 pub mod blockchain;
+pub mod code_cache;
 pub mod config;
 pub mod cfg_gen;
+pub mod signatures;
+pub mod storage_layout;
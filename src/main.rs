@@ -1,16 +1,19 @@
 use clap::{Parser, ValueHint, ArgGroup};
 use evm_cfg::{
     analyzer::TransactionAnalyzer,
-    blockchain::{EthersBlockchainService, save_transaction_trace},
-    config::Config,
+    blockchain::{self, BlockchainService, EthersBlockchainService, FetchOptions, FileBlockchainService},
+    cfg_gen::local_exec,
+    code_cache::DiskCodeCache,
+    config::{parse_fork, Config, DEFAULT_NETWORK},
+    storage_layout::StorageLayout,
 };
 use eyre::{eyre, Result};
 use std::path::Path;
-use ethers::types::H256;
+use ethers::types::{H160, H256};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "EVM Transaction Flow Visualization Engine", long_about = None)]
-#[clap(group(ArgGroup::new("input").required(true).args(&["trace", "tx_hash"])))]
+#[clap(group(ArgGroup::new("input").required(true).args(&["trace", "tx_hash", "snapshot"])))]
 struct Args {
     /// Path to transaction trace file containing debug_traceTransaction output (JSON format)
     #[clap(long, value_hint = ValueHint::FilePath, value_name = "PATH_TO_TRACE_FILE")]
@@ -20,6 +23,72 @@ struct Args {
     #[clap(long, value_name = "TRANSACTION_HASH")]
     pub tx_hash: Option<String>,
 
+    /// Path to an execution snapshot (transaction, block and prestateTracer output) to re-execute locally
+    #[clap(long, value_hint = ValueHint::FilePath, value_name = "PATH_TO_SNAPSHOT_FILE")]
+    pub snapshot: Option<String>,
+
+    /// With --tx-hash, fetch an execution snapshot and re-execute it locally instead of fetching the full trace
+    #[clap(long, default_value = "false", requires = "tx_hash")]
+    pub local: bool,
+
+    /// Block number to fetch contract code at when using --trace (defaults to latest)
+    #[clap(long, value_name = "BLOCK_NUMBER")]
+    pub block: Option<u64>,
+
+    /// Network the transaction ran on, as configured in networks.toml (mainnet, optimism, base, arbitrum, bsc built in)
+    #[clap(long, env = "CHAIN", default_value = DEFAULT_NETWORK)]
+    pub chain: String,
+
+    /// Hardfork to decode contract code as (e.g. shanghai, cancun), defaults to the one
+    /// scheduled for the transaction's block on the selected network
+    #[clap(long, value_name = "HARDFORK")]
+    pub hardfork: Option<String>,
+
+    /// Read code and traces from saved files instead of the RPC node (implied when the network has no RPC URL)
+    #[clap(long, default_value = "false")]
+    pub offline: bool,
+
+    /// Directory holding saved code and traces for --offline, defaults to the transaction's results directory
+    /// or the directory of the trace file
+    #[clap(long, value_hint = ValueHint::DirPath, value_name = "DATA_DIR")]
+    pub data_dir: Option<String>,
+
+    /// Do not use the persistent bytecode cache
+    #[clap(long, default_value = "false")]
+    pub no_cache: bool,
+
+    /// Empty the persistent bytecode cache before fetching
+    #[clap(long, default_value = "false")]
+    pub clear_cache: bool,
+
+    /// Maximum number of code requests (or batches) in flight at once
+    #[clap(long, env = "RPC_CONCURRENCY", default_value = "8")]
+    pub rpc_concurrency: usize,
+
+    /// Number of addresses per JSON-RPC batch request, 1 disables batching
+    #[clap(long, env = "RPC_BATCH_SIZE", default_value = "20")]
+    pub rpc_batch_size: usize,
+
+    /// Retries of a failed RPC request, with exponential backoff
+    #[clap(long, env = "RPC_MAX_RETRIES", default_value = "4")]
+    pub rpc_retries: u32,
+
+    /// Maximum RPC calls per second, to stay under provider quotas (unlimited if unset, must be positive)
+    #[clap(long, env = "RPC_REQUESTS_PER_SECOND", value_name = "RPS")]
+    pub rpc_rps: Option<f64>,
+
+    /// Include memory when fetching the trace (needed to recover code created by the transaction and the calldata of internal calls)
+    #[clap(long, default_value = "false")]
+    pub memory: bool,
+
+    /// Solc storage layout naming the slots of an account, from solc output, a Foundry artifact or Hardhat build info (repeatable)
+    #[clap(long, value_name = "ADDRESS=PATH[:CONTRACT]")]
+    pub storage_layout: Vec<String>,
+
+    /// Fill executed blocks by the gas they spent instead of by the operations they contain
+    #[clap(long, default_value = "false")]
+    pub gas_heatmap: bool,
+
     /// Output DOT file path
     #[clap(long, value_hint = ValueHint::FilePath, value_name = "OUTPUT_DOT_FILE")]
     pub output: Option<String>,
@@ -40,14 +109,61 @@ async fn main() -> Result<()> {
     
     // Load configuration
     let config = Config::new().map_err(|e| {
-        eyre!("Configuration loading failed: {}. Please ensure you have created a .env file in the project root and configured GETH_API", e)
+        eyre!("Configuration loading failed: {}. Please check the .env file and networks.toml in the project root", e)
     })?;
     
-    // Create blockchain service
-    let blockchain_service = EthersBlockchainService::new(&config.rpc_url)?;
+    // Create blockchain service, from saved files when there is no RPC node to talk to
+    let network = config.network(&args.chain)?;
+    let rpc_url = network.rpc_url();
+    let offline = args.offline || rpc_url.is_none();
+    let mut node_chain_id = None;
+    let blockchain_service: Box<dyn BlockchainService> = match &rpc_url {
+        Some(rpc_url) if !offline => {
+            let fetch_options = FetchOptions {
+                concurrency: args.rpc_concurrency,
+                batch_size: args.rpc_batch_size,
+                max_retries: args.rpc_retries,
+                requests_per_second: args.rpc_rps,
+                ..FetchOptions::default()
+            };
+            let service = EthersBlockchainService::new(rpc_url)?
+                .with_memory(args.memory)
+                .with_fetch_options(fetch_options)?;
+            let chain_id = service.chain_id().await?;
+            if chain_id != network.chain_id {
+                return Err(eyre!("RPC node serves chain {}, but network {} is chain {}", chain_id, args.chain, network.chain_id));
+            }
+            node_chain_id = Some(chain_id);
+            Box::new(service)
+        }
+        _ => {
+            let data_dir = args.data_dir.clone().unwrap_or_else(|| {
+                match (&args.tx_hash, args.trace.as_ref().or(args.snapshot.as_ref())) {
+                    (Some(tx_hash), _) => results_dir(&args.chain, tx_hash),
+                    (None, Some(input_file)) => Path::new(input_file)
+                        .parent()
+                        .map(|dir| dir.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| ".".to_string()),
+                    (None, None) => ".".to_string(),
+                }
+            });
+            if !args.offline {
+                println!("⚠️ No RPC URL configured for {}, running offline", args.chain);
+            }
+            println!("📂 Reading saved code and traces from {}", data_dir);
+            Box::new(FileBlockchainService::new(data_dir))
+        }
+    };
     
     // Determine transaction trace path (from file or via transaction hash)
-    let trace_path = if let Some(trace_file) = &args.trace {
+    let mut snapshot_path = args.snapshot.clone();
+    let mut fetched_frames = None; // a fetched trace is folded into frames while it is saved
+    let trace_path = if let Some(snapshot_file) = &args.snapshot {
+        if !Path::new(snapshot_file).exists() {
+            return Err(eyre!("Execution snapshot file does not exist: {}", snapshot_file));
+        }
+        snapshot_file.clone()
+    } else if let Some(trace_file) = &args.trace {
         // Use user-provided trace file
         if !Path::new(trace_file).exists() {
             return Err(eyre!("Transaction trace file does not exist: {}", trace_file));
@@ -59,23 +175,42 @@ async fn main() -> Result<()> {
         let tx_hash = tx_hash_str.parse::<H256>()
             .map_err(|_| eyre!("Invalid transaction hash: {}", tx_hash_str))?;
         
-        println!("🔍 Fetching trace for transaction {} from blockchain...", tx_hash);
-        
         // Determine output directory
-        let output_dir = format!("Results/{}", tx_hash_str);
+        let output_dir = results_dir(&args.chain, tx_hash_str);
         if !Path::new(&output_dir).exists() {
             std::fs::create_dir_all(&output_dir)?;
         }
+        let saved_file = if args.local {
+            format!("{}/Snapshot_{}.json", output_dir, tx_hash_str)
+        } else {
+            format!("{}/Trace_{}.txt", output_dir, tx_hash_str)
+        };
         
-        // Get trace content
-        let trace_content = save_transaction_trace(tx_hash, &blockchain_service).await?;
-        
-        // Save to file in the transaction's directory
-        let trace_file = format!("{}/Trace_{}.txt", output_dir, tx_hash_str);
-        std::fs::write(&trace_file, trace_content)?;
-        println!("✅ Transaction trace saved to {}", trace_file);
-        
-        trace_file
+        if offline && Path::new(&saved_file).exists() {
+            // Reuse what an earlier run saved instead of copying it onto itself
+            println!("📂 Using saved {}", saved_file);
+            if args.local {
+                snapshot_path = Some(saved_file.clone());
+            }
+            saved_file
+        } else if args.local {
+            // Only the pre-state is fetched, the trace is produced by re-executing it
+            println!("🔍 Fetching execution snapshot for transaction {}...", tx_hash);
+            let snapshot = blockchain_service.get_execution_snapshot(tx_hash).await?;
+            std::fs::write(&saved_file, serde_json::to_string_pretty(&snapshot)?)?;
+            println!("✅ Execution snapshot saved to {}", saved_file);
+            snapshot_path = Some(saved_file.clone());
+            saved_file
+        } else {
+            println!("🔍 Fetching trace for transaction {} from blockchain...", tx_hash);
+            
+            // Save to file in the transaction's directory
+            let frames = blockchain_service.save_transaction_trace(tx_hash, Path::new(&saved_file)).await?;
+            println!("✅ Transaction trace saved to {}", saved_file);
+            fetched_frames = Some(frames);
+            
+            saved_file
+        }
     } else {
         return Err(eyre!("You must provide either a transaction trace file (--trace) or a transaction hash (--tx_hash)"));
     };
@@ -85,7 +220,7 @@ async fn main() -> Result<()> {
         output_file.clone()
     } else if let Some(tx_hash) = &args.tx_hash {
         // Create output directory based on transaction hash
-        let output_dir = format!("Results/{}", tx_hash);
+        let output_dir = results_dir(&args.chain, tx_hash);
         if !Path::new(&output_dir).exists() {
             std::fs::create_dir_all(&output_dir)?;
         }
@@ -97,44 +232,175 @@ async fn main() -> Result<()> {
             .and_then(|s| s.to_str())
             .unwrap_or("output");
         
-        let output_dir = format!("Results/{}", trace_filename.replace(".txt", ""));
+        let trace_stem = trace_filename.trim_end_matches(".txt").trim_end_matches(".json");
+        let output_dir = results_dir(&args.chain, trace_stem);
         if !Path::new(&output_dir).exists() {
             std::fs::create_dir_all(&output_dir)?;
         }
         
-        format!("{}/{}.dot", output_dir, trace_filename.replace(".txt", ""))
+        format!("{}/{}.dot", output_dir, trace_stem)
     };
     
-    println!("🔍 Analyzing transaction trace...");
-    
-    // Create analyzer from trace file
-    let mut analyzer = TransactionAnalyzer::from_trace_file(&trace_path)?;
+    let mut analyzer = if let Some(snapshot_file) = &snapshot_path {
+        println!("⚙️ Re-executing transaction locally...");
+        let snapshot = local_exec::parse_snapshot_file(snapshot_file)?;
+        let (analyzer, outcome) = TransactionAnalyzer::from_snapshot(&snapshot, network, args.memory)?;
+        println!(
+            "✅ Executed {} steps, {} ({} gas used)",
+            outcome.step_count,
+            if outcome.success { "succeeded" } else { "failed" },
+            outcome.gas_used
+        );
+        if !outcome.missing_accounts.is_empty() {
+            println!(
+                "⚠️ {} accounts read during execution are not in the snapshot's prestate and were treated as empty: {:?}",
+                outcome.missing_accounts.len(),
+                outcome.missing_accounts
+            );
+        }
+        analyzer
+    } else if let Some(frames) = fetched_frames {
+        TransactionAnalyzer::from_call_frames(frames)
+    } else {
+        println!("🔍 Analyzing transaction trace...");
+        
+        // Create analyzer from trace file
+        TransactionAnalyzer::from_trace_file(&trace_path)?
+    };
     
+    analyzer.set_chain_id(network.chain_id);
     println!("📝 Identified {} contract addresses", analyzer.contract_addresses.len());
     
+    // Pin code fetching to the state the transaction actually ran against
+    if snapshot_path.is_some() {
+        // The snapshot already pins the block and carries the code of every touched account
+    } else if let Some(tx_hash_str) = &args.tx_hash {
+        let tx_hash = tx_hash_str.parse::<H256>()
+            .map_err(|_| eyre!("Invalid transaction hash: {}", tx_hash_str))?;
+        if offline {
+            // Saved code already is the transaction's pre-state
+            analyzer.tx_hash = Some(tx_hash);
+        } else {
+            let block_number = analyzer.set_transaction(tx_hash, blockchain_service.as_ref()).await?;
+            println!("📦 Transaction was included in block {}", block_number);
+        }
+    } else if let Some(block_number) = args.block {
+        analyzer.set_block(block_number);
+    }
+    
+    // Decode code as the hardfork the transaction ran under, snapshots already pinned it
+    if let Some(fork) = &args.hardfork {
+        let spec_id = parse_fork(fork).ok_or_else(|| eyre!("Unknown hardfork: {}", fork))?;
+        analyzer.set_spec_id(spec_id);
+    } else if snapshot_path.is_none() {
+        match analyzer.block_number() {
+            Some(block_number) => match blockchain_service.get_block_timestamp(block_number).await {
+                Ok(timestamp) => analyzer.set_spec_id(network.spec_at(block_number, timestamp)),
+                Err(e) => println!("⚠️ {}, decoding code as the latest hardfork (see --hardfork)", e),
+            },
+            None => println!("⚠️ Block unknown, decoding code as the latest hardfork (see --hardfork)"),
+        }
+    }
+    println!("🍴 Decoding code as {:?}", analyzer.spec_id);
+    for signature_path in &config.signature_paths {
+        analyzer.signatures.load(signature_path)?;
+        println!("🔤 Loaded signatures from {}", signature_path.display());
+    }
+    println!(
+        "🔤 {} function and {} event signatures known ({} selector collisions)",
+        analyzer.signatures.len(),
+        analyzer.signatures.event_count(),
+        analyzer.signatures.collision_count()
+    );
+    for storage_layout in &args.storage_layout {
+        let (address, path, contract) = parse_storage_layout_arg(storage_layout)?;
+        analyzer.set_storage_layout(address, StorageLayout::load(path, contract)?);
+        println!("🗂️ Loaded storage layout of {:?} from {}", address, path);
+    }
+    
+    // Saved files are read directly, the persistent cache only saves RPC requests
+    if let (Some(chain_id), false) = (node_chain_id, args.no_cache) {
+        let mut code_cache = DiskCodeCache::open(&config.code_cache_dir, chain_id)?;
+        if let Some(max_bytes) = config.code_cache_max_bytes {
+            code_cache = code_cache.with_max_bytes(max_bytes);
+        }
+        if args.clear_cache {
+            code_cache.clear()?;
+            println!("🧹 Cleared bytecode cache at {}", config.code_cache_dir.display());
+        }
+        analyzer.set_code_cache(code_cache);
+    }
+    
     // Get all contract bytecodes
-    println!("⬇️ Fetching contract bytecodes from RPC node...");
-    analyzer.fetch_bytecodes(&blockchain_service).await?;
+    if offline {
+        println!("⬇️ Loading saved contract bytecodes...");
+    } else {
+        println!("⬇️ Fetching contract bytecodes from RPC node...");
+    }
+    analyzer.fetch_bytecodes(blockchain_service.as_ref()).await?;
     println!("✅ Successfully fetched bytecodes for {} contracts", analyzer.bytecode_cache.cache.len());
+    if let Some(code_cache) = &analyzer.code_cache {
+        println!("🗄️ Bytecode cache: {} hits, {} misses", code_cache.hits, code_cache.misses);
+    }
+    if !analyzer.trace_code.is_empty() {
+        println!("🏗️ Took {} code blobs directly from the trace", analyzer.trace_code.len());
+    }
     
     // Generate CFG for each contract
     println!("🔄 Generating control flow graphs for each contract...");
+    analyzer.set_gas_heatmap(args.gas_heatmap);
     analyzer.generate_contract_cfgs()?;
+    let mut code_ids: Vec<_> = analyzer.contract_cfgs.keys().copied().collect();
+    code_ids.sort();
+    for code_id in code_ids {
+        let contract_cfg = &analyzer.contract_cfgs[&code_id];
+        if let Some(metadata) = &contract_cfg.layout.metadata {
+            println!("🧾 {} compiled with {}", code_id.file_stem(), metadata);
+        }
+        if let Some(dispatcher) = &contract_cfg.cfg_runner.dispatcher {
+            let executed = analyzer.executed_functions(&code_id).iter().map(|function| function.to_string()).collect::<Vec<_>>();
+            println!("🧭 {} dispatches {} functions, executed: {}", code_id.file_stem(), dispatcher.selector_count(), if executed.is_empty() { "none".to_string() } else { executed.join(", ") });
+        }
+    }
     
     // Build global transaction graph
     println!("🔗 Building global transaction execution graph...");
     analyzer.build_global_transaction_graph()?;
     
+    // Most expensive blocks of the transaction
+    let execution_gas = analyzer.execution_gas();
+    let hotspots = analyzer.gas_hotspots();
+    if execution_gas > 0 && !hotspots.is_empty() {
+        println!("⛽ Gas hotspots ({} gas spent executing code):", execution_gas);
+        println!("   {:>10} {:>6}  {:<10} {:<42} block", "gas", "share", "frame", "address");
+        for hotspot in hotspots.iter().take(10) {
+            println!(
+                "   {:>10} {:>5.1}%  {:<10} {:<42} {}-{}",
+                hotspot.gas,
+                hotspot.gas as f64 * 100.0 / execution_gas as f64,
+                analyzer.call_frames[hotspot.frame_id].call_path_str(),
+                format!("{:?}", hotspot.code_id.address),
+                hotspot.block.0,
+                hotspot.block.1
+            );
+        }
+    }
+    
     // Make sure the output path uses the same directory as defined earlier
     let output_dir = Path::new(&output_path).parent().unwrap_or_else(|| Path::new(".")).to_str().unwrap();
     
     // Create output directory if it doesn't exist
     if !Path::new(&output_dir).exists() {
-        std::fs::create_dir_all(&output_dir)?;
+        std::fs::create_dir_all(output_dir)?;
+    }
+
+    // Keep the fetched code next to the results so the analysis can be rerun offline
+    if !offline {
+        analyzer.bytecode_cache.save_to_dir(&Path::new(output_dir).join(blockchain::CODE_DIR))?;
     }
 
     // For trace files from --trace option, copy to the output directory
-    if args.tx_hash.is_none() {
+    if args.trace.is_some() {
         // Only needed for --trace option, as --tx-hash already saves to correct location
         let trace_basename = Path::new(&trace_path)
             .file_name()
@@ -147,13 +413,27 @@ async fn main() -> Result<()> {
         println!("💾 Saved transaction trace to {}...", trace_output_path);
     }
     
+    // Record which chain and transaction the results belong to
+    let metadata_path = format!("{}/metadata.json", output_dir);
+    analyzer.save_metadata(&metadata_path, &args.chain, network)?;
+    
+    // Storage reads and writes of every account, in execution order
+    let storage_timeline = analyzer.storage_timeline();
+    if !storage_timeline.is_empty() {
+        let storage_path = format!("{}/storage.json", output_dir);
+        analyzer.save_storage_timeline(&storage_path)?;
+        let writes = storage_timeline.values().flatten().filter(|effect| effect.op.is_write()).count();
+        let reads = storage_timeline.values().flatten().count() - writes;
+        println!("🗃️ Saved {} storage reads and {} writes of {} accounts to {}", reads, writes, storage_timeline.len(), storage_path);
+    }
+    
     // Save global transaction graph to DOT file
     println!("💾 Saving global transaction graph to {}...", output_path);
     analyzer.save_global_graph_dot(&output_path)?;
     
     // Generate highlighted CFGs (now the default behavior)
     println!("🔍 Generating highlighted CFGs for each contract...");
-    let saved_files = analyzer.save_contract_highlighted_cfgs(&output_dir)?;
+    let saved_files = analyzer.save_contract_highlighted_cfgs(output_dir)?;
     println!("✅ Saved {} highlighted contract CFGs to {}", saved_files.len(), output_dir);
     
     // Convert to images if requested
@@ -174,3 +454,28 @@ async fn main() -> Result<()> {
     
     Ok(())
 }
+
+/// Split a `--storage-layout` value into the address, the layout file and the contract to take from it
+fn parse_storage_layout_arg(arg: &str) -> Result<(H160, &str, Option<&str>)> {
+    let (address, path) = arg
+        .split_once('=')
+        .ok_or_else(|| eyre!("Expected ADDRESS=PATH[:CONTRACT] for --storage-layout, got {}", arg))?;
+    let address = address.parse().map_err(|_| eyre!("Invalid address {} for --storage-layout", address))?;
+    // A contract name never contains a path separator, a Windows drive letter is followed by one
+    match path.rsplit_once(':') {
+        Some((file, contract)) if !file.is_empty() && !contract.is_empty() && !contract.contains(['/', '\\']) => {
+            Ok((address, file, Some(contract)))
+        }
+        _ => Ok((address, path, None)),
+    }
+}
+
+/// Directory results are saved to, mainnet keeps the flat `Results/<name>` layout
+/// and other networks get their own subdirectory so they never collide with it
+fn results_dir(chain: &str, name: &str) -> String {
+    if chain == DEFAULT_NETWORK {
+        format!("Results/{}", name)
+    } else {
+        format!("Results/{}/{}", chain, name)
+    }
+}
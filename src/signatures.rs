@@ -0,0 +1,540 @@
+use ethers::{
+    abi::{Abi, Event, EventParam, HumanReadableParser, ParamType, RawLog, Token},
+    types::{H256, I256, U256},
+    utils::keccak256,
+};
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Signatures shipped with the tool, see `signatures/bundled.txt`
+const BUNDLED: &str = include_str!("../signatures/bundled.txt");
+
+/// Where a signature came from, earlier variants win selector collisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SignatureSource {
+    Artifact, // ABI of a compiled contract, names exactly what was deployed
+    User,     // signature list supplied by the user
+    Bundled,
+}
+
+#[derive(Debug, Clone)]
+struct FunctionEntry {
+    source: SignatureSource,
+    signature: String, // canonical, e.g. `transfer(address,uint256)`
+}
+
+#[derive(Debug, Clone)]
+struct EventEntry {
+    source: SignatureSource,
+    signature: String,
+    event: Event,
+    indexed_known: bool, // whether `event` says which parameters are indexed
+}
+
+/// Function and event signatures known locally, keyed by selector and topic
+///
+/// Filled from the bundled list, signature files (text or JSON) and the ABIs of
+/// Foundry/Hardhat artifacts. Nothing is ever looked up online.
+///
+/// A selector or topic shared by several signatures resolves deterministically:
+/// artifacts beat user files, which beat the bundled list, and within one source
+/// the alphabetically first signature wins.
+#[derive(Debug, Default, Clone)]
+pub struct SignatureDb {
+    functions: HashMap<[u8; 4], Vec<FunctionEntry>>, // best candidate first
+    events: HashMap<H256, Vec<EventEntry>>,
+}
+
+/// Calldata decoded against a known signature
+#[derive(Debug, Clone)]
+pub struct DecodedCall {
+    pub signature: String,
+    pub name: String,
+    pub args: Vec<String>, // empty when the calldata does not match the signature's types
+}
+
+/// Log decoded against a known event signature
+#[derive(Debug, Clone)]
+pub struct DecodedLog {
+    pub signature: String,
+    pub name: String,
+    pub args: Vec<String>, // `name=value`, empty when the split into topics and data is unknown
+}
+
+impl SignatureDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Database holding the bundled signatures only
+    pub fn bundled() -> Self {
+        let mut db = Self::new();
+        db.add_text(BUNDLED, SignatureSource::Bundled)
+            .expect("bundled signatures are valid");
+        db
+    }
+
+    /// Load a signature file, or every signature file and artifact below a directory
+    ///
+    /// `.json` files are artifacts or ABIs (anything with an `abi` array) or selector
+    /// maps such as `{"0xa9059cbb": "transfer(address,uint256)"}`, other files are
+    /// read as text with one signature per line.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let mut entries: Vec<_> = std::fs::read_dir(path)?.collect::<std::io::Result<_>>()?;
+            entries.sort_by_key(|entry| entry.path());
+            for entry in entries {
+                let entry_path = entry.path();
+                let is_signature_file = matches!(entry_path.extension().and_then(|ext| ext.to_str()), Some("json" | "txt"));
+                // Build info holds whole compiler outputs, the artifacts next to it already have every ABI
+                let is_build_info = entry_path.file_name().is_some_and(|name| name == "build-info");
+                if (entry_path.is_dir() && !is_build_info) || is_signature_file {
+                    self.load(&entry_path)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let data = std::fs::read_to_string(path).map_err(|e| eyre!("Could not read {}: {}", path.display(), e))?;
+        let result = if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+            serde_json::from_str(&data).map_err(Into::into).and_then(|json| self.add_json(&json))
+        } else {
+            self.add_text(&data, SignatureSource::User)
+        };
+        result.map_err(|e| eyre!("{}: {}", path.display(), e))
+    }
+
+    /// Add every signature of a text list, one per line, `#` starts a comment
+    ///
+    /// A line holds a canonical signature (`transfer(address,uint256)`) or a Solidity
+    /// declaration (`event Transfer(address indexed from, address indexed to, uint256)`),
+    /// optionally preceded by its selector or topic, which is then checked.
+    pub fn add_text(&mut self, text: &str, source: SignatureSource) -> Result<()> {
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.add_line(line, source)
+                .map_err(|e| eyre!("line {}: {}", line_number + 1, e))?;
+        }
+        Ok(())
+    }
+
+    fn add_line(&mut self, line: &str, source: SignatureSource) -> Result<()> {
+        let (hash, declaration) = match line.split_once(char::is_whitespace) {
+            Some((hash, declaration)) if hash.starts_with("0x") => (Some(hex::decode(&hash[2..])?), declaration.trim()),
+            _ => (None, line),
+        };
+        let is_event = declaration.starts_with("event ") || hash.as_ref().is_some_and(|hash| hash.len() == 32);
+
+        if is_event {
+            let declaration = declaration.trim_start_matches("event ").trim();
+            let event = HumanReadableParser::parse_event(&format!("event {}", declaration))
+                .map_err(|_| eyre!("invalid event signature {}", declaration))?;
+            if hash.is_some_and(|hash| hash != event.signature().as_bytes()) {
+                return Err(eyre!("topic does not match {}", declaration));
+            }
+            let indexed_known = event.inputs.iter().any(|param| param.indexed);
+            self.insert_event(event, indexed_known, source);
+        } else {
+            let declaration = declaration.trim_start_matches("function ").trim();
+            let (name, param_types) =
+                parse_signature(declaration).ok_or_else(|| eyre!("invalid function signature {}", declaration))?;
+            let signature = canonical_signature(&name, &param_types);
+            if hash.is_some_and(|hash| hash != function_selector(&signature)) {
+                return Err(eyre!("selector does not match {}", signature));
+            }
+            self.insert_function(signature, source);
+        }
+        Ok(())
+    }
+
+    /// Add the signatures of a JSON document: an artifact or ABI, or a selector map
+    fn add_json(&mut self, json: &serde_json::Value) -> Result<()> {
+        // Foundry and Hardhat artifacts keep the ABI under `abi`, a bare ABI is an array of items
+        let abi = match json {
+            serde_json::Value::Object(object) if object.get("abi").is_some_and(|abi| abi.is_array()) => object.get("abi"),
+            serde_json::Value::Array(items) if items.iter().all(|item| item.get("type").is_some()) => Some(json),
+            _ => None,
+        };
+        if let Some(abi) = abi {
+            let abi: Abi = serde_json::from_value(abi.clone())?;
+            self.add_abi(&abi);
+            return Ok(());
+        }
+        self.add_selector_map(json)
+    }
+
+    /// Add every function and event of a contract ABI
+    pub fn add_abi(&mut self, abi: &Abi) {
+        for function in abi.functions() {
+            let param_types: Vec<ParamType> = function.inputs.iter().map(|param| param.kind.clone()).collect();
+            self.insert_function(canonical_signature(&function.name, &param_types), SignatureSource::Artifact);
+        }
+        for event in abi.events() {
+            self.insert_event(event.clone(), true, SignatureSource::Artifact);
+        }
+    }
+
+    /// Add a `{"0x<selector or topic>": signature(s)}` map, nested under any keys
+    ///
+    /// Values are a signature, a list of them, or objects with a `name` (as exported
+    /// by signature databases), so dumps of 4byte-style services load as they are.
+    fn add_selector_map(&mut self, json: &serde_json::Value) -> Result<()> {
+        let serde_json::Value::Object(object) = json else {
+            return Ok(());
+        };
+        for (key, value) in object {
+            if !key.starts_with("0x") {
+                self.add_selector_map(value)?;
+                continue;
+            }
+            let names: Vec<&str> = match value {
+                serde_json::Value::String(name) => vec![name.as_str()],
+                serde_json::Value::Array(items) => items
+                    .iter()
+                    .filter_map(|item| item.as_str().or_else(|| item.get("name").and_then(|name| name.as_str())))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for name in names {
+                let is_event = key.len() == 2 + 64;
+                let line = if is_event { format!("{} event {}", key, name) } else { format!("{} {}", key, name) };
+                self.add_line(&line, SignatureSource::User)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a function signature, canonical form
+    pub fn insert_function(&mut self, signature: String, source: SignatureSource) {
+        let candidates = self.functions.entry(function_selector(&signature)).or_default();
+        match candidates.iter_mut().find(|entry| entry.signature == signature) {
+            Some(entry) => entry.source = entry.source.min(source),
+            None => candidates.push(FunctionEntry { source, signature }),
+        }
+        candidates.sort_by(|a, b| (a.source, &a.signature).cmp(&(b.source, &b.signature)));
+    }
+
+    fn insert_event(&mut self, event: Event, indexed_known: bool, source: SignatureSource) {
+        let param_types: Vec<ParamType> = event.inputs.iter().map(|param| param.kind.clone()).collect();
+        let signature = canonical_signature(&event.name, &param_types);
+        let candidates = self.events.entry(event.signature()).or_default();
+        let entry = EventEntry { source, signature, event, indexed_known };
+        match candidates.iter_mut().find(|existing| existing.signature == entry.signature) {
+            // Keep whichever knows more about the parameters
+            Some(existing) if (entry.indexed_known, std::cmp::Reverse(entry.source)) > (existing.indexed_known, std::cmp::Reverse(existing.source)) => {
+                *existing = entry
+            }
+            Some(_) => {}
+            None => candidates.push(entry),
+        }
+        candidates.sort_by(|a, b| (a.source, &a.signature).cmp(&(b.source, &b.signature)));
+    }
+
+    /// Number of known function signatures
+    pub fn len(&self) -> usize {
+        self.functions.values().map(|candidates| candidates.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.events.is_empty()
+    }
+
+    /// Number of known event signatures
+    pub fn event_count(&self) -> usize {
+        self.events.values().map(|candidates| candidates.len()).sum()
+    }
+
+    /// Selectors and topics shared by more than one signature
+    pub fn collision_count(&self) -> usize {
+        self.functions.values().filter(|candidates| candidates.len() > 1).count()
+            + self.events.values().filter(|candidates| candidates.len() > 1).count()
+    }
+
+    /// Signature of a selector, if known
+    pub fn function(&self, selector: [u8; 4]) -> Option<&str> {
+        self.functions.get(&selector)?.first().map(|entry| entry.signature.as_str())
+    }
+
+    /// Every signature sharing a selector, the one [`Self::function`] picks first
+    pub fn function_candidates(&self, selector: [u8; 4]) -> Vec<&str> {
+        self.functions
+            .get(&selector)
+            .map(|candidates| candidates.iter().map(|entry| entry.signature.as_str()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Signature of an event topic, if known
+    pub fn event(&self, topic: H256) -> Option<&str> {
+        self.events.get(&topic)?.first().map(|entry| entry.signature.as_str())
+    }
+
+    /// Decode calldata, selector first
+    ///
+    /// Candidates sharing the selector are tried in precedence order, the first one whose
+    /// types encode the arguments exactly is used. When none does, the best candidate is
+    /// returned with whatever it decodes, or no arguments.
+    pub fn decode_call(&self, input: &[u8]) -> Option<DecodedCall> {
+        let selector: [u8; 4] = input.get(..4)?.try_into().ok()?;
+        let candidates: Vec<(&str, String, Vec<ParamType>)> = self
+            .function_candidates(selector)
+            .into_iter()
+            .filter_map(|signature| {
+                let (name, param_types) = parse_signature(signature)?;
+                Some((signature, name, param_types))
+            })
+            .collect();
+        let args = &input[4..];
+        let exact = candidates.iter().find_map(|(signature, name, param_types)| {
+            let tokens = ethers::abi::decode(param_types, args).ok()?;
+            (ethers::abi::encode(&tokens) == args).then_some((signature, name, tokens))
+        });
+        if let Some((signature, name, tokens)) = exact {
+            let args = tokens.iter().map(format_token).collect();
+            return Some(DecodedCall { signature: signature.to_string(), name: name.clone(), args });
+        }
+
+        let (signature, name, param_types) = candidates.first()?;
+        let args = ethers::abi::decode(param_types, args)
+            .map(|tokens| tokens.iter().map(format_token).collect())
+            .unwrap_or_default();
+        Some(DecodedCall { signature: signature.to_string(), name: name.clone(), args })
+    }
+
+    /// Decode a log from its topics (event topic first) and data
+    ///
+    /// Which parameters are indexed is taken from the declaration. Signatures given
+    /// without `indexed` only decode when the topic count makes the split unambiguous.
+    pub fn decode_log(&self, topics: &[H256], data: &[u8]) -> Option<DecodedLog> {
+        let entry = self.events.get(topics.first()?)?.first()?;
+        let indexed_count = topics.len() - 1;
+        // ERC-20 and ERC-721 Transfer share a topic but index a different number of parameters
+        let declared_indexed = entry.event.inputs.iter().filter(|param| param.indexed).count();
+        let event = if entry.indexed_known && declared_indexed == indexed_count {
+            entry.event.clone()
+        } else if indexed_count == 0 || indexed_count == entry.event.inputs.len() {
+            let inputs = entry
+                .event
+                .inputs
+                .iter()
+                .map(|param| EventParam { indexed: indexed_count > 0, ..param.clone() })
+                .collect();
+            Event { inputs, ..entry.event.clone() }
+        } else {
+            return Some(DecodedLog { signature: entry.signature.clone(), name: entry.event.name.clone(), args: Vec::new() });
+        };
+
+        let args = event
+            .parse_log(RawLog { topics: topics.to_vec(), data: data.to_vec() })
+            .map(|log| {
+                log.params
+                    .iter()
+                    .map(|param| match param.name.as_str() {
+                        "" => format_token(&param.value),
+                        name => format!("{}={}", name, format_token(&param.value)),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(DecodedLog { signature: entry.signature.clone(), name: entry.event.name.clone(), args })
+    }
+}
+
+/// Selector of a canonical function signature
+pub fn function_selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Canonical signature, e.g. `transfer(address,uint256)`
+fn canonical_signature(name: &str, param_types: &[ParamType]) -> String {
+    let params: Vec<String> = param_types.iter().map(|kind| kind.to_string()).collect();
+    format!("{}({})", name, params.join(","))
+}
+
+/// Name and parameter types of a function signature, canonical or with parameter names
+fn parse_signature(signature: &str) -> Option<(String, Vec<ParamType>)> {
+    let function = HumanReadableParser::parse_function(&format!("function {}", signature)).ok()?;
+    let param_types = function.inputs.into_iter().map(|param| param.kind).collect();
+    Some((function.name, param_types))
+}
+
+/// Render a decoded value the way it would be written in Solidity
+pub fn format_token(token: &Token) -> String {
+    match token {
+        Token::Address(address) => format!("{:?}", address),
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => I256::from_raw(*value).to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => format!("{:?}", value),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", hex::encode(bytes)),
+        Token::Array(tokens) | Token::FixedArray(tokens) => {
+            format!("[{}]", tokens.iter().map(format_token).collect::<Vec<_>>().join(", "))
+        }
+        Token::Tuple(tokens) => format!("({})", tokens.iter().map(format_token).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+/// Render a wei amount in ether, e.g. `1.5 ETH`
+pub fn format_ether(wei: U256) -> String {
+    ethers::utils::format_ether(wei).trim_end_matches('0').trim_end_matches('.').to_string() + " ETH"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Both have selector 0x42966c68
+    const BURN: &str = "burn(uint256)";
+    const COLLATE: &str = "collate_propagate_storage(bytes16)";
+
+    /// keccak256("Transfer(address,address,uint256)")
+    const TRANSFER_TOPIC: &str = "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+    fn word(value: u64) -> H256 {
+        H256::from_low_u64_be(value)
+    }
+
+    fn transfer_topic() -> H256 {
+        H256::from_slice(&hex::decode(TRANSFER_TOPIC).unwrap())
+    }
+
+    fn calldata(signature: &str, args: &[H256]) -> Vec<u8> {
+        let mut input = function_selector(signature).to_vec();
+        for arg in args {
+            input.extend_from_slice(arg.as_bytes());
+        }
+        input
+    }
+
+    #[test]
+    fn collisions_resolve_by_source_then_name() {
+        assert_eq!(function_selector(BURN), function_selector(COLLATE));
+        let selector = function_selector(BURN);
+
+        // Same source: alphabetical
+        let mut db = SignatureDb::new();
+        db.insert_function(COLLATE.to_string(), SignatureSource::Bundled);
+        db.insert_function(BURN.to_string(), SignatureSource::Bundled);
+        assert_eq!(db.function_candidates(selector), vec![BURN, COLLATE]);
+        assert_eq!(db.collision_count(), 1);
+
+        // User beats Bundled, Artifact beats both
+        db.insert_function(COLLATE.to_string(), SignatureSource::User);
+        assert_eq!(db.function(selector), Some(COLLATE));
+        db.insert_function(BURN.to_string(), SignatureSource::Artifact);
+        assert_eq!(db.function(selector), Some(BURN));
+        // Adding again from a weaker source does not demote it
+        db.insert_function(BURN.to_string(), SignatureSource::Bundled);
+        assert_eq!(db.function_candidates(selector), vec![BURN, COLLATE]);
+        assert_eq!(db.len(), 2);
+    }
+
+    #[test]
+    fn decode_call_falls_back_to_the_candidate_that_decodes() {
+        let mut db = SignatureDb::new();
+        db.insert_function(COLLATE.to_string(), SignatureSource::User);
+        db.insert_function(BURN.to_string(), SignatureSource::Bundled);
+
+        // A bytes16 argument leaves the low half of the word zero, so the preferred candidate fits
+        let bytes16 = H256::from_slice(&[[0xab; 16], [0; 16]].concat());
+        let call = db.decode_call(&calldata(COLLATE, &[bytes16])).unwrap();
+        assert_eq!(call.signature, COLLATE);
+        assert_eq!(call.args, vec![format!("0x{}", "ab".repeat(16))]);
+
+        // burn(1) is not a valid bytes16, the next candidate is used
+        let call = db.decode_call(&calldata(BURN, &[word(1)])).unwrap();
+        assert_eq!(call.signature, BURN);
+        assert_eq!(call.name, "burn");
+        assert_eq!(call.args, vec!["1"]);
+
+        // Nothing encodes a short argument exactly: the preferred candidate, decoded leniently
+        let call = db.decode_call(&function_selector(BURN)).unwrap();
+        assert_eq!(call.signature, COLLATE);
+        assert!(call.args.is_empty());
+
+        assert!(db.decode_call(&[0xa9, 0x05, 0x9c]).is_none());
+        assert!(db.decode_call(&calldata("transfer(address,uint256)", &[])).is_none());
+    }
+
+    #[test]
+    fn insert_event_prefers_known_indexed_flags() {
+        let topics = [transfer_topic(), word(0xaa), word(0xbb)];
+        let data = word(5).as_bytes().to_vec();
+
+        // The bundled declaration knows which parameters are indexed, a user file without `indexed` does not
+        let mut db = SignatureDb::new();
+        db.add_text("event Transfer(address from, address to, uint256 value)", SignatureSource::User).unwrap();
+        db.add_text("event Transfer(address indexed from, address indexed to, uint256 value)", SignatureSource::Bundled)
+            .unwrap();
+        assert_eq!(db.event_count(), 1);
+        let log = db.decode_log(&topics, &data).unwrap();
+        assert_eq!(log.signature, "Transfer(address,address,uint256)");
+        assert_eq!(
+            log.args,
+            vec![
+                "from=0x00000000000000000000000000000000000000aa",
+                "to=0x00000000000000000000000000000000000000bb",
+                "value=5"
+            ]
+        );
+
+        // Between two declarations that both know, the better source wins
+        db.add_text("event Transfer(address indexed src, address indexed dst, uint256 wad)", SignatureSource::User)
+            .unwrap();
+        let log = db.decode_log(&topics, &data).unwrap();
+        assert_eq!(log.args[2], "wad=5");
+        db.add_text("event Transfer(address indexed owner, address indexed recipient, uint256 amount)", SignatureSource::Bundled)
+            .unwrap();
+        assert_eq!(db.decode_log(&topics, &data).unwrap().args[2], "wad=5");
+    }
+
+    #[test]
+    fn decode_log_splits_erc20_and_erc721_transfer() {
+        let db = SignatureDb::bundled();
+        assert_eq!(db.event(transfer_topic()), Some("Transfer(address,address,uint256)"));
+
+        // ERC-20: the amount is in the data
+        let log = db.decode_log(&[transfer_topic(), word(0xaa), word(0xbb)], word(5).as_bytes()).unwrap();
+        assert_eq!(log.args.last().map(String::as_str), Some("value=5"));
+
+        // ERC-721: the token id is indexed too, the data is empty
+        let log = db.decode_log(&[transfer_topic(), word(0xaa), word(0xbb), word(7)], &[]).unwrap();
+        assert_eq!(log.name, "Transfer");
+        assert_eq!(log.args.len(), 3);
+        assert_eq!(log.args.last().map(String::as_str), Some("value=7"));
+
+        // Any other split cannot be decoded, only named
+        let log = db.decode_log(&[transfer_topic(), word(0xaa)], &[0; 64]).unwrap();
+        assert_eq!(log.name, "Transfer");
+        assert!(log.args.is_empty());
+    }
+
+    #[test]
+    fn add_selector_map_reads_nested_and_exported_formats() {
+        let mut db = SignatureDb::new();
+        db.add_json(&json!({
+            "0xa9059cbb": "transfer(address,uint256)",
+            "functions": {
+                "0x42966c68": [BURN, { "name": COLLATE }],
+            },
+            "events": {
+                format!("0x{}", TRANSFER_TOPIC): "Transfer(address indexed from, address indexed to, uint256 value)",
+            },
+        }))
+        .unwrap();
+        assert_eq!(db.function([0xa9, 0x05, 0x9c, 0xbb]), Some("transfer(address,uint256)"));
+        assert_eq!(db.function_candidates(function_selector(BURN)), vec![BURN, COLLATE]);
+        assert_eq!(db.event(transfer_topic()), Some("Transfer(address,address,uint256)"));
+        assert_eq!((db.len(), db.event_count()), (3, 1));
+
+        // Selectors are checked against their signature
+        let error = db.add_json(&json!({ "0x12345678": "transfer(address,uint256)" })).unwrap_err();
+        assert!(error.to_string().contains("selector does not match"), "{}", error);
+    }
+}
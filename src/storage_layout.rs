@@ -0,0 +1,456 @@
+use crate::signatures::format_token;
+use ethers::{
+    abi::{self, ParamType, Token},
+    types::{BigEndianHash, Bytes, H256, U256},
+};
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Mappings nested deeper than this are not resolved, e.g. `allowance[owner][spender]` needs 2
+const MAX_MAPPING_DEPTH: usize = 8;
+
+/// Largest distance between a slot and the hash it is derived from, bounds dynamic array indices
+const MAX_HASH_OFFSET: u64 = 1 << 32;
+
+/// Storage layout of a contract as emitted by solc (`storageLayout` output selection)
+///
+/// Maps raw slots back to state variables. Mapping entries and dynamic array elements
+/// live at hashes of their key and slot, those are resolved with the KECCAK256
+/// preimages seen in the trace.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageLayout {
+    pub storage: Vec<StorageVariable>,
+    pub types: Option<HashMap<String, StorageType>>, // null when the contract has no state variables
+}
+
+/// A state variable or struct member
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageVariable {
+    pub label: String,
+    pub offset: usize, // byte offset within the slot, for variables packed together
+    pub slot: String,  // decimal, relative to the struct for members
+    #[serde(rename = "type")]
+    pub type_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageType {
+    pub encoding: String, // inplace, mapping, dynamic_array or bytes
+    pub label: String,
+    #[serde(rename = "numberOfBytes")]
+    pub number_of_bytes: String,
+    pub key: Option<String>,   // mappings
+    pub value: Option<String>, // mappings
+    pub base: Option<String>,  // element type of arrays
+    pub members: Option<Vec<StorageVariable>>, // structs
+}
+
+/// The value a slot belongs to
+#[derive(Debug, Clone)]
+struct Location<'a> {
+    path: String,
+    type_id: &'a str,
+    base: U256, // first slot of the value
+}
+
+impl StorageLayout {
+    /// Load a storage layout from solc output, a Foundry artifact or Hardhat build info
+    ///
+    /// `contract` picks the contract when the file holds several, it can be left out
+    /// when only one of them has a storage layout.
+    pub fn load(path: impl AsRef<Path>, contract: Option<&str>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path).map_err(|e| eyre!("Could not read {}: {}", path.display(), e))?;
+        let json: serde_json::Value = serde_json::from_str(&data).map_err(|e| eyre!("{}: {}", path.display(), e))?;
+        Self::from_json(&json, contract).map_err(|e| eyre!("{}: {}", path.display(), e))
+    }
+
+    /// Find the storage layout in a bare layout, an artifact or a compiler output
+    pub fn from_json(json: &serde_json::Value, contract: Option<&str>) -> Result<Self> {
+        if json.get("storage").is_some() {
+            return Ok(serde_json::from_value(json.clone())?);
+        }
+        if let Some(layout) = json.get("storageLayout") {
+            return Ok(serde_json::from_value(layout.clone())?);
+        }
+        // solc standard JSON output, Hardhat build info wraps it in `output`
+        let contracts = json
+            .pointer("/output/contracts")
+            .or_else(|| json.get("contracts"))
+            .and_then(|contracts| contracts.as_object())
+            .ok_or_else(|| eyre!("no storageLayout found"))?;
+        let candidates: Vec<(&String, &serde_json::Value)> = contracts
+            .values()
+            .filter_map(|source| source.as_object())
+            .flatten()
+            .filter_map(|(name, output)| Some((name, output.get("storageLayout")?)))
+            .filter(|(name, _)| contract.is_none_or(|contract| contract == name.as_str()))
+            .collect();
+        match candidates.as_slice() {
+            [(_, layout)] => Ok(serde_json::from_value((*layout).clone())?),
+            [] => Err(eyre!("no storageLayout found{}", contract.map(|contract| format!(" for {}", contract)).unwrap_or_default())),
+            _ => Err(eyre!(
+                "several contracts have a storageLayout, pick one of {}",
+                candidates.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+
+    /// Name of the variable a slot holds, e.g. `balanceOf[0xab…]` or `config.owner`
+    ///
+    /// Variables packed into the same slot are listed together. Returns `None` for
+    /// slots outside the layout and for hashed slots whose preimage was not traced.
+    pub fn slot_name(&self, slot: H256, preimages: &HashMap<H256, Bytes>) -> Option<String> {
+        let locations = self.locate(slot.into_uint(), preimages, MAX_MAPPING_DEPTH);
+        if locations.is_empty() {
+            return None;
+        }
+        Some(locations.iter().map(|location| location.path.as_str()).collect::<Vec<_>>().join(", "))
+    }
+
+    /// Values stored in a slot, several when they are packed together
+    fn locate(&self, slot: U256, preimages: &HashMap<H256, Bytes>, depth: usize) -> Vec<Location<'_>> {
+        let direct = self.members_at(&self.storage, U256::zero(), slot, "");
+        if !direct.is_empty() || depth == 0 {
+            return direct;
+        }
+
+        // Mapping entries and array data live at the hash of their key and the slot holding them
+        let Some((hash, input)) = preimages
+            .iter()
+            .map(|(hash, input)| (hash.into_uint(), input))
+            .filter(|(hash, input)| slot >= *hash && slot - *hash < U256::from(MAX_HASH_OFFSET) && input.len() >= 32)
+            .min_by_key(|(hash, _)| slot - *hash)
+        else {
+            return Vec::new();
+        };
+        let (key, parent_slot) = input.split_at(input.len() - 32);
+        let parent_slot = U256::from_big_endian(parent_slot);
+        let Some((parent, parent_type)) = self
+            .locate(parent_slot, preimages, depth - 1)
+            .into_iter()
+            .filter(|parent| parent.base == parent_slot)
+            .find_map(|parent| Some((parent.clone(), self.storage_type(parent.type_id)?)))
+        else {
+            return Vec::new();
+        };
+
+        match parent_type.encoding.as_str() {
+            "mapping" => {
+                let (Some(key_type), Some(value_type)) = (&parent_type.key, &parent_type.value) else {
+                    return Vec::new();
+                };
+                let path = format!("{}[{}]", parent.path, self.format_key(key, key_type));
+                self.descend(path, value_type, hash, slot)
+            }
+            "dynamic_array" if key.is_empty() => match &parent_type.base {
+                Some(element_type) => self.elements(&parent.path, element_type, hash, slot),
+                None => Vec::new(),
+            },
+            // Long strings and bytes keep their data from the hash on
+            "bytes" if key.is_empty() => vec![Location {
+                path: format!("{}.data[{}]", parent.path, slot - hash),
+                type_id: parent.type_id,
+                base: slot,
+            }],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Variables or struct members laid out from `base` that occupy `slot`
+    fn members_at<'a>(&'a self, members: &'a [StorageVariable], base: U256, slot: U256, prefix: &str) -> Vec<Location<'a>> {
+        let mut locations = Vec::new();
+        for member in members {
+            let Ok(member_slot) = U256::from_dec_str(&member.slot) else {
+                continue;
+            };
+            let member_base = base + member_slot;
+            if slot >= member_base && slot < member_base + self.slot_count(&member.type_id) {
+                let path = format!("{}{}", prefix, member.label);
+                locations.extend(self.descend(path, &member.type_id, member_base, slot));
+            }
+        }
+        locations
+    }
+
+    /// Go into structs and fixed-size arrays down to the value occupying `slot`
+    fn descend<'a>(&'a self, path: String, type_id: &'a str, base: U256, slot: U256) -> Vec<Location<'a>> {
+        let Some(storage_type) = self.storage_type(type_id).filter(|storage_type| storage_type.encoding == "inplace") else {
+            return vec![Location { path, type_id, base }];
+        };
+        if let Some(members) = &storage_type.members {
+            return self.members_at(members, base, slot, &format!("{}.", path));
+        }
+        match &storage_type.base {
+            Some(element_type) => self.elements(&path, element_type, base, slot),
+            None => vec![Location { path, type_id, base }],
+        }
+    }
+
+    /// Array element occupying `slot`, small elements are packed several to a slot
+    fn elements<'a>(&'a self, path: &str, element_type: &'a str, base: U256, slot: U256) -> Vec<Location<'a>> {
+        let element_size = self.byte_count(element_type);
+        let offset = slot - base;
+        if element_size > 0 && element_size <= 16 {
+            let per_slot = U256::from(32 / element_size);
+            let first = offset * per_slot;
+            return vec![Location {
+                path: format!("{}[{}..{}]", path, first, first + per_slot),
+                type_id: element_type,
+                base: slot,
+            }];
+        }
+        let element_slots = self.slot_count(element_type);
+        let index = offset / element_slots;
+        self.descend(format!("{}[{}]", path, index), element_type, base + index * element_slots, slot)
+    }
+
+    fn storage_type(&self, type_id: &str) -> Option<&StorageType> {
+        self.types.as_ref()?.get(type_id)
+    }
+
+    /// Bytes a value of the type takes in place, 32 for mappings and dynamic data
+    fn byte_count(&self, type_id: &str) -> usize {
+        self.storage_type(type_id)
+            .and_then(|storage_type| storage_type.number_of_bytes.parse().ok())
+            .unwrap_or(32)
+    }
+
+    /// Slots a value of the type takes in place, at least one
+    fn slot_count(&self, type_id: &str) -> U256 {
+        let bytes = self.storage_type(type_id).and_then(|storage_type| U256::from_dec_str(&storage_type.number_of_bytes).ok());
+        bytes.map_or(U256::one(), |bytes| ((bytes + 31) / 32).max(U256::one()))
+    }
+
+    /// Render a mapping key: value types are padded to a word, strings and bytes are hashed as they are
+    fn format_key(&self, key: &[u8], key_type: &str) -> String {
+        let label = self.storage_type(key_type).map_or("bytes32", |storage_type| storage_type.label.as_str());
+        let param_type = if label.starts_with("contract ") {
+            Some(ParamType::Address)
+        } else if label.starts_with("enum ") {
+            Some(ParamType::Uint(8))
+        } else {
+            abi::HumanReadableParser::parse_type(label).ok()
+        };
+        match param_type {
+            Some(ParamType::String) => format_token(&Token::String(String::from_utf8_lossy(key).into_owned())),
+            Some(ParamType::Bytes) | None => format_token(&Token::Bytes(key.to_vec())),
+            Some(param_type) => match abi::decode(&[param_type], key) {
+                Ok(tokens) if key.len() == 32 => tokens.iter().map(format_token).collect(),
+                _ => format_token(&Token::Bytes(key.to_vec())),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::utils::keccak256;
+    use serde_json::json;
+
+    /// keccak256 of slot 0 and slot 1, where the data of dynamic arrays at those slots starts
+    const DATA_OF_SLOT_0: &str = "290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563";
+    const DATA_OF_SLOT_1: &str = "b10e2d527612073b26eecdfd717e6a320cf44b4afac2b0732d9fcbe2b7fa0cf6";
+
+    const OWNER: &str = "00000000000000000000000000000000000000000000000000000000000000aa";
+    const SPENDER: &str = "00000000000000000000000000000000000000000000000000000000000000bb";
+
+    /// contract Token {
+    ///     address[] holders;                          // slot 0
+    ///     address owner; bool paused;                 // slot 1, packed
+    ///     mapping(address => uint256) balanceOf;      // slot 2
+    ///     mapping(address => mapping(address => uint256)) allowance; // slot 3
+    ///     string name;                                // slot 4
+    ///     uint8[] flags;                              // slot 5
+    ///     Config config;                              // slots 6 and 7, struct Config { uint256 fee; address admin; }
+    ///     mapping(string => Config) configs;          // slot 8
+    /// }
+    fn layout() -> StorageLayout {
+        let variable = |label: &str, slot: &str, offset: usize, type_id: &str| {
+            json!({ "astId": 1, "contract": "Token.sol:Token", "label": label, "offset": offset, "slot": slot, "type": type_id })
+        };
+        let inplace = |label: &str, bytes: &str| json!({ "encoding": "inplace", "label": label, "numberOfBytes": bytes });
+        let mapping = |label: &str, key: &str, value: &str| {
+            json!({ "encoding": "mapping", "label": label, "numberOfBytes": "32", "key": key, "value": value })
+        };
+        let dynamic_array = |label: &str, base: &str| {
+            json!({ "encoding": "dynamic_array", "label": label, "numberOfBytes": "32", "base": base })
+        };
+        let bytes = json!({ "encoding": "bytes", "label": "string", "numberOfBytes": "32" });
+        let config = "t_struct(Config)10_storage";
+        let json = json!({
+            "storage": [
+                variable("holders", "0", 0, "t_array(t_address)dyn_storage"),
+                variable("owner", "1", 0, "t_address"),
+                variable("paused", "1", 20, "t_bool"),
+                variable("balanceOf", "2", 0, "t_mapping(t_address,t_uint256)"),
+                variable("allowance", "3", 0, "t_mapping(t_address,t_mapping(t_address,t_uint256))"),
+                variable("name", "4", 0, "t_string_storage"),
+                variable("flags", "5", 0, "t_array(t_uint8)dyn_storage"),
+                variable("config", "6", 0, config),
+                variable("configs", "8", 0, "t_mapping(t_string_memory_ptr,t_struct(Config)10_storage)"),
+            ],
+            "types": {
+                "t_address": inplace("address", "20"),
+                "t_bool": inplace("bool", "1"),
+                "t_uint8": inplace("uint8", "1"),
+                "t_uint256": inplace("uint256", "32"),
+                "t_bytes32": inplace("bytes32", "32"),
+                "t_contract(IERC20)5": inplace("contract IERC20", "20"),
+                "t_enum(Kind)3": inplace("enum Token.Kind", "1"),
+                "t_string_storage": bytes.clone(),
+                "t_string_memory_ptr": bytes,
+                "t_array(t_address)dyn_storage": dynamic_array("address[]", "t_address"),
+                "t_array(t_uint8)dyn_storage": dynamic_array("uint8[]", "t_uint8"),
+                "t_mapping(t_address,t_uint256)": mapping("mapping(address => uint256)", "t_address", "t_uint256"),
+                "t_mapping(t_address,t_mapping(t_address,t_uint256))": mapping(
+                    "mapping(address => mapping(address => uint256))",
+                    "t_address",
+                    "t_mapping(t_address,t_uint256)"
+                ),
+                "t_mapping(t_string_memory_ptr,t_struct(Config)10_storage)": mapping(
+                    "mapping(string => struct Token.Config)",
+                    "t_string_memory_ptr",
+                    config
+                ),
+                config: {
+                    "encoding": "inplace",
+                    "label": "struct Token.Config",
+                    "numberOfBytes": "64",
+                    "members": [variable("fee", "0", 0, "t_uint256"), variable("admin", "1", 0, "t_address")],
+                },
+            },
+        });
+        StorageLayout::from_json(&json, None).unwrap()
+    }
+
+    fn slot(n: u64) -> H256 {
+        H256::from_uint(&U256::from(n))
+    }
+
+    /// Record the KECCAK256 of `key ++ slot` as the trace would, returns the hash
+    fn hash(preimages: &mut HashMap<H256, Bytes>, key: &[u8], slot: H256) -> H256 {
+        let input = [key, slot.as_bytes()].concat();
+        let hash = H256(keccak256(&input));
+        preimages.insert(hash, input.into());
+        hash
+    }
+
+    fn plus(hash: H256, offset: u64) -> H256 {
+        H256::from_uint(&(hash.into_uint() + offset))
+    }
+
+    fn name(slot: H256, preimages: &HashMap<H256, Bytes>) -> Option<String> {
+        layout().slot_name(slot, preimages)
+    }
+
+    #[test]
+    fn names_plain_and_packed_variables() {
+        let preimages = HashMap::new();
+        assert_eq!(name(slot(0), &preimages).as_deref(), Some("holders"));
+        assert_eq!(name(slot(1), &preimages).as_deref(), Some("owner, paused"));
+        assert_eq!(name(slot(2), &preimages).as_deref(), Some("balanceOf"));
+        assert_eq!(name(slot(9), &preimages), None);
+    }
+
+    #[test]
+    fn names_struct_members_by_offset() {
+        let preimages = HashMap::new();
+        assert_eq!(name(slot(6), &preimages).as_deref(), Some("config.fee"));
+        assert_eq!(name(slot(7), &preimages).as_deref(), Some("config.admin"));
+    }
+
+    #[test]
+    fn names_mapping_entries() {
+        let mut preimages = HashMap::new();
+        let entry = hash(&mut preimages, &hex::decode(OWNER).unwrap(), slot(2));
+        assert_eq!(
+            name(entry, &preimages).as_deref(),
+            Some("balanceOf[0x00000000000000000000000000000000000000aa]")
+        );
+        // Too far above the only traced hash to be derived from it
+        assert_eq!(name(plus(entry, 1 << 33), &preimages), None);
+    }
+
+    #[test]
+    fn names_nested_mapping_entries() {
+        let mut preimages = HashMap::new();
+        let inner = hash(&mut preimages, &hex::decode(OWNER).unwrap(), slot(3));
+        let entry = hash(&mut preimages, &hex::decode(SPENDER).unwrap(), inner);
+        assert_eq!(
+            name(entry, &preimages).as_deref(),
+            Some("allowance[0x00000000000000000000000000000000000000aa][0x00000000000000000000000000000000000000bb]")
+        );
+        assert_eq!(
+            name(inner, &preimages).as_deref(),
+            Some("allowance[0x00000000000000000000000000000000000000aa]")
+        );
+    }
+
+    #[test]
+    fn names_dynamic_array_elements_and_bytes_data() {
+        let mut preimages = HashMap::new();
+        let holders = hash(&mut preimages, &[], slot(0));
+        assert_eq!(hex::encode(holders), DATA_OF_SLOT_0);
+        assert_eq!(name(plus(holders, 2), &preimages).as_deref(), Some("holders[2]"));
+
+        let name_data = hash(&mut preimages, &[], slot(4));
+        assert_eq!(name(plus(name_data, 1), &preimages).as_deref(), Some("name.data[1]"));
+    }
+
+    #[test]
+    fn names_packed_array_elements_by_range() {
+        let mut preimages = HashMap::new();
+        let flags = hash(&mut preimages, &[], slot(5));
+        // 32 uint8 elements share a slot
+        assert_eq!(name(flags, &preimages).as_deref(), Some("flags[0..32]"));
+        assert_eq!(name(plus(flags, 1), &preimages).as_deref(), Some("flags[32..64]"));
+    }
+
+    #[test]
+    fn names_struct_members_of_mapping_values() {
+        let mut preimages = HashMap::new();
+        let entry = hash(&mut preimages, b"usdc", slot(8));
+        assert_eq!(name(entry, &preimages).as_deref(), Some("configs[\"usdc\"].fee"));
+        assert_eq!(name(plus(entry, 1), &preimages).as_deref(), Some("configs[\"usdc\"].admin"));
+    }
+
+    #[test]
+    fn picks_the_nearest_hash_below_the_slot() {
+        let mut preimages = HashMap::new();
+        let holders = hash(&mut preimages, &[], slot(0));
+        let owner_data = hash(&mut preimages, &[], slot(1));
+        assert_eq!(hex::encode(owner_data), DATA_OF_SLOT_1);
+        // keccak(0) < keccak(1): slots just above keccak(1) belong to it, even though
+        // they are also above keccak(0)
+        assert!(holders < owner_data);
+        // `owner` is not an array, so a slot derived from it has no name
+        assert_eq!(name(plus(owner_data, 3), &preimages), None);
+        assert_eq!(name(plus(holders, 3), &preimages).as_deref(), Some("holders[3]"));
+        // Too far from any hash to be an array index
+        assert_eq!(name(plus(holders, MAX_HASH_OFFSET), &preimages), None);
+        assert_eq!(name(plus(holders, MAX_HASH_OFFSET - 1), &preimages).as_deref(), Some("holders[4294967295]"));
+    }
+
+    #[test]
+    fn formats_keys_by_type() {
+        let layout = layout();
+        let word = |value: u64| H256::from_low_u64_be(value).as_bytes().to_vec();
+        assert_eq!(layout.format_key(&word(0xaa), "t_address"), "0x00000000000000000000000000000000000000aa");
+        assert_eq!(layout.format_key(&word(0xaa), "t_contract(IERC20)5"), "0x00000000000000000000000000000000000000aa");
+        assert_eq!(layout.format_key(&word(2), "t_enum(Kind)3"), "2");
+        assert_eq!(layout.format_key(&word(1000), "t_uint256"), "1000");
+        assert_eq!(layout.format_key(&word(1), "t_bool"), "true");
+        assert_eq!(layout.format_key(b"usdc", "t_string_memory_ptr"), "\"usdc\"");
+        assert_eq!(
+            layout.format_key(&word(1), "t_bytes32"),
+            "0x0000000000000000000000000000000000000000000000000000000000000001"
+        );
+        // Unknown types and keys of the wrong size are shown as raw bytes
+        assert_eq!(layout.format_key(&[1, 2], "t_unknown"), "0x0102");
+        assert_eq!(layout.format_key(&[1, 2], "t_uint256"), "0x0102");
+    }
+}
@@ -0,0 +1,333 @@
+use ethers::types::{BlockId, BlockNumber, H160, H256};
+use evm_cfg::analyzer::TransactionAnalyzer;
+use evm_cfg::blockchain::{BlockchainService, EthersBlockchainService, FetchOptions};
+use evm_cfg::code_cache::DiskCodeCache;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Answer to one HTTP request: status code and JSON body
+type Reply = (u16, Value);
+
+/// A JSON-RPC node on a local port, recording every request it gets
+///
+/// `respond` is called with each request body and the number of requests seen before it.
+struct MockNode {
+    url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockNode {
+    async fn start(respond: impl Fn(&Value, usize) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(serve(socket, seen.clone(), respond.clone()));
+            }
+        });
+        Self { url, requests }
+    }
+
+    fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Serve the requests of one keep-alive connection until the client closes it
+async fn serve(mut socket: TcpStream, requests: Arc<Mutex<Vec<Value>>>, respond: Arc<impl Fn(&Value, usize) -> Reply>) {
+    let mut buffer = Vec::new();
+    loop {
+        let Some(body) = read_request(&mut socket, &mut buffer).await else {
+            return;
+        };
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let (status, reply) = {
+            let mut requests = requests.lock().unwrap();
+            let reply = respond(&request, requests.len());
+            requests.push(request);
+            reply
+        };
+        let reply = reply.to_string();
+        let response = format!(
+            "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            reply.len(),
+            reply
+        );
+        if socket.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Read the body of the next request, bytes past it stay in `buffer`
+async fn read_request(socket: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    loop {
+        if let Some(header_end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            let headers = String::from_utf8_lossy(&buffer[..header_end]).to_lowercase();
+            let content_length: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            let end = header_end + 4 + content_length;
+            if buffer.len() >= end {
+                let body = buffer[header_end + 4..end].to_vec();
+                buffer.drain(..end);
+                return Some(body);
+            }
+        }
+        let mut chunk = [0u8; 4096];
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+/// The mock's code of an account is the account's own address
+fn code_result(request: &Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": request["id"], "result": request["params"][0] })
+}
+
+fn addresses(count: u64) -> Vec<H160> {
+    (1..=count).map(H160::from_low_u64_be).collect()
+}
+
+fn options(batch_size: usize) -> FetchOptions {
+    FetchOptions {
+        batch_size,
+        base_backoff: Duration::from_millis(10),
+        ..FetchOptions::default()
+    }
+}
+
+const BLOCK: BlockId = BlockId::Number(BlockNumber::Number(ethers::types::U64([100])));
+
+fn assert_codes(codes: &[eyre::Result<ethers::types::Bytes>], addresses: &[H160]) {
+    assert_eq!(codes.len(), addresses.len());
+    for (code, address) in codes.iter().zip(addresses) {
+        assert_eq!(code.as_ref().unwrap().as_ref(), address.as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn batches_get_code_and_matches_responses_by_id() {
+    let node = MockNode::start(|request, _| match request {
+        // Answer in reverse order, the client has to match responses up by id
+        Value::Array(batch) => (200, Value::Array(batch.iter().rev().map(code_result).collect())),
+        single => (200, code_result(single)),
+    })
+    .await;
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options(4)).unwrap();
+
+    let addresses = addresses(10);
+    let codes = service.get_codes_at(&addresses, BLOCK).await;
+    assert_codes(&codes, &addresses);
+
+    let requests = node.requests();
+    let batch_sizes: Vec<usize> = requests.iter().map(|request| request.as_array().unwrap().len()).collect();
+    assert_eq!(batch_sizes.iter().sum::<usize>(), 10);
+    assert!(batch_sizes.iter().all(|size| *size <= 4));
+    for call in requests.iter().flat_map(|request| request.as_array().unwrap()) {
+        assert_eq!(call["method"], "eth_getCode");
+        assert_eq!(call["params"][1], "0x64");
+    }
+}
+
+#[tokio::test]
+async fn falls_back_to_single_calls_when_batches_are_unsupported() {
+    let node = MockNode::start(|request, _| match request {
+        Value::Array(_) => (
+            200,
+            json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32600, "message": "batch requests are not supported" } }),
+        ),
+        single => (200, code_result(single)),
+    })
+    .await;
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options(4)).unwrap();
+
+    let addresses = addresses(6);
+    assert_codes(&service.get_codes_at(&addresses, BLOCK).await, &addresses);
+    // Once rejected, batching stays off
+    assert_codes(&service.get_codes_at(&addresses, BLOCK).await, &addresses);
+
+    let batches = node.requests().iter().filter(|request| request.is_array()).count();
+    assert!((1..=2).contains(&batches), "{} batch requests", batches); // chunks fetched concurrently may both try
+    let singles = node.requests().iter().filter(|request| request.is_object()).count();
+    assert_eq!(singles, 12);
+}
+
+#[tokio::test]
+async fn retries_transient_failures_with_backoff() {
+    let node = MockNode::start(|request, seen| match (request, seen) {
+        // The first two requests fail as a whole
+        (_, 0 | 1) => (503, json!({ "error": "busy" })),
+        // The first answered batch has a rate-limited item, only it is asked for again
+        (Value::Array(batch), _) => (
+            200,
+            Value::Array(
+                batch
+                    .iter()
+                    .map(|call| match call["params"][0].as_str() {
+                        Some("0x0000000000000000000000000000000000000002") => {
+                            json!({ "jsonrpc": "2.0", "id": call["id"], "error": { "code": -32005, "message": "limit exceeded" } })
+                        }
+                        _ => code_result(call),
+                    })
+                    .collect(),
+            ),
+        ),
+        (single, _) => (200, code_result(single)),
+    })
+    .await;
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options(3)).unwrap();
+
+    let addresses = addresses(3);
+    let started = Instant::now();
+    assert_codes(&service.get_codes_at(&addresses, BLOCK).await, &addresses);
+    // Backoff doubles: 10ms, 20ms, then 40ms before the single retry
+    assert!(started.elapsed() >= Duration::from_millis(70), "{:?}", started.elapsed());
+
+    let requests = node.requests();
+    assert_eq!(requests.len(), 4);
+    assert_eq!(requests[3]["params"][0], "0x0000000000000000000000000000000000000002");
+}
+
+#[tokio::test]
+async fn gives_up_after_max_retries() {
+    let node = MockNode::start(|_, _| (500, json!({ "error": "down" }))).await;
+    let options = FetchOptions { max_retries: 2, ..options(1) };
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options).unwrap();
+
+    let codes = service.get_codes_at(&addresses(1), BLOCK).await;
+    assert!(codes[0].is_err());
+    assert_eq!(node.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn limits_requests_per_second() {
+    let node = MockNode::start(|request, _| (200, code_result(request))).await;
+    let options = FetchOptions { requests_per_second: Some(20.0), ..options(1) };
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options).unwrap();
+
+    // 6 calls at 20 per second: the last one may start 250ms after the first
+    let addresses = addresses(6);
+    let started = Instant::now();
+    assert_codes(&service.get_codes_at(&addresses, BLOCK).await, &addresses);
+    assert!(started.elapsed() >= Duration::from_millis(240), "{:?}", started.elapsed());
+    assert_eq!(node.requests().len(), 6);
+}
+
+#[tokio::test]
+async fn rate_limit_counts_every_call_of_a_batch() {
+    let node = MockNode::start(|request, _| match request {
+        Value::Array(batch) => (200, Value::Array(batch.iter().map(code_result).collect())),
+        single => (200, code_result(single)),
+    })
+    .await;
+    let options = FetchOptions { requests_per_second: Some(20.0), ..options(4) };
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options).unwrap();
+
+    // Two batches of 4 calls: the second one waits for the 4 slots of the first, 200ms
+    let addresses = addresses(8);
+    let started = Instant::now();
+    assert_codes(&service.get_codes_at(&addresses, BLOCK).await, &addresses);
+    assert!(started.elapsed() >= Duration::from_millis(190), "{:?}", started.elapsed());
+    assert_eq!(node.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn retries_calls_other_than_code_fetches() {
+    // Every call fails once before it is answered
+    let node = MockNode::start(|request, seen| {
+        if seen % 2 == 0 {
+            return (503, json!({ "error": "busy" }));
+        }
+        let result = match request["method"].as_str() {
+            Some("eth_chainId") => json!("0x1"),
+            Some("debug_traceTransaction") => json!({ "0x0000000000000000000000000000000000000001": { "balance": "0x0", "code": "0x6001" } }),
+            _ => Value::Null,
+        };
+        (200, json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+    })
+    .await;
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options(1)).unwrap();
+
+    assert_eq!(service.chain_id().await.unwrap(), 1);
+    let codes = service.get_prestate_code(H256::zero()).await.unwrap();
+    assert_eq!(codes[&H160::from_low_u64_be(1)].as_ref(), [0x60, 0x01]);
+
+    let methods: Vec<String> = node.requests().iter().map(|request| request["method"].as_str().unwrap().to_string()).collect();
+    assert_eq!(methods, ["eth_chainId", "eth_chainId", "debug_traceTransaction", "debug_traceTransaction"]);
+}
+
+#[test]
+fn rejects_requests_per_second_that_are_not_positive() {
+    for rps in [0.0, -1.0, f64::NAN] {
+        let options = FetchOptions { requests_per_second: Some(rps), ..options(1) };
+        assert!(EthersBlockchainService::new("http://127.0.0.1:1").unwrap().with_fetch_options(options).is_err());
+    }
+}
+
+#[tokio::test]
+async fn cached_code_is_used_before_any_rpc_call() {
+    let contract = H160::from_low_u64_be(1);
+    let code_hash = H256::from(ethers::utils::keccak256([0x60, 0x01]));
+    let node = MockNode::start(move |request, _| {
+        let result = match request["method"].as_str() {
+            Some("debug_traceTransaction") => json!({ "0x0000000000000000000000000000000000000001": { "balance": "0x0", "code": "0x6001" } }),
+            Some("eth_getProof") => json!({
+                "address": request["params"][0],
+                "balance": "0x0",
+                "codeHash": code_hash,
+                "nonce": "0x0",
+                "storageHash": H256::zero(),
+                "accountProof": [],
+                "storageProof": [],
+            }),
+            _ => Value::Null,
+        };
+        (200, json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+    })
+    .await;
+    let service = EthersBlockchainService::new(&node.url).unwrap().with_fetch_options(options(1)).unwrap();
+    let dir = std::env::temp_dir().join(format!("evm-cfg-rpc-cache-{}", std::process::id()));
+
+    let analyze = |block: u64| {
+        let mut analyzer = TransactionAnalyzer::from_call_frames(Vec::new());
+        analyzer.contract_addresses.insert(contract);
+        analyzer.tx_hash = Some(H256::zero());
+        analyzer.set_block(block);
+        analyzer.set_code_cache(DiskCodeCache::open(&dir, 1).unwrap());
+        analyzer
+    };
+    let methods = || -> Vec<String> { node.requests().iter().map(|request| request["method"].as_str().unwrap().to_string()).collect() };
+
+    // Nothing cached yet: the code comes from the transaction's pre-state
+    let mut analyzer = analyze(100);
+    analyzer.fetch_bytecodes(&service).await.unwrap();
+    assert_eq!(analyzer.bytecode_cache.get(&contract).unwrap().as_ref(), [0x60, 0x01]);
+    assert_eq!(methods(), ["debug_traceTransaction"]);
+
+    // Same block, another run: served from disk without asking the node
+    let mut analyzer = analyze(100);
+    analyzer.fetch_bytecodes(&service).await.unwrap();
+    assert_eq!(analyzer.bytecode_cache.get(&contract).unwrap().as_ref(), [0x60, 0x01]);
+    assert_eq!(methods(), ["debug_traceTransaction"]);
+
+    // A later block is outside the known range: only the code hash is checked
+    let mut analyzer = analyze(300);
+    analyzer.fetch_bytecodes(&service).await.unwrap();
+    assert_eq!(analyzer.bytecode_cache.get(&contract).unwrap().as_ref(), [0x60, 0x01]);
+    assert_eq!(methods(), ["debug_traceTransaction", "eth_getProof"]);
+    assert_eq!(node.requests()[1]["params"][2], "0x12b");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}